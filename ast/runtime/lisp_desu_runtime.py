@@ -0,0 +1,392 @@
+"""Runtime prelude for Python generated by lisp-desu.
+
+Implements the subset of Common Lisp builtins that have no direct Python
+equivalent. Which builtins lower to these helpers and which lower straight to
+Python is decided by the builtin table in `ast/src/builtins.rs`.
+
+Supported subset:
+
+    (format dest control &rest args)    cl_format
+    (concatenate result-type &rest seqs) cl_concatenate
+    (write-to-string object)            cl_write_to_string
+    (princ object)                      cl_princ
+    (terpri)                            cl_terpri
+    (finish-output)                     cl_finish_output
+    (random limit)                      cl_random
+    (not object), (null object)         cl_not
+    (car list), (cdr list)              cl_car, cl_cdr
+    (cons car cdr)                      cl_cons
+    (list &rest objects)                cl_list
+    (append &rest lists)                cl_append
+    (nth n list)                        cl_nth
+    (length sequence)                   cl_length
+    (reverse sequence)                  cl_reverse
+
+Proper lists are Python lists, and nil is `None`, though an empty list is
+nil too. The cdr of a list is a `Tail`, a view of the list that is not
+copied. Consing onto a list makes a `Cons` cell, whose cdr may itself be a
+Python list, rather than copying the list; so does a dotted pair such as
+`(a . b)`. Cells ending in a list behave as Python sequences, so that inline
+list operations work on them. The list helpers accept all of these, and are
+used instead of inline list operations once a program contains improper
+lists.
+
+Only nil, the empty list and `False`, which Python comparisons give, are
+false to Lisp; conditions other than comparisons are tested by `cl_true`.
+
+Functions that tail call each other are compiled, with `--trampoline`, to
+bodies returning a `TailCall` instead of making the call; `cl_trampoline`
+keeps calling until a body returns a plain value.
+
+`format` understands the directives ~a, ~s, ~d, ~%, ~& and ~~. `dest` may be
+`True` (stdout), `None` (return the string) or any object with `.write`.
+Standard output remembers whether it is at the start of a line, for ~&.
+"""
+
+import random as _random
+import sys as _sys
+from itertools import islice as _islice
+
+
+class Cons:
+    """A cons cell, whose cdr is nil, a list, another cell or any object."""
+
+    __slots__ = ("car", "cdr")
+    __hash__ = None
+
+    def __init__(self, car, cdr):
+        self.car = car
+        self.cdr = cdr
+
+    def __iter__(self):
+        obj = self
+        while isinstance(obj, Cons):
+            yield obj.car
+            obj = obj.cdr
+        if not _is_list(obj):
+            raise TypeError("not a proper list: " + cl_write_to_string(self))
+        yield from obj or []
+
+    def __len__(self):
+        return sum(1 for _ in self)
+
+    def __bool__(self):
+        return True
+
+    def __getitem__(self, index):
+        if isinstance(index, slice) or index < 0:
+            return list(self)[index]
+        for i, item in enumerate(self):
+            if i == index:
+                return item
+        raise IndexError("list index out of range")
+
+    def __eq__(self, other):
+        if not isinstance(other, (list, Tail, Cons)):
+            return NotImplemented
+        a, b = self, other
+        while True:
+            cell_a, cell_b = _uncons(a), _uncons(b)
+            if cell_a is None or cell_b is None:
+                break
+            if cell_a[0] != cell_b[0]:
+                return False
+            a, b = cell_a[1], cell_b[1]
+        if cell_a is not None or cell_b is not None:
+            return False
+        return a == b or (cl_not(a) and cl_not(b))
+
+    def __add__(self, other):
+        if not isinstance(other, (list, Tail, Cons)):
+            return NotImplemented
+        return [*self, *other]
+
+    def __radd__(self, other):
+        if not isinstance(other, (list, Tail, Cons)):
+            return NotImplemented
+        return [*other, *self]
+
+    def __repr__(self):
+        if _is_proper(self):
+            return repr(list(self))
+        return cl_write_to_string(self)
+
+
+class Tail:
+    """The elements of a Python list from `start` on, without copying them."""
+
+    __slots__ = ("items", "start")
+    __hash__ = None
+
+    def __init__(self, items, start):
+        self.items = items
+        self.start = start
+
+    def __len__(self):
+        return len(self.items) - self.start
+
+    def __iter__(self):
+        return _islice(self.items, self.start, None)
+
+    def __getitem__(self, index):
+        if isinstance(index, slice):
+            return self.items[self.start :][index]
+        if index < 0:
+            index += len(self)
+        if not 0 <= index < len(self):
+            raise IndexError("list index out of range")
+        return self.items[self.start + index]
+
+    def __eq__(self, other):
+        if not isinstance(other, (list, Tail)):
+            return NotImplemented
+        return len(self) == len(other) and all(a == b for a, b in zip(self, other))
+
+    def __add__(self, other):
+        if not isinstance(other, (list, Tail)):
+            return NotImplemented
+        return [*self, *other]
+
+    def __radd__(self, other):
+        if not isinstance(other, (list, Tail)):
+            return NotImplemented
+        return [*other, *self]
+
+    def __repr__(self):
+        return repr(list(self))
+
+
+class _LineTracker:
+    """A stream remembering whether the text written to it ended a line."""
+
+    def __init__(self, stream):
+        self.stream = stream
+        self.fresh = True
+
+    def write(self, text):
+        if text:
+            self.fresh = text.endswith("\n")
+        return self.stream.write(text)
+
+    def __getattr__(self, name):
+        return getattr(self.stream, name)
+
+
+if not isinstance(_sys.stdout, _LineTracker):
+    _sys.stdout = _LineTracker(_sys.stdout)
+
+
+class TailCall:
+    """A call left for `cl_trampoline` to make."""
+
+    __slots__ = ("func", "args")
+
+    def __init__(self, func, *args):
+        self.func = func
+        self.args = args
+
+
+def cl_trampoline(func, *args):
+    result = func(*args)
+    while isinstance(result, TailCall):
+        result = result.func(*result.args)
+    return result
+
+
+def _is_list(obj):
+    """Whether `obj` ends a chain of cells as a proper list does."""
+    return obj is None or isinstance(obj, (list, Tail))
+
+
+def _uncons(obj):
+    """The car and cdr of a cell or non-empty list, or None otherwise."""
+    if isinstance(obj, Cons):
+        return obj.car, obj.cdr
+    if isinstance(obj, (list, Tail)) and obj:
+        return obj[0], cl_cdr(obj)
+    return None
+
+
+def _is_proper(obj):
+    while isinstance(obj, Cons):
+        obj = obj.cdr
+    return _is_list(obj)
+
+
+def cl_true(obj):
+    return obj is not None and obj is not False and obj != []
+
+
+def cl_not(obj):
+    return not cl_true(obj)
+
+
+def cl_write_to_string(obj):
+    if obj is None:
+        return "NIL"
+    if obj is True:
+        return "T"
+    if isinstance(obj, str):
+        return '"' + obj.replace("\\", "\\\\").replace('"', '\\"') + '"'
+    if isinstance(obj, (list, Tail)):
+        if not obj:
+            return "NIL"
+        return "(" + " ".join(cl_write_to_string(x) for x in obj) + ")"
+    if isinstance(obj, Cons):
+        items = []
+        while isinstance(obj, Cons):
+            items.append(cl_write_to_string(obj.car))
+            obj = obj.cdr
+        if isinstance(obj, (list, Tail)):
+            items.extend(cl_write_to_string(x) for x in obj)
+            return "(" + " ".join(items) + ")"
+        return "(" + " ".join(items) + " . " + cl_write_to_string(obj) + ")"
+    return str(obj)
+
+
+def cl_princ_to_string(obj):
+    if isinstance(obj, str):
+        return obj
+    return cl_write_to_string(obj)
+
+
+def cl_princ(obj):
+    _sys.stdout.write(cl_princ_to_string(obj))
+    return obj
+
+
+def cl_terpri():
+    _sys.stdout.write("\n")
+    return None
+
+
+def cl_finish_output():
+    _sys.stdout.flush()
+    return None
+
+
+def cl_random(limit):
+    if isinstance(limit, float):
+        return _random.uniform(0, limit)
+    return _random.randrange(limit)
+
+
+def cl_concatenate(result_type, *seqs):
+    if result_type == "string":
+        return "".join(seqs)
+    out = []
+    for seq in seqs:
+        out.extend(seq)
+    return out
+
+
+def cl_car(obj):
+    if isinstance(obj, Cons):
+        return obj.car
+    return obj[0] if obj else None
+
+
+def cl_cdr(obj):
+    if isinstance(obj, Cons):
+        return obj.cdr
+    if isinstance(obj, Tail):
+        obj, start = obj.items, obj.start + 1
+    else:
+        start = 1
+    if obj is None or start >= len(obj):
+        return None
+    return Tail(obj, start)
+
+
+def cl_cons(car, cdr):
+    if _is_list(cdr) and not cdr:
+        return [car]
+    return Cons(car, cdr)
+
+
+def cl_list(*objects):
+    return list(objects)
+
+
+def cl_append(*lists):
+    if not lists:
+        return []
+    *init, last = lists
+    out = []
+    for obj in init:
+        while isinstance(obj, Cons):
+            out.append(obj.car)
+            obj = obj.cdr
+        out.extend(obj or [])
+    for car in reversed(out):
+        last = cl_cons(car, last)
+    return last if last is not None else []
+
+
+def cl_nth(n, obj):
+    if isinstance(obj, (list, Tail)):
+        return obj[n] if n < len(obj) else None
+    for _ in range(n):
+        obj = cl_cdr(obj)
+    return cl_car(obj)
+
+
+def cl_length(obj):
+    if isinstance(obj, Cons) and not _is_proper(obj):
+        raise TypeError("length: not a proper list: " + cl_write_to_string(obj))
+    return len(obj or [])
+
+
+def cl_reverse(obj):
+    if isinstance(obj, Cons) and not _is_proper(obj):
+        raise TypeError("reverse: not a proper list: " + cl_write_to_string(obj))
+    return (obj or [])[::-1]
+
+
+def cl_line_break(dest):
+    """The newline `~&` starting a control string writes to `dest`."""
+    stream = _sys.stdout if dest is True else dest
+    if dest is None or getattr(stream, "fresh", True):
+        return ""
+    return "\n"
+
+
+def cl_format(dest, control, *args):
+    args = list(args)
+    out = []
+    i = 0
+    while i < len(control):
+        c = control[i]
+        if c != "~":
+            out.append(c)
+            i += 1
+            continue
+        if i + 1 >= len(control):
+            raise ValueError("format: control string ends in '~'")
+        directive = control[i + 1].lower()
+        if directive == "a":
+            out.append(cl_princ_to_string(args.pop(0)))
+        elif directive == "s":
+            out.append(cl_write_to_string(args.pop(0)))
+        elif directive == "d":
+            out.append(str(args.pop(0)))
+        elif directive == "%":
+            out.append("\n")
+        elif directive == "&":
+            if not out:
+                out.append(cl_line_break(dest))
+            elif not "".join(out).endswith("\n"):
+                out.append("\n")
+        elif directive == "~":
+            out.append("~")
+        else:
+            raise ValueError("format: unknown directive ~" + control[i + 1])
+        i += 2
+    string = "".join(out)
+    if dest is None:
+        return string
+    if dest is True:
+        _sys.stdout.write(string)
+    else:
+        dest.write(string)
+    return None
@@ -0,0 +1,140 @@
+//! Arity checking of calls to the program's `defun`s, the functions it
+//! imports and the builtins.
+//!
+//! Each call is checked against the lambda list of the function it calls:
+//! the number of positional arguments, and for `&key` parameters that the
+//! rest of the arguments are pairs of known keywords and values. Builtins
+//! are checked against the number of arguments their lowering takes.
+use crate::builtins;
+use crate::diagnostic::Diagnostic;
+use crate::expr::{Expr, ExprKind, LambdaList};
+use crate::resolve::Import;
+use lexer::Span;
+use std::collections::HashMap;
+
+pub fn check(exprs: &[Expr], imports: &[Import]) -> Vec<Diagnostic> {
+    // Lambda lists, and where they are defined for the program's own
+    let mut defuns = HashMap::new();
+    for import in imports {
+        if let Some(params) = &import.params {
+            defuns.insert(import.local(), (params, None));
+        }
+    }
+    for expr in exprs {
+        expr.walk(&mut |expr| {
+            if let ExprKind::Defun(ref defun) = expr.kind {
+                let defined = Some(defun.params.span);
+                defuns.insert(defun.name.name.as_str(), (&defun.params, defined));
+            }
+        });
+    }
+
+    let mut diagnostics = vec![];
+    for expr in exprs {
+        expr.walk(&mut |expr| {
+            let ExprKind::Call { ref func, ref args } = expr.kind else {
+                return;
+            };
+            if let Some(&(params, defined)) = defuns.get(func.name.as_str()) {
+                diagnostics.extend(check_call(&func.name, params, defined, expr, args));
+            } else if let Some(builtin) = builtins::lookup(&func.name) {
+                let (least, most) = builtin.arity;
+                if args.len() < least || most.is_some_and(|most| args.len() > most) {
+                    diagnostics.push(Diagnostic::error(
+                        expr.span,
+                        format!(
+                            "`{}` expects {}, got {}",
+                            func.name,
+                            between(least, most),
+                            args.len()
+                        ),
+                    ));
+                }
+            }
+        });
+    }
+    diagnostics
+}
+
+fn check_call(
+    name: &str,
+    params: &LambdaList,
+    defined: Option<Span>,
+    call: &Expr,
+    args: &[Expr],
+) -> Option<Diagnostic> {
+    let defined_here = |diagnostic: Diagnostic| match defined {
+        Some(span) => diagnostic.with_note(span, format!("`{name}` is defined here")),
+        None => diagnostic,
+    };
+
+    let required = params.required.len();
+    let positional = required + params.optional.len();
+    let too_many = params.rest.is_none() && params.key.is_empty() && args.len() > positional;
+    if args.len() < required || too_many {
+        return Some(defined_here(Diagnostic::error(
+            call.span,
+            format!("`{name}` expects {}, got {}", expected(params), args.len()),
+        )));
+    }
+
+    if params.key.is_empty() {
+        return None;
+    }
+    let keywords = &args[positional.min(args.len())..];
+    if !keywords.len().is_multiple_of(2) {
+        return Some(defined_here(Diagnostic::error(
+            call.span,
+            format!("odd number of keyword arguments to `{name}`"),
+        )));
+    }
+    for pair in keywords.chunks(2) {
+        let keyword = &pair[0];
+        let ExprKind::Symbol(ref symbol) = keyword.kind else {
+            return Some(defined_here(Diagnostic::error(
+                keyword.span,
+                format!("expected a keyword argument to `{name}`"),
+            )));
+        };
+        let Some(key) = symbol.strip_prefix(':') else {
+            return Some(defined_here(Diagnostic::error(
+                keyword.span,
+                format!("expected a keyword argument to `{name}`, got `{symbol}`"),
+            )));
+        };
+        let known = params.key.iter().any(|(param, _)| param.name == key);
+        if !known && !params.allow_other_keys {
+            return Some(defined_here(Diagnostic::error(
+                keyword.span,
+                format!("unknown keyword argument `{symbol}` to `{name}`"),
+            )));
+        }
+    }
+    None
+}
+
+/// Number of positional arguments accepted, such as `1 to 2 arguments`.
+fn expected(params: &LambdaList) -> String {
+    let required = params.required.len();
+    let positional = required + params.optional.len();
+    match params.rest {
+        Some(_) => between(required, None),
+        None => between(required, Some(positional)),
+    }
+}
+
+/// `least` to `most` arguments, or at least `least` with no `most`.
+fn between(least: usize, most: Option<usize>) -> String {
+    match most {
+        None => format!("at least {}", arguments(least)),
+        Some(most) if most > least => format!("{least} to {}", arguments(most)),
+        Some(_) => arguments(least),
+    }
+}
+
+fn arguments(n: usize) -> String {
+    match n {
+        1 => "1 argument".to_string(),
+        n => format!("{n} arguments"),
+    }
+}
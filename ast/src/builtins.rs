@@ -0,0 +1,579 @@
+/// Source of the Python runtime prelude. Helpers referenced by
+/// [`Lowering::Prelude`] are defined here.
+pub const RUNTIME: &str = include_str!("../runtime/lisp_desu_runtime.py");
+
+/// Module name the runtime is written to when it is not inlined.
+pub const RUNTIME_MODULE: &str = "lisp_desu_runtime";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Lowering {
+    /// Call a Python builtin directly.
+    Python(&'static str),
+    /// Call a helper from the runtime prelude.
+    Prelude(&'static str),
+    /// Operator or expression built from the lowered arguments.
+    Inline(fn(&[String]) -> String),
+    /// List primitive, lowered inline on Python lists, nil or `Cons` cells
+    /// ending in a list. Falls back to the prelude helper, which also
+    /// understands improper lists, once they appear in the program or when
+    /// `inline` rejects the arguments.
+    List {
+        inline: fn(&[String]) -> Option<String>,
+        prelude: &'static str,
+    },
+}
+
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    /// Least and most number of arguments, with no most for `None`.
+    pub arity: (usize, Option<usize>),
+    pub lowering: Lowering,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "print",
+        arity: (1, None),
+        lowering: Lowering::Python("print"),
+    },
+    Builtin {
+        name: "parse-integer",
+        arity: (1, Some(1)),
+        lowering: Lowering::Python("int"),
+    },
+    Builtin {
+        name: "read-line",
+        arity: (0, Some(0)),
+        lowering: Lowering::Python("input"),
+    },
+    Builtin {
+        name: "format",
+        arity: (2, None),
+        lowering: Lowering::Prelude("cl_format"),
+    },
+    Builtin {
+        name: "concatenate",
+        arity: (1, None),
+        lowering: Lowering::Prelude("cl_concatenate"),
+    },
+    Builtin {
+        name: "write-to-string",
+        arity: (1, Some(1)),
+        lowering: Lowering::Prelude("cl_write_to_string"),
+    },
+    Builtin {
+        name: "princ",
+        arity: (1, Some(1)),
+        lowering: Lowering::Prelude("cl_princ"),
+    },
+    Builtin {
+        name: "terpri",
+        arity: (0, Some(0)),
+        lowering: Lowering::Prelude("cl_terpri"),
+    },
+    Builtin {
+        name: "finish-output",
+        arity: (0, Some(0)),
+        lowering: Lowering::Prelude("cl_finish_output"),
+    },
+    Builtin {
+        name: "random",
+        arity: (1, Some(1)),
+        lowering: Lowering::Prelude("cl_random"),
+    },
+    Builtin {
+        name: "max",
+        arity: (1, None),
+        lowering: Lowering::Python("max"),
+    },
+    Builtin {
+        name: "min",
+        arity: (1, None),
+        lowering: Lowering::Python("min"),
+    },
+    Builtin {
+        name: "abs",
+        arity: (1, Some(1)),
+        lowering: Lowering::Python("abs"),
+    },
+    Builtin {
+        name: "+",
+        arity: (0, None),
+        lowering: Lowering::Inline(|args| match args {
+            [] => "0".to_string(),
+            args => binary(" + ", PREC_ADD, args),
+        }),
+    },
+    Builtin {
+        name: "-",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| match args {
+            [x] => format!("-{}", paren(x, PREC_UNARY)),
+            args => binary(" - ", PREC_ADD, args),
+        }),
+    },
+    Builtin {
+        name: "*",
+        arity: (0, None),
+        lowering: Lowering::Inline(|args| match args {
+            [] => "1".to_string(),
+            args => binary(" * ", PREC_MUL, args),
+        }),
+    },
+    Builtin {
+        name: "/",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| match args {
+            [x] => format!("1 / {}", paren(x, PREC_UNARY)),
+            args => binary(" / ", PREC_MUL, args),
+        }),
+    },
+    Builtin {
+        name: "mod",
+        arity: (2, Some(2)),
+        lowering: Lowering::Inline(|args| binary(" % ", PREC_MUL, args)),
+    },
+    Builtin {
+        name: "1+",
+        arity: (1, Some(1)),
+        lowering: Lowering::Inline(|args| binary(" + ", PREC_ADD, &[args.join(", "), "1".into()])),
+    },
+    Builtin {
+        name: "1-",
+        arity: (1, Some(1)),
+        lowering: Lowering::Inline(|args| binary(" - ", PREC_ADD, &[args.join(", "), "1".into()])),
+    },
+    Builtin {
+        name: "<",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| compare(" < ", args)),
+    },
+    Builtin {
+        name: ">",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| compare(" > ", args)),
+    },
+    Builtin {
+        name: "<=",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| compare(" <= ", args)),
+    },
+    Builtin {
+        name: ">=",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| compare(" >= ", args)),
+    },
+    Builtin {
+        name: "=",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| compare(" == ", args)),
+    },
+    Builtin {
+        name: "/=",
+        arity: (1, None),
+        lowering: Lowering::Inline(|args| match args {
+            [a, b] => compare(" != ", &[a.clone(), b.clone()]),
+            args => format!("len({{{}}}) == {}", args.join(", "), args.len()),
+        }),
+    },
+    Builtin {
+        name: "eq",
+        arity: (2, Some(2)),
+        lowering: Lowering::Inline(|args| compare(" == ", args)),
+    },
+    Builtin {
+        name: "eql",
+        arity: (2, Some(2)),
+        lowering: Lowering::Inline(|args| compare(" == ", args)),
+    },
+    Builtin {
+        name: "equal",
+        arity: (2, Some(2)),
+        lowering: Lowering::Inline(|args| compare(" == ", args)),
+    },
+    // Python's `not` would take `0` and `""` for nil
+    Builtin {
+        name: "not",
+        arity: (1, Some(1)),
+        lowering: Lowering::Prelude("cl_not"),
+    },
+    Builtin {
+        name: "null",
+        arity: (1, Some(1)),
+        lowering: Lowering::Prelude("cl_not"),
+    },
+    Builtin {
+        name: "zerop",
+        arity: (1, Some(1)),
+        lowering: Lowering::Inline(|args| compare(" == ", &[args.join(", "), "0".into()])),
+    },
+    Builtin {
+        name: "car",
+        arity: (1, Some(1)),
+        lowering: Lowering::List {
+            inline: |args| match args {
+                [list] if is_list_display(list) && !is_empty_list(list) => {
+                    Some(format!("{list}[0]"))
+                }
+                [list] => Some(format!("({} or [None])[0]", paren(list, PREC_OR + 1))),
+                _ => None,
+            },
+            prelude: "cl_car",
+        },
+    },
+    // Slicing would copy the list, so that walking it took quadratic time
+    Builtin {
+        name: "cdr",
+        arity: (1, Some(1)),
+        lowering: Lowering::Prelude("cl_cdr"),
+    },
+    // Copying the list consed onto would make accumulating a list take
+    // quadratic time, so a `Cons` cell is made instead
+    Builtin {
+        name: "cons",
+        arity: (2, Some(2)),
+        lowering: Lowering::List {
+            inline: |args| match args {
+                [car, cdr] if is_empty_list(cdr) => Some(format!("[{car}]")),
+                _ => None,
+            },
+            prelude: "cl_cons",
+        },
+    },
+    Builtin {
+        name: "list",
+        arity: (0, None),
+        lowering: Lowering::List {
+            inline: |args| Some(format!("[{}]", args.join(", "))),
+            prelude: "cl_list",
+        },
+    },
+    Builtin {
+        name: "append",
+        arity: (0, None),
+        lowering: Lowering::List {
+            inline: |args| {
+                let lists = args
+                    .iter()
+                    .filter(|list| !is_empty_list(list))
+                    .map(|list| or_empty(list))
+                    .collect::<Vec<_>>();
+                Some(match lists.len() {
+                    0 => "[]".to_string(),
+                    _ => lists.join(" + "),
+                })
+            },
+            prelude: "cl_append",
+        },
+    },
+    // Indexing would fail past the end of the list rather than give nil
+    Builtin {
+        name: "nth",
+        arity: (2, Some(2)),
+        lowering: Lowering::Prelude("cl_nth"),
+    },
+    Builtin {
+        name: "length",
+        arity: (1, Some(1)),
+        lowering: Lowering::List {
+            inline: |args| match args {
+                [list] if is_list_display(list) => Some(format!("len({list})")),
+                [list] => Some(format!("len({} or [])", paren(list, PREC_OR + 1))),
+                _ => None,
+            },
+            prelude: "cl_length",
+        },
+    },
+    Builtin {
+        name: "reverse",
+        arity: (1, Some(1)),
+        lowering: Lowering::List {
+            inline: |args| match args {
+                [list] => Some(format!("{}[::-1]", or_empty(list))),
+                _ => None,
+            },
+            prelude: "cl_reverse",
+        },
+    },
+];
+
+/// Builtins giving `True` or `False`, whose truth Python takes as Lisp does.
+pub const PREDICATES: &[&str] = &[
+    "<", ">", "<=", ">=", "=", "/=", "eq", "eql", "equal", "not", "null", "zerop",
+];
+
+/// Python builtins the lowered code calls, which its variables must not hide.
+pub const PYTHON_NAMES: &[&str] = &[
+    "abs",
+    "bool",
+    "callable",
+    "float",
+    "input",
+    "int",
+    "isinstance",
+    "iter",
+    "len",
+    "list",
+    "max",
+    "min",
+    "print",
+    "set",
+    "str",
+    "zip",
+    "Any",
+    "Callable",
+];
+
+/// Names the runtime prelude defines at the top level.
+pub fn runtime_names() -> impl Iterator<Item = &'static str> {
+    RUNTIME.lines().filter_map(|line| {
+        let rest = line
+            .strip_prefix("def ")
+            .or_else(|| line.strip_prefix("class "))?;
+        rest.split(['(', ':']).next()
+    })
+}
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Turn a Lisp symbol into a valid Python identifier, giving different
+/// symbols different identifiers.
+///
+/// Words joined by `-`, such as `foo-bar`, become `foo_bar`, `*foo*`
+/// becomes `_foo_`, and names qualified by a package become `pkg__name` for
+/// `pkg:name` and `pkg___name` for `pkg::name`, as words never contain `__`.
+/// Any other symbol, such as `1+` or `foo_bar`, becomes
+/// `__` followed by its ASCII letters and digits, with each other character
+/// as `_{hex}_`: `__1_2b_` and `__foo_5f_bar`. Python keywords get a
+/// trailing `_`. No symbol becomes a name starting with a single `_` but not
+/// ending in one, which are left to temporaries such as `_t1`.
+pub fn mangle(symbol: &str) -> String {
+    let earmuffed = symbol.strip_prefix('*').and_then(|s| s.strip_suffix('*'));
+    let qualified = match symbol.split_once("::") {
+        Some((package, name)) => Some((package, "___", name)),
+        None => symbol
+            .split_once(':')
+            .map(|(package, name)| (package, "__", name)),
+    };
+    let ident = match (earmuffed, qualified) {
+        (Some(inner), _) if is_words(inner) => format!("_{}_", inner.replace('-', "_")),
+        (_, Some((package, sep, name))) if is_words(package) && is_words(name) => {
+            format!(
+                "{}{sep}{}",
+                package.replace('-', "_"),
+                name.replace('-', "_")
+            )
+        }
+        _ if is_words(symbol) => symbol.replace('-', "_"),
+        _ => {
+            let mut ident = "__".to_string();
+            for c in symbol.chars() {
+                match c.is_ascii_alphanumeric() {
+                    true => ident.push(c),
+                    false => ident.push_str(&format!("_{:x}_", c as u32)),
+                }
+            }
+            ident
+        }
+    };
+    match PYTHON_KEYWORDS.contains(&ident.as_str()) {
+        true => format!("{ident}_"),
+        false => ident,
+    }
+}
+
+/// Whether `symbol` is words of letters and digits joined by single `-`s,
+/// starting with a letter.
+fn is_words(symbol: &str) -> bool {
+    symbol.starts_with(char::is_alphabetic)
+        && symbol
+            .split('-')
+            .all(|word| !word.is_empty() && word.chars().all(char::is_alphanumeric))
+}
+
+/// Turn a name that is already Python's, such as a module or a Python
+/// function given as `py:name`, into a valid Python identifier, only
+/// replacing the characters Python does not allow.
+pub fn python_ident(name: &str) -> String {
+    let ident = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == '_' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    match ident.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{ident}"),
+        false if PYTHON_KEYWORDS.contains(&ident.as_str()) => format!("{ident}_"),
+        false => ident,
+    }
+}
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+/// Escape text for use inside a double quoted Python string.
+pub fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn string_literal(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+// Binding strength of lowered Python expressions, loosest first.
+pub const PREC_LAMBDA: u8 = 0;
+pub const PREC_TERNARY: u8 = 1;
+pub const PREC_OR: u8 = 2;
+pub const PREC_AND: u8 = 3;
+pub const PREC_NOT: u8 = 4;
+pub const PREC_COMPARE: u8 = 5;
+pub const PREC_ADD: u8 = 6;
+pub const PREC_MUL: u8 = 7;
+pub const PREC_UNARY: u8 = 8;
+pub const PREC_ATOM: u8 = 9;
+
+/// Precedence of the loosest operator at the top level of `expr`. Binary
+/// operators are always lowered with spaces around them.
+pub fn precedence(expr: &str) -> u8 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    // `expr` with nested and quoted text blanked out
+    let mut top = String::new();
+    for c in expr.chars() {
+        match (quote, c) {
+            // The character after a backslash never closes the string
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, c) if depth == 0 => top.push(c),
+            _ => {}
+        }
+        if quote.is_some() || depth > 0 {
+            top.push('_');
+        }
+    }
+
+    let has = |ops: &[&str]| ops.iter().any(|op| top.contains(op));
+    if top.starts_with("lambda") {
+        PREC_LAMBDA
+    } else if has(&[" if "]) {
+        PREC_TERNARY
+    } else if has(&[" or "]) {
+        PREC_OR
+    } else if has(&[" and "]) {
+        PREC_AND
+    } else if top.starts_with("not ") {
+        PREC_NOT
+    } else if has(&[" < ", " > ", " <= ", " >= ", " == ", " != ", " is ", " in "]) {
+        PREC_COMPARE
+    } else if has(&[" + ", " - "]) {
+        PREC_ADD
+    } else if has(&[" * ", " / ", " // ", " % "]) {
+        PREC_MUL
+    } else if top.starts_with('-') {
+        PREC_UNARY
+    } else {
+        PREC_ATOM
+    }
+}
+
+/// Parenthesize `expr` unless it binds at least as tightly as `min`.
+pub fn paren(expr: &str, min: u8) -> String {
+    match precedence(expr) >= min {
+        true => expr.to_string(),
+        false => format!("({expr})"),
+    }
+}
+
+/// Parenthesize a lowered expression unless it is a single operand.
+pub fn atom(expr: &str) -> String {
+    paren(expr, PREC_ATOM)
+}
+
+/// Left associative binary operator.
+fn binary(op: &str, prec: u8, args: &[String]) -> String {
+    args.iter()
+        .enumerate()
+        .map(|(i, arg)| match i {
+            0 => paren(arg, prec),
+            _ => paren(arg, prec + 1),
+        })
+        .collect::<Vec<_>>()
+        .join(op)
+}
+
+/// Comparisons chain in both Lisp and Python.
+fn compare(op: &str, args: &[String]) -> String {
+    match args {
+        [_] => "True".to_string(),
+        args => args
+            .iter()
+            .map(|arg| paren(arg, PREC_COMPARE + 1))
+            .collect::<Vec<_>>()
+            .join(op),
+    }
+}
+
+fn is_empty_list(expr: &str) -> bool {
+    matches!(expr, "None" | "[]")
+}
+
+/// `list`, or `[]` in its place if it is `None`.
+pub fn or_empty(list: &str) -> String {
+    match is_list_display(list) {
+        true => list.to_string(),
+        false => format!("({} or [])", paren(list, PREC_OR + 1)),
+    }
+}
+
+/// Whether `expr` is a list display such as `[a, *b]`, which is never `None`.
+fn is_list_display(expr: &str) -> bool {
+    if !expr.starts_with('[') {
+        return false;
+    }
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in expr.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            // The `[` opening `expr` must close it, unlike in `[a] + b`
+            (None, ')' | ']' | '}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return i == expr.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
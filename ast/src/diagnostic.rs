@@ -0,0 +1,109 @@
+//! Errors and warnings reported by the analysis passes.
+use lexer::Span;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+    /// Related locations, such as the definition a call refers to.
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            span,
+            message: message.into(),
+            notes: vec![],
+        }
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(span, message)
+        }
+    }
+
+    pub fn with_note(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.notes.push(Note {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render as `path:row:col: severity: message`, followed by the source
+    /// line with the span underlined, and likewise for each note.
+    pub fn render(&self, path: &str, src: &str) -> String {
+        let mut out = String::new();
+        render_at(
+            &mut out,
+            path,
+            src,
+            self.span,
+            &self.severity.to_string(),
+            &self.message,
+        );
+        for note in &self.notes {
+            render_at(&mut out, path, src, note.span, "note", &note.message);
+        }
+        out
+    }
+}
+
+fn render_at(out: &mut String, path: &str, src: &str, span: Span, label: &str, message: &str) {
+    // Diagnostics about the whole file have no location
+    if span.start_row == 0 {
+        out.push_str(&format!("{path}: {label}: {message}\n"));
+        return;
+    }
+    out.push_str(&format!(
+        "{path}:{}:{}: {label}: {message}\n",
+        span.start_row, span.start_col
+    ));
+    let Some(line) = src.lines().nth(span.start_row.saturating_sub(1)) else {
+        return;
+    };
+    let end_col = match span.end_row == span.start_row {
+        true => span.end_col.max(span.start_col),
+        false => line.chars().count().max(span.start_col),
+    };
+    let gutter = span.start_row.to_string().len();
+    out.push_str(&format!("{} |\n", " ".repeat(gutter)));
+    out.push_str(&format!("{} | {line}\n", span.start_row));
+    out.push_str(&format!(
+        "{} | {}{}\n",
+        " ".repeat(gutter),
+        " ".repeat(span.start_col.saturating_sub(1)),
+        "^".repeat(end_col + 1 - span.start_col)
+    ));
+}
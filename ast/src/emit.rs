@@ -0,0 +1,358 @@
+//! Readable dumps of the stages of the pipeline, printed by `--emit`.
+use crate::expr::{CondClause, Datum, Expr, ExprKind, Ident, LambdaList, TypeSpec};
+use lexer::{Cursor, Span, TokenKind as LexerTokenKind};
+use parser::{AtomKind, SExpr, Token, TokenKind};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Lexer tokens with their spans.
+    Tokens,
+    /// S-expressions read by the parser.
+    Sexpr,
+    /// Forms as read, before analysis.
+    Ast,
+    /// The generated module.
+    Py,
+}
+
+impl FromStr for Stage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Stage::Tokens),
+            "sexpr" => Ok(Stage::Sexpr),
+            "ast" => Ok(Stage::Ast),
+            "py" => Ok(Stage::Py),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Table of the tokens of `src` other than whitespace, a line each.
+pub fn tokens(src: &str) -> String {
+    let mut cursor = Cursor::new(src);
+    let mut offset = 0;
+    let mut out = String::new();
+    loop {
+        let token = cursor.next_token();
+        if token.kind == LexerTokenKind::EOF {
+            break;
+        }
+        let text = &src[offset..offset + token.len];
+        offset += token.len;
+        if token.kind != LexerTokenKind::Whitespace {
+            let kind = format!("{:?}", token.kind);
+            out.push_str(&format!("{:<12} {kind:<16} {text:?}\n", span(token.span)));
+        }
+    }
+    out
+}
+
+/// `token` re-printed as an s-expression.
+pub fn sexpr(token: &Token, src: &str) -> String {
+    match &token.kind {
+        TokenKind::SExpr(SExpr::Cons { car, cdr, tail }) => {
+            let mut items = vec![sexpr(car, src)];
+            // The `)` closing the list is read as a `Nil`
+            items.extend(
+                cdr.iter()
+                    .filter(|token| !matches!(token.kind, TokenKind::Nil))
+                    .map(|token| sexpr(token, src)),
+            );
+            if let Some(tail) = tail {
+                items.push(".".to_owned());
+                items.push(sexpr(tail, src));
+            }
+            format!("({})", items.join(" "))
+        }
+        TokenKind::SExpr(SExpr::Nil) | TokenKind::Nil | TokenKind::ListNil => "()".to_owned(),
+        TokenKind::Atom(AtomKind::Literal(token) | AtomKind::Symbol(token, _)) => {
+            token.as_str(src).to_owned()
+        }
+        TokenKind::Quote(datum) => format!("'{}", sexpr(datum, src)),
+        TokenKind::EOF => String::new(),
+    }
+}
+
+/// Indented tree of `exprs`, a node per line with its span.
+pub fn ast(exprs: &[Expr]) -> String {
+    let mut dump = Dump::default();
+    for expr in exprs {
+        dump.expr(expr);
+    }
+    dump.out
+}
+
+#[derive(Default)]
+struct Dump {
+    out: String,
+    depth: usize,
+}
+
+impl Dump {
+    fn line(&mut self, text: impl Display) {
+        self.out.push_str(&"  ".repeat(self.depth));
+        self.out.push_str(&format!("{text}\n"));
+    }
+
+    /// Write `label`, with the lines written by `f` nested under it.
+    fn nested(&mut self, label: impl Display, f: impl FnOnce(&mut Self)) {
+        self.line(label);
+        self.depth += 1;
+        f(self);
+        self.depth -= 1;
+    }
+
+    fn exprs(&mut self, label: &str, exprs: &[Expr]) {
+        self.nested(label, |dump| {
+            for expr in exprs {
+                dump.expr(expr);
+            }
+        });
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let at = span(expr.span);
+        match &expr.kind {
+            ExprKind::Nil => self.line(format_args!("Nil {at}")),
+            ExprKind::T => self.line(format_args!("T {at}")),
+            ExprKind::Number(n) => self.line(format_args!("Number {n} {at}")),
+            ExprKind::Str(s) => self.line(format_args!("Str {s:?} {at}")),
+            ExprKind::Symbol(name) => self.line(format_args!("Symbol {name} {at}")),
+            ExprKind::Function(func) => self.line(format_args!("Function {} {at}", func.name)),
+            ExprKind::Quote(datum) => self.line(format_args!("Quote {} {at}", DatumText(datum))),
+            ExprKind::Call { func, args } => {
+                self.nested(format_args!("Call {} {at}", func.name), |dump| {
+                    for arg in args {
+                        dump.expr(arg);
+                    }
+                })
+            }
+            ExprKind::Funcall { func, args } => self.nested(format_args!("Funcall {at}"), |dump| {
+                dump.expr(func);
+                for arg in args {
+                    dump.expr(arg);
+                }
+            }),
+            ExprKind::Defun(defun) => {
+                self.nested(format_args!("Defun {} {at}", defun.name.name), |dump| {
+                    dump.params(&defun.params);
+                    if let Some(doc) = &defun.doc {
+                        dump.line(format_args!("doc {doc:?}"));
+                    }
+                    for (param, spec) in &defun.declarations {
+                        dump.line(format_args!("declare {} {}", param.name, TypeText(spec)));
+                    }
+                    dump.exprs("body", &defun.body);
+                })
+            }
+            ExprKind::Lambda { params, body } => self.nested(format_args!("Lambda {at}"), |dump| {
+                dump.params(params);
+                dump.exprs("body", body);
+            }),
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => self.nested(format_args!("If {at}"), |dump| {
+                dump.expr(cond);
+                dump.expr(then);
+                if let Some(otherwise) = otherwise {
+                    dump.expr(otherwise);
+                }
+            }),
+            ExprKind::Cond(clauses) => self.nested(format_args!("Cond {at}"), |dump| {
+                for CondClause { test, body } in clauses {
+                    dump.nested("clause", |dump| {
+                        dump.expr(test);
+                        for expr in body {
+                            dump.expr(expr);
+                        }
+                    });
+                }
+            }),
+            ExprKind::Progn(body) => self.exprs(&format!("Progn {at}"), body),
+            ExprKind::Let {
+                bindings,
+                body,
+                sequential,
+            } => {
+                let form = if *sequential { "Let*" } else { "Let" };
+                self.nested(format_args!("{form} {at}"), |dump| {
+                    dump.bindings(bindings);
+                    dump.exprs("body", body);
+                })
+            }
+            ExprKind::Setq(pairs) => {
+                self.nested(format_args!("Setq {at}"), |dump| dump.bindings(pairs))
+            }
+            ExprKind::Defvar { name, value } => {
+                self.nested(format_args!("Defvar {} {at}", name.name), |dump| {
+                    if let Some(value) = value {
+                        dump.expr(value);
+                    }
+                })
+            }
+            ExprKind::And(args) => self.exprs(&format!("And {at}"), args),
+            ExprKind::Or(args) => self.exprs(&format!("Or {at}"), args),
+            ExprKind::Loop(body) => self.exprs(&format!("Loop {at}"), body),
+            ExprKind::Return(value) => self.nested(format_args!("Return {at}"), |dump| {
+                if let Some(value) = value {
+                    dump.expr(value);
+                }
+            }),
+            ExprKind::ReturnFrom { name, value } => {
+                self.nested(format_args!("ReturnFrom {} {at}", name.name), |dump| {
+                    if let Some(value) = value {
+                        dump.expr(value);
+                    }
+                })
+            }
+            ExprKind::The { ty, value } => self
+                .nested(format_args!("The {} {at}", TypeText(ty)), |dump| {
+                    dump.expr(value)
+                }),
+            ExprKind::Declaim { inline, notinline } => {
+                self.nested(format_args!("Declaim {at}"), |dump| {
+                    if !inline.is_empty() {
+                        dump.line(format_args!("inline {}", names(inline)));
+                    }
+                    if !notinline.is_empty() {
+                        dump.line(format_args!("notinline {}", names(notinline)));
+                    }
+                })
+            }
+            ExprKind::Defpackage(package) => self.nested(
+                format_args!("Defpackage {} {at}", package.name.name),
+                |dump| {
+                    if !package.uses.is_empty() {
+                        dump.line(format_args!("use {}", names(&package.uses)));
+                    }
+                    if !package.exports.is_empty() {
+                        dump.line(format_args!("export {}", names(&package.exports)));
+                    }
+                },
+            ),
+            ExprKind::InPackage(name) => self.line(format_args!("InPackage {} {at}", name.name)),
+            ExprKind::Require(modules) => {
+                self.line(format_args!("Require {} {at}", names(modules)))
+            }
+            ExprKind::Import(modules) => self.line(format_args!("Import {} {at}", names(modules))),
+            ExprKind::MethodCall {
+                object,
+                method,
+                args,
+            } => self.nested(format_args!("MethodCall {} {at}", method.name), |dump| {
+                dump.expr(object);
+                for arg in args {
+                    dump.expr(arg);
+                }
+            }),
+            ExprKind::Attribute { object, attr } => self
+                .nested(format_args!("Attribute {} {at}", attr.name), |dump| {
+                    dump.expr(object)
+                }),
+        }
+    }
+
+    fn params(&mut self, params: &LambdaList) {
+        self.nested(format_args!("params {}", span(params.span)), |dump| {
+            if !params.required.is_empty() {
+                dump.line(format_args!("required {}", names(&params.required)));
+            }
+            for (param, default) in &params.optional {
+                dump.nested(format_args!("optional {}", param.name), |dump| {
+                    if let Some(default) = default {
+                        dump.expr(default);
+                    }
+                });
+            }
+            if let Some(rest) = &params.rest {
+                dump.line(format_args!("rest {}", rest.name));
+            }
+            for (param, default) in &params.key {
+                dump.nested(format_args!("key {}", param.name), |dump| {
+                    if let Some(default) = default {
+                        dump.expr(default);
+                    }
+                });
+            }
+            if params.allow_other_keys {
+                dump.line("allow-other-keys");
+            }
+        });
+    }
+
+    fn bindings(&mut self, bindings: &[(Ident, Expr)]) {
+        for (name, value) in bindings {
+            self.nested(format_args!("{} {}", name.name, span(name.span)), |dump| {
+                dump.expr(value)
+            });
+        }
+    }
+}
+
+fn span(span: Span) -> String {
+    format!(
+        "{}:{}-{}:{}",
+        span.start_row, span.start_col, span.end_row, span.end_col
+    )
+}
+
+fn names(idents: &[Ident]) -> String {
+    idents
+        .iter()
+        .map(|ident| ident.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quoted data, as written in Lisp.
+struct DatumText<'d>(&'d Datum);
+
+impl Display for DatumText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Datum::Nil => f.write_str("nil"),
+            Datum::Number(n) | Datum::Symbol(n) => f.write_str(n),
+            Datum::Str(s) => write!(f, "{s:?}"),
+            Datum::Quote(datum) => write!(f, "'{}", DatumText(datum)),
+            Datum::List(items, tail) => {
+                f.write_str("(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", DatumText(item))?;
+                }
+                if let Some(tail) = tail {
+                    write!(f, " . {}", DatumText(tail))?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// Type specifier, as written in Lisp.
+struct TypeText<'t>(&'t TypeSpec);
+
+impl Display for TypeText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            TypeSpec::Integer => "integer",
+            TypeSpec::Float => "float",
+            TypeSpec::String => "string",
+            TypeSpec::Boolean => "boolean",
+            TypeSpec::List => "list",
+            TypeSpec::Function => "function",
+            TypeSpec::Null => "null",
+            TypeSpec::T => "t",
+            TypeSpec::OrNull(spec) => return write!(f, "(or null {})", TypeText(spec)),
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,1168 @@
+//! Lisp syntax tree, built from the parser's s-expressions with special
+//! forms recognised.
+use lexer::{Keyword, Span, TokenKind as LexerTokenKind};
+use parser::{AtomKind, SExpr, Token, TokenKind};
+
+/// Heads of the special forms, read as forms of their own rather than calls.
+pub const SPECIAL_FORMS: [&str; 29] = [
+    "quote",
+    "function",
+    "defun",
+    "lambda",
+    "funcall",
+    "if",
+    "when",
+    "unless",
+    "cond",
+    "progn",
+    "let",
+    "let*",
+    "setq",
+    "setf",
+    "defvar",
+    "defparameter",
+    "and",
+    "or",
+    "loop",
+    "return",
+    "return-from",
+    "the",
+    "declare",
+    "declaim",
+    "defpackage",
+    "in-package",
+    "require",
+    "import",
+    ".",
+];
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Nil,
+    T,
+    Number(String),
+    /// String contents, with Lisp escapes resolved.
+    Str(String),
+    Symbol(String),
+    /// `#'f` or `(function f)`, naming the function `f`.
+    Function(Ident),
+    Quote(Datum),
+    Call {
+        func: Ident,
+        args: Vec<Expr>,
+    },
+    /// `(funcall f args...)` or `((lambda ...) args...)`
+    Funcall {
+        func: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Defun(Defun),
+    Lambda {
+        params: LambdaList,
+        body: Vec<Expr>,
+    },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Option<Box<Expr>>,
+    },
+    Cond(Vec<CondClause>),
+    Progn(Vec<Expr>),
+    /// `let`, or `let*` when `sequential`.
+    Let {
+        bindings: Vec<(Ident, Expr)>,
+        body: Vec<Expr>,
+        sequential: bool,
+    },
+    Setq(Vec<(Ident, Expr)>),
+    /// `defvar` or `defparameter`
+    Defvar {
+        name: Ident,
+        value: Option<Box<Expr>>,
+    },
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Loop(Vec<Expr>),
+    /// `(return value)`, leaving the innermost `loop`.
+    Return(Option<Box<Expr>>),
+    /// `(return-from name value)`, leaving the function `name`.
+    ReturnFrom {
+        name: Ident,
+        value: Option<Box<Expr>>,
+    },
+    /// `(the type value)`
+    The {
+        ty: TypeSpec,
+        value: Box<Expr>,
+    },
+    /// `(declaim (inline f) (notinline g))`
+    Declaim {
+        inline: Vec<Ident>,
+        notinline: Vec<Ident>,
+    },
+    Defpackage(Package),
+    /// `(in-package name)`, naming the package of the forms after it.
+    InPackage(Ident),
+    /// `(require name...)`, importing Python modules of the same names.
+    Require(Vec<Ident>),
+    /// `(import module...)`, with dotted Python module names.
+    Import(Vec<Ident>),
+    /// `(.method object args...)`
+    MethodCall {
+        object: Box<Expr>,
+        method: Ident,
+        args: Vec<Expr>,
+    },
+    /// `(. object attr)`
+    Attribute {
+        object: Box<Expr>,
+        attr: Ident,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Defun {
+    pub name: Ident,
+    pub params: LambdaList,
+    pub doc: Option<String>,
+    /// Parameter types from `(declare (type ...))` at the start of the body.
+    pub declarations: Vec<Declaration>,
+    pub body: Vec<Expr>,
+}
+
+/// `(defpackage name (:use ...) (:export ...))`. Packages map onto the
+/// modules of the files in them: a module imports what it refers to from
+/// the packages it uses, and lists what it defines of its package's exports
+/// in `__all__`.
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: Ident,
+    /// Packages whose exports are visible without a package prefix.
+    pub uses: Vec<Ident>,
+    pub exports: Vec<Ident>,
+}
+
+/// A parameter and its declared type.
+pub type Declaration = (Ident, TypeSpec);
+
+/// A type specifier, as in `declare` and `the`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeSpec {
+    Integer,
+    /// `float`, or `number` and `real`, which integers are too.
+    Float,
+    String,
+    Boolean,
+    List,
+    Function,
+    Null,
+    /// `t`, any value.
+    T,
+    /// `(or null type)`
+    OrNull(Box<TypeSpec>),
+}
+
+impl TypeSpec {
+    fn from_name(name: &str) -> Option<TypeSpec> {
+        Some(match name {
+            "integer" | "fixnum" | "bignum" => TypeSpec::Integer,
+            "float" | "single-float" | "double-float" | "real" | "number" => TypeSpec::Float,
+            "string" | "simple-string" => TypeSpec::String,
+            "boolean" => TypeSpec::Boolean,
+            "list" | "cons" => TypeSpec::List,
+            "function" => TypeSpec::Function,
+            "null" => TypeSpec::Null,
+            "t" => TypeSpec::T,
+            _ => return None,
+        })
+    }
+}
+
+/// Parameters of a `defun` or `lambda`.
+#[derive(Debug, Clone, Default)]
+pub struct LambdaList {
+    pub required: Vec<Ident>,
+    /// `&optional` parameters, with their default values.
+    pub optional: Vec<(Ident, Option<Expr>)>,
+    /// `&rest` or `&body` parameter.
+    pub rest: Option<Ident>,
+    /// `&key` parameters, with their default values.
+    pub key: Vec<(Ident, Option<Expr>)>,
+    pub allow_other_keys: bool,
+    pub span: Span,
+}
+
+impl LambdaList {
+    /// Every parameter, in the order they are bound.
+    pub fn names(&self) -> impl Iterator<Item = &Ident> {
+        self.required
+            .iter()
+            .chain(self.optional.iter().map(|(name, _)| name))
+            .chain(&self.rest)
+            .chain(self.key.iter().map(|(name, _)| name))
+    }
+
+    /// Default value expressions, in the order they are evaluated.
+    pub fn defaults(&self) -> impl Iterator<Item = &Expr> {
+        self.optional
+            .iter()
+            .chain(&self.key)
+            .filter_map(|(_, default)| default.as_ref())
+    }
+
+    pub fn defaults_mut(&mut self) -> impl Iterator<Item = &mut Expr> {
+        self.optional
+            .iter_mut()
+            .chain(&mut self.key)
+            .filter_map(|(_, default)| default.as_mut())
+    }
+
+    /// Whether there are only required parameters.
+    pub fn is_simple(&self) -> bool {
+        self.optional.is_empty() && self.rest.is_none() && self.key.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CondClause {
+    pub test: Expr,
+    pub body: Vec<Expr>,
+}
+
+/// Quoted data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Nil,
+    Number(String),
+    Str(String),
+    Symbol(String),
+    /// Elements, followed by the tail of a dotted list.
+    List(Vec<Datum>, Option<Box<Datum>>),
+    Quote(Box<Datum>),
+}
+
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub span: Span,
+    pub kind: SyntaxErrorKind,
+}
+
+#[derive(Debug)]
+pub enum SyntaxErrorKind {
+    /// A special form is missing a part, or has it in the wrong shape.
+    Malformed {
+        form: &'static str,
+        expected: &'static str,
+    },
+    /// Dotted lists are only allowed in quoted data.
+    DottedForm,
+}
+
+impl Expr {
+    pub fn from_token(token: &Token, src: &str) -> Result<Expr, SyntaxError> {
+        let span = token.span;
+        let kind = match token.kind {
+            TokenKind::Atom(ref atom) => atom_kind(atom, src),
+            TokenKind::Quote(ref datum) => ExprKind::Quote(Datum::from_token(datum, src)),
+            TokenKind::Nil | TokenKind::ListNil | TokenKind::SExpr(SExpr::Nil) => ExprKind::Nil,
+            TokenKind::SExpr(SExpr::Cons { ref tail, .. }) if tail.is_some() => {
+                return Err(SyntaxError {
+                    span,
+                    kind: SyntaxErrorKind::DottedForm,
+                })
+            }
+            TokenKind::SExpr(SExpr::Cons {
+                ref car, ref cdr, ..
+            }) => {
+                let args = elements(cdr);
+                return Form { span, src }.build(car, args);
+            }
+            TokenKind::EOF => unreachable!("EOF is not a form"),
+        };
+        Ok(Expr { kind, span })
+    }
+
+    /// Immediate subexpressions, in evaluation order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Nil
+            | ExprKind::T
+            | ExprKind::Number(_)
+            | ExprKind::Str(_)
+            | ExprKind::Symbol(_)
+            | ExprKind::Function(_)
+            | ExprKind::Quote(_)
+            | ExprKind::Declaim { .. }
+            | ExprKind::Defpackage(_)
+            | ExprKind::InPackage(_)
+            | ExprKind::Require(_)
+            | ExprKind::Import(_) => vec![],
+            ExprKind::Call { args, .. } => args.iter().collect(),
+            ExprKind::MethodCall { object, args, .. } => {
+                std::iter::once(&**object).chain(args).collect()
+            }
+            ExprKind::Attribute { object, .. } => vec![object],
+            ExprKind::Funcall { func, args } => std::iter::once(&**func).chain(args).collect(),
+            ExprKind::Defun(defun) => defun.params.defaults().chain(&defun.body).collect(),
+            ExprKind::Lambda { params, body } => params.defaults().chain(body).collect(),
+            ExprKind::Progn(body)
+            | ExprKind::And(body)
+            | ExprKind::Or(body)
+            | ExprKind::Loop(body) => body.iter().collect(),
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => [Some(&**cond), Some(&**then), otherwise.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            ExprKind::Cond(clauses) => clauses
+                .iter()
+                .flat_map(|clause| std::iter::once(&clause.test).chain(&clause.body))
+                .collect(),
+            ExprKind::Let { bindings, body, .. } => bindings
+                .iter()
+                .map(|(_, value)| value)
+                .chain(body)
+                .collect(),
+            ExprKind::Setq(pairs) => pairs.iter().map(|(_, value)| value).collect(),
+            ExprKind::The { value, .. } => vec![value],
+            ExprKind::Defvar { value, .. }
+            | ExprKind::Return(value)
+            | ExprKind::ReturnFrom { value, .. } => value.iter().map(|value| &**value).collect(),
+        }
+    }
+
+    /// Immediate subexpressions, mutably, in evaluation order.
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match &mut self.kind {
+            ExprKind::Nil
+            | ExprKind::T
+            | ExprKind::Number(_)
+            | ExprKind::Str(_)
+            | ExprKind::Symbol(_)
+            | ExprKind::Function(_)
+            | ExprKind::Quote(_)
+            | ExprKind::Declaim { .. }
+            | ExprKind::Defpackage(_)
+            | ExprKind::InPackage(_)
+            | ExprKind::Require(_)
+            | ExprKind::Import(_) => vec![],
+            ExprKind::Call { args, .. } => args.iter_mut().collect(),
+            ExprKind::MethodCall { object, args, .. } => {
+                std::iter::once(&mut **object).chain(args).collect()
+            }
+            ExprKind::Attribute { object, .. } => vec![object],
+            ExprKind::Funcall { func, args } => std::iter::once(&mut **func).chain(args).collect(),
+            ExprKind::Defun(defun) => defun.params.defaults_mut().chain(&mut defun.body).collect(),
+            ExprKind::Lambda { params, body } => params.defaults_mut().chain(body).collect(),
+            ExprKind::Progn(body)
+            | ExprKind::And(body)
+            | ExprKind::Or(body)
+            | ExprKind::Loop(body) => body.iter_mut().collect(),
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => [
+                Some(&mut **cond),
+                Some(&mut **then),
+                otherwise.as_deref_mut(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            ExprKind::Cond(clauses) => clauses
+                .iter_mut()
+                .flat_map(|clause| std::iter::once(&mut clause.test).chain(&mut clause.body))
+                .collect(),
+            ExprKind::Let { bindings, body, .. } => bindings
+                .iter_mut()
+                .map(|(_, value)| value)
+                .chain(body)
+                .collect(),
+            ExprKind::Setq(pairs) => pairs.iter_mut().map(|(_, value)| value).collect(),
+            ExprKind::The { value, .. } => vec![value],
+            ExprKind::Defvar { value, .. }
+            | ExprKind::Return(value)
+            | ExprKind::ReturnFrom { value, .. } => {
+                value.iter_mut().map(|value| &mut **value).collect()
+            }
+        }
+    }
+
+    /// Call `f` on this expression and every expression nested in it.
+    pub fn walk<'e>(&'e self, f: &mut impl FnMut(&'e Expr)) {
+        f(self);
+        for child in self.children() {
+            child.walk(f);
+        }
+    }
+}
+
+impl Datum {
+    pub fn from_token(token: &Token, src: &str) -> Datum {
+        match token.kind {
+            TokenKind::Atom(ref atom) => match atom_kind(atom, src) {
+                ExprKind::Nil => Datum::Nil,
+                ExprKind::T => Datum::Symbol("t".to_string()),
+                ExprKind::Number(n) => Datum::Number(n),
+                ExprKind::Str(s) => Datum::Str(s),
+                ExprKind::Symbol(s) => Datum::Symbol(s),
+                ExprKind::Function(ident) => Datum::Symbol(format!("#'{}", ident.name)),
+                _ => unreachable!("atoms are literals or symbols"),
+            },
+            TokenKind::Quote(ref datum) => Datum::Quote(Box::new(Datum::from_token(datum, src))),
+            TokenKind::SExpr(SExpr::Cons {
+                ref car,
+                ref cdr,
+                ref tail,
+            }) => {
+                let items = std::iter::once(&**car)
+                    .chain(elements(cdr))
+                    .map(|token| Datum::from_token(token, src))
+                    .collect();
+                let tail = tail
+                    .as_deref()
+                    .map(|tail| Box::new(Datum::from_token(tail, src)));
+                Datum::List(items, tail)
+            }
+            TokenKind::Nil | TokenKind::ListNil | TokenKind::SExpr(SExpr::Nil) => Datum::Nil,
+            TokenKind::EOF => unreachable!("EOF is not a datum"),
+        }
+    }
+
+    /// Whether this is a proper list (or nil), ignoring nested elements.
+    pub fn is_list(&self) -> bool {
+        match self {
+            Datum::Nil => true,
+            Datum::List(_, tail) => tail.as_deref().is_none_or(Datum::is_list),
+            _ => false,
+        }
+    }
+}
+
+/// List elements of a cons' cdr, without the closing parenthesis.
+fn elements(cdr: &[Token]) -> &[Token] {
+    match cdr.last() {
+        Some(Token {
+            kind: TokenKind::Nil,
+            ..
+        }) => &cdr[..cdr.len() - 1],
+        _ => cdr,
+    }
+}
+
+fn atom_kind(atom: &AtomKind, src: &str) -> ExprKind {
+    match atom {
+        AtomKind::Symbol(_, Some(Keyword::nil)) => ExprKind::Nil,
+        AtomKind::Symbol(token, _) => symbol_kind(token.as_str(src), token.span),
+        AtomKind::Literal(token) => match token.kind {
+            LexerTokenKind::Literal if token.as_str(src).starts_with('"') => {
+                ExprKind::Str(unescape(token.as_str(src)))
+            }
+            LexerTokenKind::Literal => ExprKind::Number(token.as_str(src).to_string()),
+            // Operators such as `<` and `=` have their own token kinds
+            _ => symbol_kind(token.as_str(src), token.span),
+        },
+    }
+}
+
+fn symbol_kind(text: &str, span: Span) -> ExprKind {
+    match text {
+        "t" => ExprKind::T,
+        // Uninterned symbols only serve as names, as keywords do
+        text if text.starts_with("#:") => ExprKind::Symbol(format!(":{}", &text[2..])),
+        text if text.starts_with("#'") => ExprKind::Function(Ident {
+            name: text[2..].to_string(),
+            span,
+        }),
+        text if is_number(text) => ExprKind::Number(text.to_string()),
+        text => ExprKind::Symbol(text.to_string()),
+    }
+}
+
+/// Keyword arguments of a call, by name.
+pub type Keywords<'e> = Vec<(&'e str, &'e Expr)>;
+
+/// Positional arguments of a call to Python and the keyword arguments after
+/// them, or `None` if a keyword is missing its value.
+pub fn split_keywords(args: &[Expr]) -> Option<(&[Expr], Keywords<'_>)> {
+    fn keyword(expr: &Expr) -> Option<&str> {
+        match &expr.kind {
+            ExprKind::Symbol(name) => name.strip_prefix(':'),
+            _ => None,
+        }
+    }
+    let start = args
+        .iter()
+        .position(|arg| keyword(arg).is_some())
+        .unwrap_or(args.len());
+    let (positional, rest) = args.split_at(start);
+    let mut keywords = vec![];
+    for pair in rest.chunks(2) {
+        let [name, value] = pair else {
+            return None;
+        };
+        keywords.push((keyword(name)?, value));
+    }
+    Some((positional, keywords))
+}
+
+/// Whether `name` is a dotted Python name such as `os.path`.
+fn is_python_name(name: &str) -> bool {
+    name.split('.').all(|part| {
+        part.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+fn is_number(text: &str) -> bool {
+    let digits = text.strip_prefix(['-', '+']).unwrap_or(text);
+    !digits.is_empty()
+        && digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits.parse::<f64>().is_ok()
+}
+
+/// Contents of a string literal with its quotes removed and escapes resolved.
+fn unescape(literal: &str) -> String {
+    let content = literal.strip_prefix('"').unwrap_or(literal);
+    let content = content.strip_suffix('"').unwrap_or(content);
+    let mut out = String::new();
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A list form being turned into an expression.
+struct Form<'a> {
+    span: Span,
+    src: &'a str,
+}
+
+impl<'a> Form<'a> {
+    fn build(&self, car: &Token, args: &[Token]) -> Result<Expr, SyntaxError> {
+        let head = match car.kind {
+            TokenKind::Atom(ref atom) => match atom_kind(atom, self.src) {
+                ExprKind::Symbol(name) => Ident {
+                    name,
+                    span: car.span,
+                },
+                _ => return self.malformed("call", "a function name"),
+            },
+            _ => {
+                let func = Box::new(Expr::from_token(car, self.src)?);
+                let args = self.exprs(args)?;
+                return Ok(self.expr(ExprKind::Funcall { func, args }));
+            }
+        };
+
+        let kind = match head.name.as_str() {
+            "quote" => match args {
+                [datum] => ExprKind::Quote(Datum::from_token(datum, self.src)),
+                _ => return self.malformed("quote", "exactly one datum"),
+            },
+            "function" => match args {
+                // `(function (lambda ...))` is the lambda itself
+                [name @ Token {
+                    kind: TokenKind::SExpr(_),
+                    ..
+                }] => Expr::from_token(name, self.src)?.kind,
+                [name] => ExprKind::Function(self.ident("function", name)?),
+                _ => return self.malformed("function", "a function name"),
+            },
+            "defun" => {
+                let [name, params, body @ ..] = args else {
+                    return self.malformed("defun", "a name and a parameter list");
+                };
+                let name = self.ident("defun", name)?;
+                let params = self.params("defun", params)?;
+                let (declarations, body) = self.declarations(body)?;
+                let mut body = body
+                    .into_iter()
+                    .map(|token| Expr::from_token(token, self.src))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let doc = match body.first() {
+                    Some(Expr {
+                        kind: ExprKind::Str(doc),
+                        ..
+                    }) if body.len() > 1 => Some(doc.clone()),
+                    _ => None,
+                };
+                if doc.is_some() {
+                    body.remove(0);
+                }
+                ExprKind::Defun(Defun {
+                    name,
+                    params,
+                    doc,
+                    declarations,
+                    body,
+                })
+            }
+            "lambda" => {
+                let [params, body @ ..] = args else {
+                    return self.malformed("lambda", "a parameter list");
+                };
+                ExprKind::Lambda {
+                    params: self.params("lambda", params)?,
+                    body: self.exprs(body)?,
+                }
+            }
+            "funcall" => {
+                let [func, args @ ..] = args else {
+                    return self.malformed("funcall", "a function");
+                };
+                ExprKind::Funcall {
+                    func: Box::new(Expr::from_token(func, self.src)?),
+                    args: self.exprs(args)?,
+                }
+            }
+            "if" => {
+                let (cond, then, otherwise) = match args {
+                    [cond, then] => (cond, then, None),
+                    [cond, then, otherwise] => (cond, then, Some(otherwise)),
+                    _ => return self.malformed("if", "a condition and one or two branches"),
+                };
+                ExprKind::If {
+                    cond: Box::new(Expr::from_token(cond, self.src)?),
+                    then: Box::new(Expr::from_token(then, self.src)?),
+                    otherwise: otherwise
+                        .map(|otherwise| Expr::from_token(otherwise, self.src))
+                        .transpose()?
+                        .map(Box::new),
+                }
+            }
+            "when" | "unless" => {
+                let form = match head.name.as_str() {
+                    "when" => "when",
+                    _ => "unless",
+                };
+                let [cond, body @ ..] = args else {
+                    return self.malformed(form, "a condition");
+                };
+                let cond = Expr::from_token(cond, self.src)?;
+                let body = self.expr(ExprKind::Progn(self.exprs(body)?));
+                let (then, otherwise) = match form {
+                    "when" => (body, None),
+                    _ => (self.expr(ExprKind::Nil), Some(Box::new(body))),
+                };
+                ExprKind::If {
+                    cond: Box::new(cond),
+                    then: Box::new(then),
+                    otherwise,
+                }
+            }
+            "cond" => {
+                let mut clauses = vec![];
+                for clause in args {
+                    let TokenKind::SExpr(SExpr::Cons {
+                        ref car,
+                        ref cdr,
+                        tail: None,
+                    }) = clause.kind
+                    else {
+                        return self.malformed("cond", "clauses of the form (test body...)");
+                    };
+                    clauses.push(CondClause {
+                        test: Expr::from_token(car, self.src)?,
+                        body: self.exprs(elements(cdr))?,
+                    });
+                }
+                ExprKind::Cond(clauses)
+            }
+            "progn" => ExprKind::Progn(self.exprs(args)?),
+            "let" | "let*" => {
+                let sequential = head.name == "let*";
+                let form = if sequential { "let*" } else { "let" };
+                let [bindings, body @ ..] = args else {
+                    return self.malformed(form, "a binding list");
+                };
+                ExprKind::Let {
+                    bindings: self.bindings(form, bindings)?,
+                    body: self.exprs(body)?,
+                    sequential,
+                }
+            }
+            "setq" | "setf" => {
+                let form = if head.name == "setf" { "setf" } else { "setq" };
+                if !args.len().is_multiple_of(2) {
+                    return self.malformed(form, "pairs of variables and values");
+                }
+                let mut pairs = vec![];
+                for pair in args.chunks(2) {
+                    // Only variables are places
+                    if form == "setf" && matches!(pair[0].kind, TokenKind::SExpr(_)) {
+                        return Err(self.error_at(
+                            pair[0].span,
+                            form,
+                            "a variable, as places such as `(car x)` are not supported",
+                        ));
+                    }
+                    pairs.push((
+                        self.ident(form, &pair[0])?,
+                        Expr::from_token(&pair[1], self.src)?,
+                    ));
+                }
+                ExprKind::Setq(pairs)
+            }
+            "defvar" | "defparameter" => {
+                let form = if head.name == "defvar" {
+                    "defvar"
+                } else {
+                    "defparameter"
+                };
+                let [name, rest @ ..] = args else {
+                    return self.malformed(form, "a variable name");
+                };
+                ExprKind::Defvar {
+                    name: self.ident(form, name)?,
+                    value: rest
+                        .first()
+                        .map(|value| Expr::from_token(value, self.src))
+                        .transpose()?
+                        .map(Box::new),
+                }
+            }
+            "and" => ExprKind::And(self.exprs(args)?),
+            "or" => ExprKind::Or(self.exprs(args)?),
+            "loop" => ExprKind::Loop(self.exprs(args)?),
+            "return" => match args {
+                [] => ExprKind::Return(None),
+                [value] => ExprKind::Return(Some(Box::new(Expr::from_token(value, self.src)?))),
+                _ => return self.malformed("return", "at most one value"),
+            },
+            "return-from" => match args {
+                [name] => ExprKind::ReturnFrom {
+                    name: self.ident("return-from", name)?,
+                    value: None,
+                },
+                [name, value] => ExprKind::ReturnFrom {
+                    name: self.ident("return-from", name)?,
+                    value: Some(Box::new(Expr::from_token(value, self.src)?)),
+                },
+                _ => return self.malformed("return-from", "a block name and at most one value"),
+            },
+            "the" => match args {
+                [ty, value] => ExprKind::The {
+                    ty: self.type_spec("the", ty)?,
+                    value: Box::new(Expr::from_token(value, self.src)?),
+                },
+                _ => return self.malformed("the", "a type and a value"),
+            },
+            "declare" => return self.malformed("declare", "to be at the start of a defun body"),
+            "declaim" => {
+                let mut inline = vec![];
+                let mut notinline = vec![];
+                for clause in args {
+                    let expected = "proclamations such as (inline f)";
+                    let items = self.list("declaim", clause, expected)?;
+                    let [kind, functions @ ..] = items.as_slice() else {
+                        return Err(self.error_at(clause.span, "declaim", expected));
+                    };
+                    let names = match self.head(kind) {
+                        Some("inline") => &mut inline,
+                        Some("notinline") => &mut notinline,
+                        Some("optimize") => continue,
+                        _ => return Err(self.error_at(kind.span, "declaim", expected)),
+                    };
+                    for function in functions {
+                        names.push(self.ident("declaim", function)?);
+                    }
+                }
+                ExprKind::Declaim { inline, notinline }
+            }
+            "defpackage" => {
+                let [name, options @ ..] = args else {
+                    return self.malformed("defpackage", "a package name");
+                };
+                let mut package = Package {
+                    name: self.designator("defpackage", name)?,
+                    uses: vec![],
+                    exports: vec![],
+                };
+                for option in options {
+                    let expected = "options such as (:use ...) or (:export ...)";
+                    let items = self.list("defpackage", option, expected)?;
+                    let [kind, names @ ..] = items.as_slice() else {
+                        return Err(self.error_at(option.span, "defpackage", expected));
+                    };
+                    let list = match self.head(kind) {
+                        Some(":use") => &mut package.uses,
+                        Some(":export") => &mut package.exports,
+                        Some(":documentation") => continue,
+                        _ => return Err(self.error_at(kind.span, "defpackage", expected)),
+                    };
+                    for name in names {
+                        list.push(self.designator("defpackage", name)?);
+                    }
+                }
+                ExprKind::Defpackage(package)
+            }
+            "in-package" => match args {
+                [name] => ExprKind::InPackage(self.designator("in-package", name)?),
+                _ => return self.malformed("in-package", "a package name"),
+            },
+            "require" => {
+                if args.is_empty() {
+                    return self.malformed("require", "a module name");
+                }
+                let names = args
+                    .iter()
+                    .map(|name| self.designator("require", name))
+                    .collect::<Result<_, _>>()?;
+                ExprKind::Require(names)
+            }
+            "import" => {
+                if args.is_empty() {
+                    return self.malformed("import", "a module name");
+                }
+                let modules = args
+                    .iter()
+                    .map(|module| self.python_name("import", module))
+                    .collect::<Result<_, _>>()?;
+                ExprKind::Import(modules)
+            }
+            "." => match args {
+                [object, attr] => ExprKind::Attribute {
+                    object: Box::new(Expr::from_token(object, self.src)?),
+                    attr: self.python_name(".", attr)?,
+                },
+                _ => return self.malformed(".", "an object and an attribute name"),
+            },
+            method if method.starts_with('.') => {
+                let [object, args @ ..] = args else {
+                    return self.malformed("method call", "an object");
+                };
+                let method = Ident {
+                    name: method[1..].to_owned(),
+                    span: head.span,
+                };
+                if !is_python_name(&method.name) {
+                    return Err(self.error_at(head.span, "method call", "a Python name"));
+                }
+                ExprKind::MethodCall {
+                    object: Box::new(Expr::from_token(object, self.src)?),
+                    method,
+                    args: self.python_args("method call", args)?,
+                }
+            }
+            _ if head.name.starts_with("py:") => {
+                if !is_python_name(&head.name[3..]) {
+                    return Err(self.error_at(head.span, "call", "a Python name after `py:`"));
+                }
+                ExprKind::Call {
+                    args: self.python_args("call", args)?,
+                    func: head,
+                }
+            }
+            _ => ExprKind::Call {
+                func: head,
+                args: self.exprs(args)?,
+            },
+        };
+        Ok(self.expr(kind))
+    }
+
+    /// Arguments of a call to Python, whose keyword arguments must come in
+    /// pairs after the positional ones.
+    fn python_args(&self, form: &'static str, args: &[Token]) -> Result<Vec<Expr>, SyntaxError> {
+        let args = self.exprs(args)?;
+        match split_keywords(&args) {
+            Some(_) => Ok(args),
+            None => self.malformed(form, "a value after each keyword argument"),
+        }
+    }
+
+    /// Symbol naming a Python module or attribute, possibly dotted.
+    fn python_name(&self, form: &'static str, token: &Token) -> Result<Ident, SyntaxError> {
+        match self.ident(form, token) {
+            Ok(ident) if is_python_name(&ident.name) => Ok(ident),
+            _ => Err(self.error_at(token.span, form, "a Python name")),
+        }
+    }
+
+    fn expr(&self, kind: ExprKind) -> Expr {
+        Expr {
+            kind,
+            span: self.span,
+        }
+    }
+
+    fn exprs(&self, tokens: &[Token]) -> Result<Vec<Expr>, SyntaxError> {
+        tokens
+            .iter()
+            .map(|token| Expr::from_token(token, self.src))
+            .collect()
+    }
+
+    fn ident(&self, form: &'static str, token: &Token) -> Result<Ident, SyntaxError> {
+        match token.kind {
+            TokenKind::Atom(ref atom) => match atom_kind(atom, self.src) {
+                ExprKind::Symbol(name) => Ok(Ident {
+                    name,
+                    span: token.span,
+                }),
+                _ => Err(self.error_at(token.span, form, "a symbol")),
+            },
+            _ => Err(self.error_at(token.span, form, "a symbol")),
+        }
+    }
+
+    /// Name given by a symbol, keyword, uninterned symbol or string,
+    /// lowercased as the Lisp reader would upcase it.
+    fn designator(&self, form: &'static str, token: &Token) -> Result<Ident, SyntaxError> {
+        let name = match token.kind {
+            TokenKind::Atom(ref atom) => match atom_kind(atom, self.src) {
+                ExprKind::Symbol(name) => name.strip_prefix(':').unwrap_or(&name).to_owned(),
+                ExprKind::Str(name) => name,
+                _ => return Err(self.error_at(token.span, form, "a name")),
+            },
+            TokenKind::Quote(ref datum) => return self.designator(form, datum),
+            _ => return Err(self.error_at(token.span, form, "a name")),
+        };
+        Ok(Ident {
+            name: name.to_lowercase(),
+            span: token.span,
+        })
+    }
+
+    fn params(&self, form: &'static str, token: &Token) -> Result<LambdaList, SyntaxError> {
+        enum Section {
+            Required,
+            Optional,
+            Rest,
+            Key,
+        }
+        let mut params = LambdaList {
+            span: token.span,
+            ..LambdaList::default()
+        };
+        let mut section = Section::Required;
+        for param in self.list(form, token, "a parameter list")? {
+            let keyword = match param.kind {
+                TokenKind::Atom(AtomKind::Symbol(ref symbol, None)) => symbol.as_str(self.src),
+                _ => "",
+            };
+            section = match keyword {
+                "&optional" => Section::Optional,
+                "&rest" | "&body" => Section::Rest,
+                "&key" => Section::Key,
+                "&allow-other-keys" => {
+                    params.allow_other_keys = true;
+                    continue;
+                }
+                keyword if keyword.starts_with('&') => {
+                    return Err(self.error_at(
+                        param.span,
+                        form,
+                        "one of &optional, &rest, &body, &key or &allow-other-keys",
+                    ))
+                }
+                _ => {
+                    match section {
+                        Section::Required => params.required.push(self.ident(form, param)?),
+                        Section::Optional => params.optional.push(self.defaulted(form, param)?),
+                        Section::Rest if params.rest.is_none() => {
+                            params.rest = Some(self.ident(form, param)?)
+                        }
+                        Section::Rest => {
+                            return Err(self.error_at(param.span, form, "a single &rest parameter"))
+                        }
+                        Section::Key => params.key.push(self.defaulted(form, param)?),
+                    }
+                    continue;
+                }
+            };
+        }
+        Ok(params)
+    }
+
+    /// `name` or `(name default)` in the `&optional` or `&key` section.
+    fn defaulted(
+        &self,
+        form: &'static str,
+        token: &Token,
+    ) -> Result<(Ident, Option<Expr>), SyntaxError> {
+        if let TokenKind::Atom(_) = token.kind {
+            return Ok((self.ident(form, token)?, None));
+        }
+        match self
+            .list(form, token, "a parameter of the form (name default)")?
+            .as_slice()
+        {
+            [name] => Ok((self.ident(form, name)?, None)),
+            [name, default] => Ok((
+                self.ident(form, name)?,
+                Some(Expr::from_token(default, self.src)?),
+            )),
+            _ => Err(self.error_at(token.span, form, "a parameter of the form (name default)")),
+        }
+    }
+
+    /// Type declarations among the docstring and `declare` forms starting
+    /// a `defun` body, and the rest of the body.
+    fn declarations<'t>(
+        &self,
+        body: &'t [Token],
+    ) -> Result<(Vec<Declaration>, Vec<&'t Token>), SyntaxError> {
+        let mut declarations = vec![];
+        let mut rest = vec![];
+        let mut header = true;
+        for token in body {
+            let clauses = match &token.kind {
+                TokenKind::SExpr(SExpr::Cons { car, cdr, .. })
+                    if header && self.head(car) == Some("declare") =>
+                {
+                    elements(cdr)
+                }
+                TokenKind::Atom(AtomKind::Literal(literal))
+                    if header && literal.as_str(self.src).starts_with('"') =>
+                {
+                    rest.push(token);
+                    continue;
+                }
+                _ => {
+                    header = false;
+                    rest.push(token);
+                    continue;
+                }
+            };
+            for clause in clauses {
+                let expected = "declarations such as (type integer n)";
+                let clause_items = self.list("declare", clause, expected)?;
+                let [kind, args @ ..] = clause_items.as_slice() else {
+                    return Err(self.error_at(clause.span, "declare", expected));
+                };
+                let (ty, names) = match self.head(kind) {
+                    Some("type") => match args {
+                        [ty, names @ ..] => (self.type_spec("declare", ty)?, names),
+                        [] => return Err(self.error_at(clause.span, "declare", "a type")),
+                    },
+                    Some("ignore" | "ignorable" | "optimize" | "dynamic-extent") => continue,
+                    _ => (self.type_spec("declare", kind)?, args),
+                };
+                for name in names {
+                    declarations.push((self.ident("declare", name)?, ty.clone()));
+                }
+            }
+        }
+        Ok((declarations, rest))
+    }
+
+    /// `integer`, `(or null string)` and the like.
+    fn type_spec(&self, form: &'static str, token: &Token) -> Result<TypeSpec, SyntaxError> {
+        let expected = "a type such as integer, string or (or null list)";
+        if let Some(spec) = self.head(token).and_then(TypeSpec::from_name) {
+            return Ok(spec);
+        }
+        // `nil` is read as a keyword rather than a symbol
+        if let TokenKind::Atom(AtomKind::Symbol(_, Some(Keyword::nil))) = token.kind {
+            return Ok(TypeSpec::Null);
+        }
+        match self.list(form, token, expected)?.as_slice() {
+            [or, a, b] if self.head(or) == Some("or") => {
+                match (self.type_spec(form, a)?, self.type_spec(form, b)?) {
+                    (TypeSpec::Null, ty) | (ty, TypeSpec::Null) => {
+                        Ok(TypeSpec::OrNull(Box::new(ty)))
+                    }
+                    _ => Ok(TypeSpec::T),
+                }
+            }
+            _ => Err(self.error_at(token.span, form, expected)),
+        }
+    }
+
+    /// Name of a symbol token.
+    fn head(&self, token: &Token) -> Option<&'a str> {
+        match token.kind {
+            TokenKind::Atom(AtomKind::Symbol(ref symbol, _)) => Some(symbol.as_str(self.src)),
+            _ => None,
+        }
+    }
+
+    /// Bindings of the `let` or `let*` named `form`.
+    fn bindings(
+        &self,
+        form: &'static str,
+        token: &Token,
+    ) -> Result<Vec<(Ident, Expr)>, SyntaxError> {
+        let mut bindings = vec![];
+        for binding in self.list(form, token, "a binding list")? {
+            bindings.push(match binding.kind {
+                // `(let (x) ...)` and `(let ((x)) ...)` bind to nil
+                TokenKind::Atom(_) => (
+                    self.ident(form, binding)?,
+                    Expr {
+                        kind: ExprKind::Nil,
+                        span: binding.span,
+                    },
+                ),
+                _ => match self
+                    .list(form, binding, "bindings of the form (name value)")?
+                    .as_slice()
+                {
+                    [name] => (
+                        self.ident(form, name)?,
+                        Expr {
+                            kind: ExprKind::Nil,
+                            span: binding.span,
+                        },
+                    ),
+                    [name, value] => (self.ident(form, name)?, Expr::from_token(value, self.src)?),
+                    _ => return Err(self.error_at(binding.span, form, "a name and a value")),
+                },
+            });
+        }
+        Ok(bindings)
+    }
+
+    /// Elements of a proper list token, which may be empty.
+    fn list<'t>(
+        &self,
+        form: &'static str,
+        token: &'t Token,
+        expected: &'static str,
+    ) -> Result<Vec<&'t Token>, SyntaxError> {
+        match token.kind {
+            TokenKind::ListNil
+            | TokenKind::SExpr(SExpr::Nil)
+            | TokenKind::Atom(AtomKind::Symbol(_, Some(Keyword::nil))) => Ok(vec![]),
+            TokenKind::SExpr(SExpr::Cons {
+                ref car,
+                ref cdr,
+                tail: None,
+            }) => Ok(std::iter::once(&**car).chain(elements(cdr)).collect()),
+            _ => Err(self.error_at(token.span, form, expected)),
+        }
+    }
+
+    fn malformed<T>(&self, form: &'static str, expected: &'static str) -> Result<T, SyntaxError> {
+        Err(self.error_at(self.span, form, expected))
+    }
+
+    fn error_at(&self, span: Span, form: &'static str, expected: &'static str) -> SyntaxError {
+        SyntaxError {
+            span,
+            kind: SyntaxErrorKind::Malformed { form, expected },
+        }
+    }
+}
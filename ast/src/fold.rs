@@ -0,0 +1,275 @@
+//! Constant folding, run at `-O1` after the analysis passes.
+//!
+//! Arithmetic and comparisons on number literals, `concatenate` of string
+//! literals and `not` of constants are evaluated, `if` and `cond` on constant
+//! conditions are reduced to the branch taken, and a `progn` of one form
+//! becomes that form. Folded predicates give `t` or `nil`.
+//!
+//! Conditions are only folded when Lisp and the generated Python agree on
+//! their truth: `0` and `""` are true in Lisp but false in Python, so they
+//! are left alone.
+use crate::expr::{CondClause, Datum, Expr, ExprKind};
+use crate::resolve::{Resolution, Resolved};
+
+pub fn fold(exprs: &mut [Expr], resolution: &Resolution) {
+    for expr in exprs {
+        fold_expr(expr, resolution);
+    }
+}
+
+fn fold_expr(expr: &mut Expr, resolution: &Resolution) {
+    for child in expr.children_mut() {
+        fold_expr(child, resolution);
+    }
+
+    let folded = match &mut expr.kind {
+        ExprKind::Call { func, args } => match resolution.get(func.span) {
+            Some(Resolved::Builtin(builtin)) => call(builtin.name, args),
+            _ => None,
+        },
+        ExprKind::If {
+            cond,
+            then,
+            otherwise,
+        } => match truth(cond) {
+            Some(true) => Some(std::mem::replace(&mut then.kind, ExprKind::Nil)),
+            Some(false) => Some(match otherwise {
+                Some(otherwise) => std::mem::replace(&mut otherwise.kind, ExprKind::Nil),
+                None => ExprKind::Nil,
+            }),
+            None => None,
+        },
+        ExprKind::Cond(clauses) => {
+            clauses.retain(|clause| truth(&clause.test) != Some(false));
+            match clauses.first().map(|clause| truth(&clause.test)) {
+                None => Some(ExprKind::Nil),
+                Some(Some(true)) => {
+                    let CondClause { test, body } = clauses.remove(0);
+                    match body.is_empty() {
+                        true => Some(test.kind),
+                        false => Some(ExprKind::Progn(body)),
+                    }
+                }
+                Some(_) => {
+                    // Clauses after one that is always taken are never reached
+                    if let Some(always) = clauses
+                        .iter()
+                        .position(|clause| truth(&clause.test) == Some(true))
+                    {
+                        clauses.truncate(always + 1);
+                    }
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    if let Some(kind) = folded {
+        expr.kind = kind;
+    }
+
+    // A `progn` may have been produced by folding the forms above
+    if let ExprKind::Progn(body) = &mut expr.kind {
+        match body.len() {
+            0 => expr.kind = ExprKind::Nil,
+            1 => *expr = body.pop().expect("one form"),
+            _ => {}
+        }
+    }
+}
+
+/// Value of a builtin called on constants.
+fn call(name: &str, args: &[Expr]) -> Option<ExprKind> {
+    match name {
+        "not" | "null" => match args {
+            [arg] => truth(arg).map(|truth| boolean(!truth)),
+            _ => None,
+        },
+        "concatenate" => {
+            let [Expr {
+                kind: ExprKind::Quote(Datum::Symbol(ty)),
+                ..
+            }, strings @ ..] = args
+            else {
+                return None;
+            };
+            if ty != "string" {
+                return None;
+            }
+            let mut out = String::new();
+            for string in strings {
+                match &string.kind {
+                    ExprKind::Str(s) => out.push_str(s),
+                    _ => return None,
+                }
+            }
+            Some(ExprKind::Str(out))
+        }
+        _ => {
+            let numbers = args
+                .iter()
+                .map(|arg| match &arg.kind {
+                    ExprKind::Number(n) => Number::parse(n),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            arithmetic(name, &numbers)
+        }
+    }
+}
+
+fn arithmetic(name: &str, args: &[Number]) -> Option<ExprKind> {
+    let number = match (name, args) {
+        ("+", _) => args.iter().try_fold(Number::Int(0), |a, &b| a.add(b))?,
+        ("*", _) => args.iter().try_fold(Number::Int(1), |a, &b| a.mul(b))?,
+        ("-", [x]) => Number::Int(0).sub(*x)?,
+        ("-", [first, rest @ ..]) => rest.iter().try_fold(*first, |a, &b| a.sub(b))?,
+        // Lowered to Python's true division, which always gives a float
+        ("/", [x]) => Number::Float(1.0).div(*x)?,
+        ("/", [first, rest @ ..]) => rest
+            .iter()
+            .try_fold(Number::Float(first.float()), |a, &b| a.div(b))?,
+        ("mod", [a, b]) => a.modulo(*b)?,
+        ("1+", [x]) => x.add(Number::Int(1))?,
+        ("1-", [x]) => x.sub(Number::Int(1))?,
+        ("zerop", [x]) => return Some(boolean(x.float() == 0.0)),
+        ("<" | ">" | "<=" | ">=" | "=", [_, ..]) => {
+            let holds = args.windows(2).all(|pair| {
+                let (a, b) = (pair[0].float(), pair[1].float());
+                match name {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    ">=" => a >= b,
+                    _ => a == b,
+                }
+            });
+            return Some(boolean(holds));
+        }
+        ("/=", [_, ..]) => {
+            let distinct = args
+                .iter()
+                .enumerate()
+                .all(|(i, a)| args[i + 1..].iter().all(|b| a.float() != b.float()));
+            return Some(boolean(distinct));
+        }
+        _ => return None,
+    };
+    Some(ExprKind::Number(number.literal()?))
+}
+
+/// Truth of a constant condition, which conditions are lowered to test as
+/// Lisp does: only nil is false, not `0` or `""` as in Python.
+fn truth(expr: &Expr) -> Option<bool> {
+    match &expr.kind {
+        ExprKind::Nil | ExprKind::Quote(Datum::Nil) => Some(false),
+        ExprKind::T
+        | ExprKind::Number(_)
+        | ExprKind::Str(_)
+        | ExprKind::Function(_)
+        | ExprKind::Quote(_) => Some(true),
+        _ => None,
+    }
+}
+
+fn boolean(value: bool) -> ExprKind {
+    match value {
+        true => ExprKind::T,
+        false => ExprKind::Nil,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn parse(text: &str) -> Option<Number> {
+        let text = text.strip_prefix('+').unwrap_or(text);
+        // Integers too big for an `i64` are left to Python
+        match text.contains(['.', 'e', 'E']) {
+            true => text.parse::<f64>().ok().map(Number::Float),
+            false => text.parse::<i64>().ok().map(Number::Int),
+        }
+    }
+
+    /// Python literal, unless the value has none.
+    fn literal(self) -> Option<String> {
+        match self {
+            Number::Int(n) => Some(n.to_string()),
+            // `Debug` keeps the `.0` of whole floats, like Python's `repr`
+            Number::Float(x) if x.is_finite() => Some(format!("{x:?}")),
+            Number::Float(_) => None,
+        }
+    }
+
+    fn float(self) -> f64 {
+        match self {
+            Number::Int(n) => n as f64,
+            Number::Float(x) => x,
+        }
+    }
+
+    /// Apply an operation to integers, or to floats if either operand is
+    /// one. Integer overflow is left for Python's big integers.
+    fn apply(
+        self,
+        other: Number,
+        int: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> Option<Number> {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => int(a, b).map(Number::Int),
+            (a, b) => Some(Number::Float(float(a.float(), b.float()))),
+        }
+    }
+
+    fn add(self, other: Number) -> Option<Number> {
+        self.apply(other, i64::checked_add, |a, b| a + b)
+    }
+
+    fn sub(self, other: Number) -> Option<Number> {
+        self.apply(other, i64::checked_sub, |a, b| a - b)
+    }
+
+    fn mul(self, other: Number) -> Option<Number> {
+        self.apply(other, i64::checked_mul, |a, b| a * b)
+    }
+
+    /// Division by zero is left to raise at runtime.
+    fn div(self, other: Number) -> Option<Number> {
+        match other.float() {
+            0.0 => None,
+            divisor => Some(Number::Float(self.float() / divisor)),
+        }
+    }
+
+    /// Python's `%`, whose result has the sign of the divisor like `mod`.
+    fn modulo(self, other: Number) -> Option<Number> {
+        if other.float() == 0.0 {
+            return None;
+        }
+        self.apply(
+            other,
+            |a, b| {
+                a.checked_rem(b).map(|r| {
+                    if r != 0 && (r < 0) != (b < 0) {
+                        r + b
+                    } else {
+                        r
+                    }
+                })
+            },
+            |a, b| {
+                let r = a % b;
+                if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                    r + b
+                } else {
+                    r
+                }
+            },
+        )
+    }
+}
@@ -0,0 +1,419 @@
+//! Compile-time translation of `format` control strings.
+//!
+//! Supported directives are `~a`, `~s`, `~d`, `~%`, `~&`, `~~` and
+//! `~{...~}` iteration (optionally with `~^` before a trailing separator).
+//! Arguments are printed by the same runtime helpers as `cl_format` uses, so
+//! that a control string prints the same whether it is known at compile time
+//! or not. `~&` starting the control string asks the runtime whether the
+//! output is at the start of a line; elsewhere it is emitted as a newline
+//! unless it directly follows one.
+use crate::builtins::{escape, or_empty};
+use lexer::Span;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+#[derive(Debug)]
+pub struct FormatError {
+    pub span: Span,
+    pub kind: FormatErrorKind,
+}
+
+#[derive(Debug)]
+pub enum FormatErrorKind {
+    /// The control string ends in a lone `~`.
+    UnterminatedDirective,
+    UnknownDirective(char),
+    /// `~{` without a matching `~}`.
+    UnclosedIteration,
+    /// `~}` without a matching `~{`.
+    UnmatchedIterationEnd,
+    NestedIteration,
+    /// `~^` outside of an iteration, or followed by more directives.
+    MisplacedEscape,
+    /// An iteration body that never consumes an element would loop forever.
+    EmptyIteration,
+    NotEnoughArgs {
+        expected: usize,
+        got: usize,
+    },
+}
+
+impl Display for FormatErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatErrorKind::UnterminatedDirective => f.write_str("control string ends in `~`"),
+            FormatErrorKind::UnknownDirective(c) => write!(f, "unknown directive `~{c}`"),
+            FormatErrorKind::UnclosedIteration => f.write_str("`~{` without a matching `~}`"),
+            FormatErrorKind::UnmatchedIterationEnd => f.write_str("`~}` without a matching `~{`"),
+            FormatErrorKind::NestedIteration => f.write_str("nested `~{` is not supported"),
+            FormatErrorKind::MisplacedEscape => f.write_str("`~^` must come last in an iteration"),
+            FormatErrorKind::EmptyIteration => f.write_str("iteration body consumes no arguments"),
+            FormatErrorKind::NotEnoughArgs { expected, got } => {
+                write!(f, "control string needs {expected} arguments, got {got}")
+            }
+        }
+    }
+}
+
+/// Where the output of a `format` call goes.
+#[derive(Debug)]
+pub enum Destination {
+    /// `t`
+    Stdout,
+    /// `nil`
+    String,
+    /// Any other form, lowered to the given Python expression.
+    Stream(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Directive {
+    Text(String),
+    /// `~&` starting the control string.
+    FreshLine,
+    /// `~a`
+    Aesthetic,
+    /// `~s`
+    Standard,
+    /// `~d`
+    Decimal,
+    /// `~{body~}`, where `separator` is the text after a `~^`.
+    Iteration {
+        body: Vec<Directive>,
+        separator: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct ControlString {
+    directives: Vec<Directive>,
+    span: Span,
+}
+
+impl ControlString {
+    /// Parse the contents of the string literal at `span`.
+    pub fn parse(content: &str, span: Span) -> Result<Self, FormatError> {
+        let mut parser = DirectiveParser {
+            chars: content.chars().collect(),
+            pos: 0,
+            span,
+        };
+        let (directives, _) = parser.parse(false)?;
+        Ok(Self { directives, span })
+    }
+
+    /// Number of arguments consumed by the control string.
+    pub fn arity(&self) -> usize {
+        self.directives.iter().filter(|d| d.consumes()).count()
+    }
+
+    /// Lower a `format` call to a Python expression, given its already
+    /// lowered arguments, adding the runtime helpers it calls to `prelude`.
+    pub fn lower(
+        &self,
+        dest: Destination,
+        args: &[String],
+        prelude: &mut BTreeSet<&'static str>,
+    ) -> Result<String, FormatError> {
+        if args.len() < self.arity() {
+            return Err(FormatError {
+                span: self.span,
+                kind: FormatErrorKind::NotEnoughArgs {
+                    expected: self.arity(),
+                    got: args.len(),
+                },
+            });
+        }
+
+        // Where `~&` looks for the start of a line, as `cl_format` takes it
+        let fresh_line = match &dest {
+            Destination::Stdout => Some("True"),
+            // A new string starts a line
+            Destination::String => None,
+            Destination::Stream(stream) if is_simple(stream) => Some(stream.as_str()),
+            // Which would be evaluated twice, and is not known to track lines
+            Destination::Stream(_) => None,
+        };
+        let string = self.lower_string(fresh_line, args, prelude);
+        Ok(match dest {
+            Destination::Stdout => format!("print({string}, end=\"\")"),
+            Destination::String => string,
+            Destination::Stream(stream) => format!("{stream}.write({string})"),
+        })
+    }
+
+    fn lower_string(
+        &self,
+        fresh_line: Option<&str>,
+        args: &[String],
+        prelude: &mut BTreeSet<&'static str>,
+    ) -> String {
+        let mut template = String::new();
+        let mut values = vec![];
+        let mut args = args.iter();
+        // Arbitrary expressions cannot be nested inside an f-string before
+        // Python 3.12, so only simple arguments are interpolated directly.
+        let mut interpolate = true;
+        for directive in &self.directives {
+            match directive {
+                Directive::Text(text) => template.push_str(&escape_braces(text)),
+                Directive::FreshLine => {
+                    if let Some(dest) = fresh_line {
+                        prelude.insert("cl_line_break");
+                        template.push_str("{}");
+                        values.push(format!("cl_line_break({dest})"));
+                    }
+                }
+                Directive::Iteration { body, separator } => {
+                    let list = args.next().expect("arity is checked");
+                    template.push_str("{}");
+                    values.push(lower_iteration(body, separator.as_deref(), list, prelude));
+                    interpolate = false;
+                }
+                directive => {
+                    let arg = args.next().expect("arity is checked");
+                    template.push_str("{}");
+                    values.push(directive.print(arg, prelude));
+                    interpolate &= is_simple(arg);
+                }
+            }
+        }
+        // Extra arguments are still evaluated, as in Lisp.
+        let extra = args.cloned().collect::<Vec<_>>();
+        if values.is_empty() && extra.is_empty() {
+            return format!("\"{template}\"");
+        }
+        if interpolate && extra.is_empty() {
+            let mut values = values.into_iter();
+            let fstring = fill_template(&template, || values.next().expect("one per `{}`"));
+            return format!("f\"{fstring}\"");
+        }
+        values.extend(extra);
+        format!("\"{template}\".format({})", values.join(", "))
+    }
+}
+
+impl Directive {
+    fn consumes(&self) -> bool {
+        !matches!(self, Directive::Text(_) | Directive::FreshLine)
+    }
+
+    /// The text `arg`, a lowered argument, is printed as by this directive.
+    fn print(&self, arg: &str, prelude: &mut BTreeSet<&'static str>) -> String {
+        let helper = match self {
+            // Numbers and strings print the same through `str`
+            Directive::Aesthetic if is_number(arg) || is_string(arg) => return arg.to_string(),
+            Directive::Standard if is_number(arg) => return arg.to_string(),
+            Directive::Aesthetic => "cl_princ_to_string",
+            Directive::Standard => "cl_write_to_string",
+            _ => return arg.to_string(),
+        };
+        prelude.insert(helper);
+        format!("{helper}({arg})")
+    }
+}
+
+/// `text` in a `str.format` template.
+fn escape_braces(text: &str) -> String {
+    escape(text).replace('{', "{{").replace('}', "}}")
+}
+
+/// Replace each placeholder of `template` with `{expr}` for f-strings.
+fn fill_template(template: &str, mut next: impl FnMut() -> String) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(idx) = rest.find(['{', '}']) {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        // Each of `{{`, `}}` and `{}` is two characters long
+        match rest.starts_with("{{") || rest.starts_with("}}") {
+            true => out.push_str(&rest[..2]),
+            false => out.push_str(&format!("{{{}}}", next())),
+        }
+        rest = &rest[2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lower_iteration(
+    body: &[Directive],
+    separator: Option<&str>,
+    list: &str,
+    prelude: &mut BTreeSet<&'static str>,
+) -> String {
+    let used = body.iter().filter(|d| d.consumes()).count();
+    let elems = match used {
+        1 => vec!["_x".to_string()],
+        _ => (0..used).map(|i| format!("_xs[{i}]")).collect(),
+    };
+    let mut template = String::new();
+    let mut values = vec![];
+    let mut elems = elems.iter();
+    for directive in body {
+        match directive {
+            Directive::Text(text) => template.push_str(&escape_braces(text)),
+            directive => {
+                template.push_str("{}");
+                values.push(directive.print(elems.next().expect("one per element"), prelude));
+            }
+        }
+    }
+    let joiner = format!("\"{}\"", escape(separator.unwrap_or_default()));
+    let list = or_empty(list);
+    let item = match template.as_str() {
+        "{}" => values.remove(0),
+        _ => format!("\"{template}\".format({})", values.join(", ")),
+    };
+    match used {
+        1 => format!("{joiner}.join({item} for _x in {list})"),
+        _ => format!("{joiner}.join({item} for _xs in zip(*[iter({list})] * {used}))"),
+    }
+}
+
+fn is_simple(arg: &str) -> bool {
+    !arg.is_empty() && arg.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn is_number(arg: &str) -> bool {
+    arg.parse::<f64>().is_ok() && !arg.contains(char::is_alphabetic)
+}
+
+fn is_string(arg: &str) -> bool {
+    arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"')
+}
+
+struct DirectiveParser {
+    chars: Vec<char>,
+    pos: usize,
+    span: Span,
+}
+
+impl DirectiveParser {
+    /// Parse directives up to the end of the string, or up to the closing
+    /// `~}` when inside an iteration. The second element is the separator
+    /// following a `~^`.
+    fn parse(
+        &mut self,
+        in_iteration: bool,
+    ) -> Result<(Vec<Directive>, Option<String>), FormatError> {
+        let mut directives = vec![];
+        let mut text = String::new();
+        let open = self.pos.saturating_sub(2);
+
+        while let Some(c) = self.next() {
+            match c {
+                '~' => {}
+                c => {
+                    text.push(c);
+                    continue;
+                }
+            }
+
+            let directive_start = self.pos - 1;
+            let Some(d) = self.next() else {
+                return Err(self.error(directive_start, FormatErrorKind::UnterminatedDirective));
+            };
+            match d.to_ascii_lowercase() {
+                '~' => text.push('~'),
+                '%' => text.push('\n'),
+                '&' if text.is_empty() && directives.is_empty() && !in_iteration => {
+                    directives.push(Directive::FreshLine)
+                }
+                '&' => {
+                    let at_line_start = match text.is_empty() {
+                        true => directives.is_empty(),
+                        false => text.ends_with('\n'),
+                    };
+                    if !at_line_start {
+                        text.push('\n');
+                    }
+                }
+                '}' if in_iteration => {
+                    flush(&mut directives, &mut text);
+                    return Ok((directives, None));
+                }
+                '}' => {
+                    return Err(self.error(directive_start, FormatErrorKind::UnmatchedIterationEnd))
+                }
+                '^' if in_iteration => {
+                    flush(&mut directives, &mut text);
+                    let separator = self.parse_separator(directive_start)?;
+                    return Ok((directives, Some(separator)));
+                }
+                '^' => return Err(self.error(directive_start, FormatErrorKind::MisplacedEscape)),
+                '{' if in_iteration => {
+                    return Err(self.error(directive_start, FormatErrorKind::NestedIteration))
+                }
+                '{' => {
+                    flush(&mut directives, &mut text);
+                    let (body, separator) = self.parse(true)?;
+                    if !body.iter().any(Directive::consumes) {
+                        return Err(self.error(directive_start, FormatErrorKind::EmptyIteration));
+                    }
+                    directives.push(Directive::Iteration { body, separator });
+                }
+                'a' => push(&mut directives, &mut text, Directive::Aesthetic),
+                's' => push(&mut directives, &mut text, Directive::Standard),
+                'd' => push(&mut directives, &mut text, Directive::Decimal),
+                _ => return Err(self.error(directive_start, FormatErrorKind::UnknownDirective(d))),
+            }
+        }
+
+        if in_iteration {
+            return Err(self.error(open, FormatErrorKind::UnclosedIteration));
+        }
+        flush(&mut directives, &mut text);
+        Ok((directives, None))
+    }
+
+    /// Read the text between `~^` and `~}`.
+    fn parse_separator(&mut self, escape_start: usize) -> Result<String, FormatError> {
+        let mut separator = String::new();
+        loop {
+            match self.next() {
+                Some('~') => match self.next().map(|c| c.to_ascii_lowercase()) {
+                    Some('}') => return Ok(separator),
+                    Some('%') => separator.push('\n'),
+                    Some('~') => separator.push('~'),
+                    _ => return Err(self.error(escape_start, FormatErrorKind::MisplacedEscape)),
+                },
+                Some(c) => separator.push(c),
+                None => return Err(self.error(escape_start, FormatErrorKind::UnclosedIteration)),
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    /// Error spanning the two character directive starting at `pos`.
+    fn error(&self, pos: usize, kind: FormatErrorKind) -> FormatError {
+        let before = &self.chars[..pos];
+        let newlines = before.iter().filter(|c| **c == '\n').count();
+        let row = self.span.start_row + newlines;
+        // Column of the opening quote, plus one for the quote itself.
+        let col = match before.iter().rposition(|c| *c == '\n') {
+            Some(idx) => pos - idx,
+            None => self.span.start_col + 1 + pos,
+        };
+        FormatError {
+            span: Span::new(row, row, col, col + 1),
+            kind,
+        }
+    }
+}
+
+fn push(directives: &mut Vec<Directive>, text: &mut String, directive: Directive) {
+    flush(directives, text);
+    directives.push(directive);
+}
+
+fn flush(directives: &mut Vec<Directive>, text: &mut String) {
+    if !text.is_empty() {
+        directives.push(Directive::Text(std::mem::take(text)));
+    }
+}
@@ -0,0 +1,97 @@
+//! Classes of the tokens of a source, for highlighting it.
+//!
+//! Tokens are classed by the lexer, and symbols further by what the resolver
+//! found them to name, so that a parameter reads differently from the
+//! function it is passed to. Sources that cannot be read are classed by the
+//! lexer alone.
+use crate::expr::SPECIAL_FORMS;
+use crate::resolve::{BindingKind, Resolved};
+use crate::Pythonify;
+use lexer::{Cursor, Keyword, TokenKind};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Whitespace, and punctuation such as the `.` of a dotted pair.
+    Plain,
+    /// Head of a special form, or a lambda list keyword such as `&optional`.
+    Keyword,
+    /// `nil`, `t` and keywords such as `:key`.
+    Constant,
+    /// Function defined by the program, where named or called.
+    Function,
+    Builtin,
+    /// Global or `let` variable.
+    Variable,
+    Parameter,
+    /// Symbol the resolver knows nothing of, such as quoted data.
+    Symbol,
+    Number,
+    String,
+    Comment,
+    /// `'` or `` ` ``.
+    Quote,
+    /// Parenthesis, by the number of lists around its own.
+    Paren(usize),
+}
+
+/// Tokens of `src` with their classes, covering it in order.
+pub fn highlight(src: &str) -> Vec<(Range<usize>, Class)> {
+    let analysis = Pythonify::new(src).analyse().ok();
+    let resolved = |span| {
+        let resolution = &analysis.as_ref()?.1.resolution;
+        Some(match resolution.references.get(&span)? {
+            Resolved::Builtin(_) => Class::Builtin,
+            Resolved::Binding(id) => match resolution.binding(*id).kind {
+                BindingKind::Function => Class::Function,
+                BindingKind::Param => Class::Parameter,
+                BindingKind::Let | BindingKind::Global => Class::Variable,
+            },
+        })
+    };
+    let mut cursor = Cursor::new(src);
+    let mut out = vec![];
+    let mut offset = 0;
+    let mut depth: usize = 0;
+    // Whether the last token other than whitespace opened a list
+    let mut head = false;
+    loop {
+        let token = cursor.next_token();
+        let text = &src[offset..offset + token.len];
+        let class = match token.kind {
+            TokenKind::EOF => break,
+            TokenKind::Whitespace | TokenKind::Dot | TokenKind::Comma => Class::Plain,
+            TokenKind::LineComment => Class::Comment,
+            TokenKind::Backquote => Class::Quote,
+            TokenKind::Literal if text.starts_with('"') => Class::String,
+            TokenKind::Literal => Class::Number,
+            TokenKind::OpenParen => {
+                depth += 1;
+                Class::Paren(depth - 1)
+            }
+            TokenKind::CloseParen => {
+                depth = depth.saturating_sub(1);
+                Class::Paren(depth)
+            }
+            _ => resolved(token.span).unwrap_or_else(|| symbol(token.kind, text, head)),
+        };
+        if token.kind != TokenKind::Whitespace {
+            head = token.kind == TokenKind::OpenParen;
+        }
+        out.push((offset..offset + token.len, class));
+        offset += token.len;
+    }
+    out
+}
+
+/// Class of the unresolved symbol `text`, which heads a list if `head`.
+fn symbol(kind: TokenKind, text: &str, head: bool) -> Class {
+    match kind {
+        TokenKind::Keyword(Keyword::nil) => Class::Constant,
+        TokenKind::Keyword(_) => Class::Keyword,
+        _ if head && SPECIAL_FORMS.contains(&text) => Class::Keyword,
+        _ if text.starts_with('&') => Class::Keyword,
+        _ if text == "t" || text.starts_with(':') || text.starts_with("#:") => Class::Constant,
+        _ => Class::Symbol,
+    }
+}
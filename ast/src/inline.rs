@@ -0,0 +1,362 @@
+//! Inlining of small `defun`s, run at `-O1` before constant folding.
+//!
+//! A call to a function whose body has at most `inline_threshold` forms, or
+//! that is declaimed `inline`, becomes a `let` binding its arguments to the
+//! parameters, so they are still evaluated once and in order. Literals and
+//! local variables are substituted for parameters instead when that cannot
+//! change what they refer to or the value read. When `checked`, arguments
+//! for parameters the callee declares a type of are bound as `(the type
+//! arg)`, so they are still checked.
+//!
+//! Recursive functions, functions with `&optional`, `&rest` or `&key`
+//! parameters or using `return-from`, and functions declaimed `notinline`
+//! are never inlined. Nor are calls outside a function body, where the
+//! parameters bound would be module globals.
+use crate::expr::{Defun, Expr, ExprKind, Ident};
+use crate::resolve::{BindingId, BindingKind, Resolution, Resolved};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+pub fn inline(exprs: &mut [Expr], resolution: &Resolution, threshold: usize, checked: bool) {
+    let mut inline = HashSet::new();
+    let mut notinline = HashSet::new();
+    let mut defuns = HashMap::new();
+    for expr in exprs.iter() {
+        match &expr.kind {
+            ExprKind::Declaim {
+                inline: on,
+                notinline: off,
+            } => {
+                inline.extend(on.iter().map(|func| func.name.clone()));
+                notinline.extend(off.iter().map(|func| func.name.clone()));
+            }
+            ExprKind::Defun(defun) => {
+                defuns.insert(defun.name.name.clone(), defun.clone());
+            }
+            _ => {}
+        }
+    }
+
+    let graph = defuns
+        .iter()
+        .map(|(name, defun)| (name.as_str(), callees(defun, &defuns)))
+        .collect::<HashMap<_, _>>();
+    let candidates = defuns
+        .iter()
+        .filter(|(name, defun)| {
+            let small = inline.contains(*name) || size(&defun.body) <= threshold;
+            small
+                && !notinline.contains(*name)
+                && defun.params.is_simple()
+                && !is_recursive(name, &graph)
+                && defun.body.iter().all(|expr| !exits_or_defines(expr))
+        })
+        .map(|(name, defun)| (name.clone(), defun))
+        .collect::<HashMap<_, _>>();
+    if candidates.is_empty() {
+        return;
+    }
+
+    // Parameters bound around an inlined body could capture the globals of
+    // functions inlined into it
+    let params = candidates
+        .values()
+        .flat_map(|defun| defun.params.names().map(|param| param.name.clone()))
+        .collect::<HashSet<_>>();
+    let inliner = Inliner {
+        resolution,
+        candidates,
+        checked,
+    };
+    for expr in exprs {
+        let mut locals = bound_names(expr);
+        locals.extend(params.iter().cloned());
+        inliner.expr(expr, &locals, false);
+    }
+}
+
+struct Inliner<'a> {
+    resolution: &'a Resolution,
+    candidates: HashMap<String, &'a Defun>,
+    checked: bool,
+}
+
+impl Inliner<'_> {
+    /// Inline calls in `expr`, whose enclosing top-level form binds `locals`,
+    /// if it is `in_function`.
+    fn expr(&self, expr: &mut Expr, locals: &HashSet<String>, in_function: bool) {
+        let body = in_function || matches!(expr.kind, ExprKind::Defun(_) | ExprKind::Lambda { .. });
+        for child in expr.children_mut() {
+            self.expr(child, locals, body);
+        }
+        if !in_function {
+            return;
+        }
+        let ExprKind::Call { func, args } = &mut expr.kind else {
+            return;
+        };
+        let Some(defun) = self.candidates.get(&func.name) else {
+            return;
+        };
+        if args.len() != defun.params.required.len()
+            || self
+                .globals(defun)
+                .iter()
+                .any(|global| locals.contains(*global))
+        {
+            return;
+        }
+        let mut args = std::mem::take(args);
+        if self.checked {
+            declare(defun, &mut args);
+        }
+        expr.kind = self.expand(defun, args);
+        self.expr(expr, locals, in_function);
+    }
+
+    /// Body of `defun` with `args` bound to its parameters.
+    fn expand(&self, defun: &Defun, args: Vec<Expr>) -> ExprKind {
+        let params = &defun.params.required;
+        let bound = bound_names_in(&defun.body);
+        let mut substitute = params
+            .iter()
+            .zip(&args)
+            .enumerate()
+            .map(|(i, (param, arg))| {
+                let assigned = self.is_assigned(&defun.body, self.binding(param));
+                let later_pure = args[i + 1..].iter().all(|arg| self.is_trivial(arg));
+                match &arg.kind {
+                    // A later argument could assign it, and bindings in the
+                    // body could capture it
+                    ExprKind::Symbol(name) if self.is_local(arg) => {
+                        !assigned && later_pure && !bound.contains(name)
+                    }
+                    _ => !assigned && is_literal(arg),
+                }
+            })
+            .collect::<Vec<_>>();
+        // Nor can a variable be captured by the parameters still bound
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..params.len() {
+                let ExprKind::Symbol(ref name) = args[i].kind else {
+                    continue;
+                };
+                let captured = params
+                    .iter()
+                    .zip(&substitute)
+                    .any(|(param, substituted)| !substituted && param.name == *name);
+                if substitute[i] && captured {
+                    substitute[i] = false;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut substitutions = HashMap::new();
+        let mut bindings = vec![];
+        for ((param, arg), substitute) in params.iter().zip(args).zip(substitute) {
+            match (substitute, self.binding(param)) {
+                (true, Some(id)) => {
+                    substitutions.insert(id, arg);
+                }
+                _ => bindings.push((param.clone(), arg)),
+            }
+        }
+
+        let mut body = defun.body.clone();
+        for expr in &mut body {
+            self.substitute(expr, &substitutions);
+        }
+        match bindings.is_empty() {
+            true => ExprKind::Progn(body),
+            false => ExprKind::Let {
+                bindings,
+                body,
+                sequential: false,
+            },
+        }
+    }
+
+    fn substitute(&self, expr: &mut Expr, substitutions: &HashMap<BindingId, Expr>) {
+        if let ExprKind::Symbol(_) = expr.kind {
+            if let Some(Resolved::Binding(id)) = self.resolution.get(expr.span) {
+                if let Some(arg) = substitutions.get(&id) {
+                    *expr = arg.clone();
+                }
+            }
+            return;
+        }
+        for child in expr.children_mut() {
+            self.substitute(child, substitutions);
+        }
+    }
+
+    /// Names of the globals `defun` reads or assigns.
+    fn globals<'d>(&self, defun: &'d Defun) -> BTreeSet<&'d str> {
+        let mut globals = BTreeSet::new();
+        for expr in &defun.body {
+            expr.walk(&mut |expr| {
+                let names = match &expr.kind {
+                    ExprKind::Symbol(name) => vec![(name.as_str(), expr.span)],
+                    ExprKind::Setq(pairs) => pairs
+                        .iter()
+                        .map(|(name, _)| (name.name.as_str(), name.span))
+                        .collect(),
+                    _ => vec![],
+                };
+                for (name, span) in names {
+                    if let Some(Resolved::Binding(id)) = self.resolution.get(span) {
+                        if self.resolution.binding(id).kind == BindingKind::Global {
+                            globals.insert(name);
+                        }
+                    }
+                }
+            });
+        }
+        globals
+    }
+
+    fn is_assigned(&self, body: &[Expr], id: Option<BindingId>) -> bool {
+        let mut assigned = false;
+        for expr in body {
+            expr.walk(&mut |expr| {
+                if let ExprKind::Setq(pairs) = &expr.kind {
+                    assigned |= pairs.iter().any(|(name, _)| self.binding(name) == id);
+                }
+            });
+        }
+        assigned
+    }
+
+    /// Whether evaluating `arg` has no effects and reads nothing that
+    /// another argument could change.
+    fn is_trivial(&self, arg: &Expr) -> bool {
+        is_literal(arg) || self.is_local(arg)
+    }
+
+    fn is_local(&self, expr: &Expr) -> bool {
+        match (&expr.kind, self.resolution.get(expr.span)) {
+            (ExprKind::Symbol(_), Some(Resolved::Binding(id))) => matches!(
+                self.resolution.binding(id).kind,
+                BindingKind::Param | BindingKind::Let
+            ),
+            _ => false,
+        }
+    }
+
+    fn binding(&self, ident: &Ident) -> Option<BindingId> {
+        match self.resolution.get(ident.span)? {
+            Resolved::Binding(id) => Some(id),
+            Resolved::Builtin(_) => None,
+        }
+    }
+}
+
+/// Wrap the arguments for the parameters `defun` declares the types of in
+/// `the`, checking them as the parameters would be.
+fn declare(defun: &Defun, args: &mut [Expr]) {
+    for (param, arg) in defun.params.required.iter().zip(args) {
+        let declared = defun
+            .declarations
+            .iter()
+            .find(|(name, _)| name.name == param.name);
+        if let Some((_, ty)) = declared {
+            let span = arg.span;
+            let nil = Expr {
+                kind: ExprKind::Nil,
+                span,
+            };
+            let value = Box::new(std::mem::replace(arg, nil));
+            *arg = Expr {
+                kind: ExprKind::The {
+                    ty: ty.clone(),
+                    value,
+                },
+                span,
+            };
+        }
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Nil
+        | ExprKind::T
+        | ExprKind::Number(_)
+        | ExprKind::Str(_)
+        | ExprKind::Quote(_)
+        | ExprKind::Function(_) => true,
+        ExprKind::Symbol(name) => name.starts_with(':'),
+        _ => false,
+    }
+}
+
+/// Number of forms in `body`, counting nested ones.
+fn size(body: &[Expr]) -> usize {
+    let mut size = 0;
+    for expr in body {
+        expr.walk(&mut |_| size += 1);
+    }
+    size
+}
+
+/// Whether `expr` leaves its function with `return-from`, or defines a
+/// function, either of which would mean something else once inlined.
+fn exits_or_defines(expr: &Expr) -> bool {
+    let mut found = false;
+    expr.walk(&mut |expr| {
+        found |= matches!(expr.kind, ExprKind::ReturnFrom { .. } | ExprKind::Defun(_));
+    });
+    found
+}
+
+fn callees<'d>(defun: &'d Defun, defuns: &HashMap<String, Defun>) -> BTreeSet<&'d str> {
+    let mut callees = BTreeSet::new();
+    for expr in &defun.body {
+        expr.walk(&mut |expr| match &expr.kind {
+            ExprKind::Call { func, .. } | ExprKind::Function(func)
+                if defuns.contains_key(&func.name) =>
+            {
+                callees.insert(func.name.as_str());
+            }
+            _ => {}
+        });
+    }
+    callees
+}
+
+fn is_recursive(name: &str, graph: &HashMap<&str, BTreeSet<&str>>) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = graph[name].iter().copied().collect::<Vec<_>>();
+    while let Some(callee) = stack.pop() {
+        if callee == name {
+            return true;
+        }
+        if seen.insert(callee) {
+            stack.extend(graph.get(callee).into_iter().flatten().copied());
+        }
+    }
+    false
+}
+
+/// Names of the parameters and `let` bindings in `expr`.
+fn bound_names(expr: &Expr) -> HashSet<String> {
+    bound_names_in(std::slice::from_ref(expr))
+}
+
+fn bound_names_in(exprs: &[Expr]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for expr in exprs {
+        expr.walk(&mut |expr| match &expr.kind {
+            ExprKind::Defun(Defun { params, .. }) | ExprKind::Lambda { params, .. } => {
+                names.extend(params.names().map(|param| param.name.clone()));
+            }
+            ExprKind::Let { bindings, .. } => {
+                names.extend(bindings.iter().map(|(name, _)| name.name.clone()));
+            }
+            _ => {}
+        });
+    }
+    names
+}
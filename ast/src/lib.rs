@@ -1,13 +1,49 @@
 #![allow(dead_code)]
-use parser::{AtomKind, ParseError, SExpr, StringReader, Token, TokenKind};
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::{io, path::Path};
+use builtins::{RUNTIME, RUNTIME_MODULE};
+use diagnostic::Diagnostic;
+use emit::Stage;
+use expr::{Expr, ExprKind, Package, SyntaxError, SyntaxErrorKind};
+use format::FormatError;
+use lexer::Span;
+use lower::Lower;
+use parser::{ParseError, StringReader, TokenKind};
+use reach::Reachability;
+use resolve::{Import, Resolution};
+use sourcemap::SourceMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use types::Types;
+
+pub mod arity;
+pub mod builtins;
+pub mod diagnostic;
+pub mod emit;
+pub mod expr;
+pub mod fold;
+pub mod format;
+pub mod highlight;
+pub mod inline;
+pub mod json;
+pub mod lower;
+pub mod package;
+pub mod project;
+pub mod reach;
+pub mod resolve;
+pub mod sourcemap;
+pub mod tco;
+pub mod types;
 
 #[derive(Debug)]
 pub enum TranspileError {
     ParseError(ParseError),
-    IoError(io::Error),
+    SyntaxError(SyntaxError),
+    FormatError(FormatError),
+    /// Errors found by the analysis passes, along with any warnings.
+    Diagnostics(Vec<Diagnostic>),
+    /// Writing the file at the path failed.
+    IoError(PathBuf, io::Error),
 }
 
 impl From<ParseError> for TranspileError {
@@ -16,108 +52,438 @@ impl From<ParseError> for TranspileError {
     }
 }
 
-impl From<io::Error> for TranspileError {
-    fn from(value: io::Error) -> Self {
-        Self::IoError(value)
+impl From<SyntaxError> for TranspileError {
+    fn from(value: SyntaxError) -> Self {
+        Self::SyntaxError(value)
+    }
+}
+
+impl From<FormatError> for TranspileError {
+    fn from(value: FormatError) -> Self {
+        Self::FormatError(value)
+    }
+}
+
+impl TranspileError {
+    /// The errors found in the source as diagnostics, or the error itself if
+    /// it is not about the source.
+    pub fn into_diagnostics(self) -> Result<Vec<Diagnostic>, TranspileError> {
+        let diagnostic = match self {
+            TranspileError::ParseError(err) => Diagnostic::error(err.span(), err.to_string()),
+            TranspileError::SyntaxError(SyntaxError { span, kind }) => {
+                let message = match kind {
+                    SyntaxErrorKind::Malformed { form, expected } => {
+                        format!("malformed `{form}`: expected {expected}")
+                    }
+                    SyntaxErrorKind::DottedForm => {
+                        "dotted lists are only allowed in quoted data".to_owned()
+                    }
+                };
+                Diagnostic::error(span, message)
+            }
+            TranspileError::FormatError(FormatError { span, kind }) => {
+                Diagnostic::error(span, format!("invalid `format` control string: {kind}"))
+            }
+            TranspileError::Diagnostics(diagnostics) => return Ok(diagnostics),
+            err @ TranspileError::IoError(..) => return Err(err),
+        };
+        Ok(vec![diagnostic])
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Prelude {
+    /// Paste the runtime at the top of the generated module.
+    #[default]
+    Inline,
+    /// Write the runtime next to the generated module and import from it.
+    File,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub prelude: Prelude,
+    /// Run functions that tail call each other through the runtime's
+    /// trampoline, so mutual recursion does not grow the Python stack.
+    pub trampoline: bool,
+    /// Annotate generated `def`s and variables with the inferred types.
+    pub type_hints: bool,
+    /// Assert the types given by `declare` and `the` at runtime.
+    pub checked: bool,
+    /// `0` lowers the program as written, `1` inlines small functions,
+    /// folds constants and removes dead code first.
+    pub opt_level: u8,
+    /// Largest number of forms in the body of a function inlined at `-O1`.
+    pub inline_threshold: usize,
+    /// Function to run when the module is run as a script.
+    pub entry: Option<String>,
+    /// Keep every top-level function, as other modules may import them,
+    /// rather than removing those the module never calls.
+    pub library: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            prelude: Prelude::default(),
+            trampoline: false,
+            type_hints: false,
+            checked: false,
+            opt_level: 1,
+            inline_threshold: 8,
+            entry: None,
+            library: false,
+        }
     }
 }
 
+/// A transpiled module.
+#[derive(Debug)]
+pub struct Output {
+    pub python: String,
+    /// Whether the module imports the runtime, which must then be written
+    /// next to it.
+    pub imports_runtime: bool,
+    pub warnings: Vec<Diagnostic>,
+    /// Span of the form each line of `python` was lowered from, or the
+    /// default span for lines lowered from no form.
+    pub spans: Vec<Span>,
+    /// Definitions of other modules the module imports.
+    pub imports: Vec<Import>,
+}
+
+/// What a source defines for the other modules of its package.
+#[derive(Debug)]
+pub struct Definitions {
+    /// Package named by its `in-package`.
+    pub package: Option<String>,
+    /// Packages defined by its `defpackage`s.
+    pub packages: Vec<Package>,
+    /// Top-level `defun`s and `defvar`s, with the spans of their names.
+    pub names: Vec<(Import, Span)>,
+}
+
 pub struct Pythonify<'a> {
     src: &'a str,
+    /// Path of the source, recorded in source maps.
+    path: Option<&'a str>,
     parser: StringReader<'a>,
+    options: Options,
+    /// Dotted name of the module within its package, which imports
+    /// relative to it.
+    module: Option<&'a str>,
+    /// Definitions of the other modules of the package.
+    imports: &'a [Import],
+    /// Packages defined by the other modules.
+    packages: &'a [Package],
 }
 
 impl<'a> Pythonify<'a> {
     pub fn new(src: &'a str) -> Pythonify<'a> {
+        Self::with_options(src, Options::default())
+    }
+
+    pub fn with_options(src: &'a str, options: Options) -> Pythonify<'a> {
         Self {
             src,
+            path: None,
             parser: StringReader::new(src),
+            options,
+            module: None,
+            imports: &[],
+            packages: &[],
         }
     }
 
-    pub fn output(mut self, path: impl AsRef<Path>) -> Result<(), TranspileError> {
-        let mut file = BufWriter::new(File::create(path)?);
+    /// Name the file the source was read from.
+    pub fn with_path(mut self, path: &'a str) -> Pythonify<'a> {
+        self.path = Some(path);
+        self
+    }
 
-        let token = self.parser.next_token()?;
-        let string = self.transpile(token, String::new())?;
-        println!("{string}");
-        file.write_all(string.as_bytes())?;
-        file.flush()?;
-        Ok(())
+    /// Transpile to `module` of a package, to which the definitions of the
+    /// package's other modules are visible.
+    pub fn with_imports(mut self, module: &'a str, imports: &'a [Import]) -> Pythonify<'a> {
+        self.module = Some(module);
+        self.imports = imports;
+        self
     }
 
-    fn transpile(&mut self, token: Token, string: String) -> Result<String, TranspileError> {
-        self.parser.dbg_token(&token);
-        match token.kind {
-            TokenKind::SExpr(ref _s) => {
-                let sexpr = self.transpile_sexpr(&token, string)?;
-                let token = self.parser.next_token()?;
-                self.transpile(token, sexpr)
-            }
-            TokenKind::EOF => Ok(string),
-            _ => todo!(),
+    /// Make the packages defined by the other modules known.
+    pub fn with_packages(mut self, packages: &'a [Package]) -> Pythonify<'a> {
+        self.packages = packages;
+        self
+    }
+
+    /// Packages and top-level definitions of the source, as imported from
+    /// the module `module`.
+    pub fn definitions(mut self, module: &str) -> Result<Definitions, TranspileError> {
+        let exprs = self.read()?;
+        let mut definitions = Definitions {
+            package: package::current(&exprs).map(str::to_owned),
+            packages: package::defined(&exprs).cloned().collect(),
+            names: vec![],
+        };
+        for expr in exprs {
+            let (name, params) = match expr.kind {
+                ExprKind::Defun(defun) => (defun.name, Some(defun.params)),
+                ExprKind::Defvar { name, .. } => (name, None),
+                _ => continue,
+            };
+            let import = Import {
+                name: name.name,
+                alias: None,
+                module: module.to_owned(),
+                params,
+            };
+            definitions.names.push((import, name.span));
         }
+        Ok(definitions)
     }
 
-    fn transpile_sexpr(&mut self, token: &Token, string: String) -> Result<String, TranspileError> {
-        let sexpr = match token.kind {
-            TokenKind::SExpr(ref s) => s,
-            _ => return self.transpose_non_cons(token, string),
+    /// Transpile to the Python module at `path`, along with its source map
+    /// at `path.map`, returning the warnings found along the way.
+    pub fn output(self, path: impl AsRef<Path>) -> Result<Vec<Diagnostic>, TranspileError> {
+        let path = path.as_ref();
+        let source = match self.path {
+            Some(source) => source.to_owned(),
+            None => path.with_extension("lisu").to_string_lossy().into_owned(),
         };
-        match sexpr {
-            SExpr::Cons { car, cdr } => match car.kind {
-                TokenKind::SExpr(_) => {
-                    let func = self.transpile_sexpr(car, string)?;
-                    let args = {
-                        let mut args_vec = vec![];
-                        for token in cdr {
-                            args_vec.push(self.transpile_sexpr(token, String::new())?)
-                        }
-                        args_vec.join(" ").trim().to_owned()
-                    };
-                    Ok(format!("{func}({args})"))
-                }
-                TokenKind::Atom(AtomKind::Literal(ref literal)) => {
-                    Ok(format!("{string}\n{}", literal.as_str(self.src)))
-                }
-                TokenKind::Atom(AtomKind::Symbol(ref symbol, _)) => {
-                    let args = {
-                        let mut args_vec = vec![];
-                        for token in cdr {
-                            //self.parser.dbg_token(token);
-                            args_vec.push(self.transpile_sexpr(token, String::new())?)
-                        }
-                        args_vec.join(" ").trim().to_owned()
-                    };
-                    Ok(format!(
-                        "{string}\n{func}({args})",
-                        func = symbol.as_str(self.src),
-                    ))
+        let output = self.transpile()?;
+        let write = |path: &Path, contents: &str| {
+            fs::write(path, contents).map_err(|err| TranspileError::IoError(path.to_owned(), err))
+        };
+        if output.imports_runtime {
+            write(
+                &path.with_file_name(RUNTIME_MODULE).with_extension("py"),
+                RUNTIME,
+            )?;
+        }
+        write(path, &output.python)?;
+        let map = SourceMap {
+            source,
+            lines: output.spans,
+        };
+        let mut map_path = path.as_os_str().to_owned();
+        map_path.push(".map");
+        write(Path::new(&map_path), &map.to_json())?;
+        Ok(output.warnings)
+    }
+
+    /// Run the analyses and lowering without writing anything, returning
+    /// the warnings. Errors come with the warnings found before them.
+    pub fn check(mut self) -> Result<Vec<Diagnostic>, TranspileError> {
+        let exprs = self.read()?;
+        let mut analysis = analyse(&exprs, &self.options, self.imports);
+        if analysis.diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(TranspileError::Diagnostics(analysis.diagnostics));
+        }
+        // Malformed `format` control strings are only found when lowering
+        let lowered = Lower::new(&self.options, &analysis).program(&exprs);
+        match lowered {
+            Ok(_) => Ok(analysis.diagnostics),
+            Err(err) => {
+                analysis.diagnostics.extend(err.into_diagnostics()?);
+                Err(TranspileError::Diagnostics(analysis.diagnostics))
+            }
+        }
+    }
+
+    /// Read the source and run the analyses, for tools working on the
+    /// program rather than its output.
+    pub fn analyse(mut self) -> Result<(Vec<Expr>, Analysis), TranspileError> {
+        let exprs = self.read()?;
+        let analysis = analyse(&exprs, &self.options, self.imports);
+        Ok((exprs, analysis))
+    }
+
+    /// Dump of the source at `stage` of the pipeline.
+    pub fn emit(mut self, stage: Stage) -> Result<String, TranspileError> {
+        match stage {
+            Stage::Tokens => Ok(emit::tokens(self.src)),
+            Stage::Sexpr => {
+                let mut out = String::new();
+                for token in self.read_tokens()? {
+                    out.push_str(&emit::sexpr(&token, self.src));
+                    out.push('\n');
                 }
-                TokenKind::Nil => Ok(string),
-                TokenKind::ListNil => Ok(format!("{string}()")),
-                TokenKind::EOF => Ok(string),
-            },
-            SExpr::Nil => Ok(string),
-        }
-    }
-
-    fn transpose_non_cons(
-        &mut self,
-        token: &Token,
-        string: String,
-    ) -> Result<String, TranspileError> {
-        match token.kind {
-            TokenKind::Atom(AtomKind::Literal(ref literal)) => {
-                Ok(literal.as_str(self.src).to_string())
+                Ok(out)
             }
-            TokenKind::Atom(AtomKind::Symbol(ref symbol, _)) => {
-                Ok(symbol.as_str(self.src).to_string())
+            Stage::Ast => Ok(emit::ast(&self.read()?)),
+            Stage::Py => Ok(self.transpile()?.python),
+        }
+    }
+
+    /// Transpile to Python source.
+    pub fn transpile(mut self) -> Result<Output, TranspileError> {
+        let mut exprs = self.read()?;
+
+        let analysis = analyse(&exprs, &self.options, self.imports);
+        if analysis.diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(TranspileError::Diagnostics(analysis.diagnostics));
+        }
+
+        if self.options.opt_level >= 1 {
+            inline::inline(
+                &mut exprs,
+                &analysis.resolution,
+                self.options.inline_threshold,
+                self.options.checked,
+            );
+            fold::fold(&mut exprs, &analysis.resolution);
+            // Functions may no longer be called once inlined
+            let entry = self.options.entry.as_deref();
+            let (reachability, _) =
+                reach::analyse(&exprs, &analysis.resolution, entry, self.options.library);
+            reach::eliminate(&mut exprs, &reachability);
+        }
+
+        let mut lower = Lower::new(&self.options, &analysis);
+        let (body, body_spans) = lower.program(&exprs)?;
+        let mut python = self.prelude(&lower.prelude_used);
+        if !lower.typing_used.is_empty() {
+            let names = lower.typing_used.iter().copied().collect::<Vec<_>>();
+            python.push_str(&format!("from typing import {}\n", names.join(", ")));
+        }
+        let imports = &analysis.resolution.imports;
+        let mut modules = BTreeMap::<&str, BTreeSet<String>>::new();
+        for import in imports {
+            let names = modules.entry(&import.module).or_default();
+            let (name, local) = (
+                builtins::mangle(&import.name),
+                builtins::mangle(import.local()),
+            );
+            match name == local {
+                true => names.insert(name),
+                false => names.insert(format!("{name} as {local}")),
+            };
+        }
+        for (module, names) in modules {
+            let names = names.into_iter().collect::<Vec<_>>();
+            let module = self.relative(module);
+            python.push_str(&format!("from {module} import {}\n", names.join(", ")));
+        }
+        let exports = self.exports(&exprs);
+        if !exports.is_empty() {
+            python.push_str(&format!("__all__ = [{}]\n", exports.join(", ")));
+        }
+        let mut spans = vec![Span::default(); python.lines().count()];
+        spans.extend(body_spans);
+        python.push_str(&body);
+        Ok(Output {
+            python,
+            imports_runtime: self.options.prelude == Prelude::File
+                && !lower.prelude_used.is_empty(),
+            imports: imports.clone(),
+            warnings: analysis.diagnostics,
+            spans,
+        })
+    }
+
+    fn read_tokens(&mut self) -> Result<Vec<parser::Token>, TranspileError> {
+        let mut tokens = vec![];
+        loop {
+            let token = self.parser.next_token()?;
+            if let TokenKind::EOF = token.kind {
+                return Ok(tokens);
             }
-            TokenKind::Nil => Ok(string),
-            TokenKind::ListNil => Ok("()".to_string()),
-            TokenKind::EOF => Ok(string),
-            _ => unreachable!(),
+            tokens.push(token);
         }
     }
+
+    fn read(&mut self) -> Result<Vec<Expr>, TranspileError> {
+        let mut exprs = self
+            .read_tokens()?
+            .iter()
+            .map(|token| Ok(Expr::from_token(token, self.src)?))
+            .collect::<Result<Vec<_>, TranspileError>>()?;
+        let current = package::current(&exprs).map(str::to_owned);
+        let packages = package::defined(&exprs)
+            .chain(self.packages)
+            .map(|package| package.name.name.clone())
+            .collect::<Vec<_>>();
+        let packages = packages.iter().map(String::as_str).collect::<Vec<_>>();
+        package::unqualify(&mut exprs, current.as_deref(), &packages);
+        Ok(exprs)
+    }
+
+    /// String literals of the names `exprs` defines that their package
+    /// exports, making up the module's `__all__`.
+    fn exports(&self, exprs: &[Expr]) -> Vec<String> {
+        let Some(current) = package::current(exprs) else {
+            return vec![];
+        };
+        let Some(package) = package::defined(exprs)
+            .chain(self.packages)
+            .find(|package| package.name.name == current)
+        else {
+            return vec![];
+        };
+        let defined = exprs
+            .iter()
+            .filter_map(|expr| match &expr.kind {
+                ExprKind::Defun(defun) => Some(defun.name.name.as_str()),
+                ExprKind::Defvar { name, .. } => Some(name.name.as_str()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        package
+            .exports
+            .iter()
+            .filter(|export| defined.contains(export.name.as_str()))
+            .map(|export| builtins::string_literal(&builtins::mangle(&export.name)))
+            .collect()
+    }
+
+    /// Name importing `module` of the package, relative to the module being
+    /// transpiled, or `module` itself outside a package.
+    fn relative(&self, module: &str) -> String {
+        match self.module {
+            Some(current) => format!("{}{module}", ".".repeat(current.split('.').count())),
+            None => module.to_owned(),
+        }
+    }
+
+    /// Header making the referenced runtime helpers available.
+    fn prelude(&self, used: &BTreeSet<&'static str>) -> String {
+        if used.is_empty() {
+            return String::new();
+        }
+        match self.options.prelude {
+            Prelude::Inline => format!("{RUNTIME}\n"),
+            Prelude::File => {
+                let helpers = used.iter().copied().collect::<Vec<_>>().join(", ");
+                let module = self.relative(RUNTIME_MODULE);
+                format!("from {module} import {helpers}\n")
+            }
+        }
+    }
+}
+
+/// Results of the analysis passes.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub resolution: Resolution,
+    pub types: Types,
+    pub reachability: Reachability,
+    /// Diagnostics of every pass, in source order.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+pub fn analyse(exprs: &[Expr], options: &Options, imports: &[Import]) -> Analysis {
+    let mut resolution = Resolution::resolve(exprs, imports);
+    let mut diagnostics = std::mem::take(&mut resolution.diagnostics);
+    diagnostics.extend(arity::check(exprs, &resolution.imports));
+    let (types, type_errors) = types::infer(exprs, &resolution);
+    diagnostics.extend(type_errors);
+    let entry = options.entry.as_deref();
+    let (reachability, dead_code) = reach::analyse(exprs, &resolution, entry, options.library);
+    diagnostics.extend(dead_code);
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.start_row, diagnostic.span.start_col));
+    Analysis {
+        resolution,
+        types,
+        reachability,
+        diagnostics,
+    }
 }
@@ -0,0 +1,1427 @@
+//! Lowering of the syntax tree to Python source.
+//!
+//! Lisp forms are expressions while many of their Python counterparts are
+//! statements. Forms are lowered as statements given a [`Ctx`] saying where
+//! their value goes; when a form that needs statements appears inside an
+//! expression, those statements are hoisted in front of it and its value is
+//! passed through a temporary.
+use crate::builtins::{self, atom, paren, Lowering, PREC_AND, PREC_NOT, PREC_OR, PREC_TERNARY};
+use crate::expr::{
+    split_keywords, Datum, Declaration, Defun, Expr, ExprKind, Ident, LambdaList, TypeSpec,
+};
+use crate::format::{ControlString, Destination};
+use crate::types::Type;
+use crate::{resolve, tco, Analysis, Options, TranspileError};
+use lexer::Span;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where the value of a lowered form goes.
+#[derive(Debug, Clone)]
+enum Ctx {
+    /// Evaluated for its side effects only.
+    Discard,
+    /// Returned from the enclosing function.
+    Return,
+    /// Assigned to a variable.
+    Assign(String),
+}
+
+pub struct Lower<'o> {
+    options: &'o Options,
+    analysis: &'o Analysis,
+    lines: Vec<Line>,
+    /// Span of the innermost form being lowered, recorded for each line.
+    span: Span,
+    indent: usize,
+    temps: usize,
+    /// Runtime helpers referenced by the generated code.
+    pub prelude_used: BTreeSet<&'static str>,
+    /// Names used by type hints that need importing from `typing`.
+    pub typing_used: BTreeSet<&'static str>,
+    /// Whether the program builds improper lists, requiring `Cons` cells.
+    improper_lists: bool,
+    /// Lambda lists of the functions defined or imported by the program,
+    /// which shadow builtins.
+    defuns: BTreeMap<String, LambdaList>,
+    /// Functions run through the trampoline.
+    trampolined: BTreeSet<String>,
+    /// Lisp variables in scope and their Python names, innermost last.
+    scopes: Vec<Vec<(String, String)>>,
+    /// Python names of the module's globals, functions and imports, which
+    /// local bindings are renamed to keep clear of.
+    module_names: BTreeSet<String>,
+    function: Option<Function>,
+    /// Where the values of the enclosing `loop`s go, innermost last.
+    loops: Vec<Ctx>,
+}
+
+/// Line of Python and the span of the form it was lowered from.
+struct Line {
+    text: String,
+    span: Span,
+}
+
+/// Parameter set up at the top of a function body: assigned its default
+/// value when missing, or turned into a list when it is the `&rest` one.
+type Setup<'p> = (String, Option<&'p Expr>);
+
+/// The function being lowered.
+struct Function {
+    name: Option<String>,
+    params: Vec<String>,
+    /// Whether self tail calls jump back to the top of a `while True:` loop.
+    tail_loop: bool,
+    trampolined: bool,
+    /// Globals assigned in the body, which need a `global` declaration.
+    globals: BTreeSet<String>,
+}
+
+impl<'o> Lower<'o> {
+    pub fn new(options: &'o Options, analysis: &'o Analysis) -> Self {
+        Self {
+            options,
+            analysis,
+            lines: vec![],
+            span: Span::default(),
+            indent: 0,
+            temps: 0,
+            prelude_used: BTreeSet::new(),
+            typing_used: BTreeSet::new(),
+            improper_lists: false,
+            defuns: BTreeMap::new(),
+            trampolined: BTreeSet::new(),
+            scopes: vec![],
+            module_names: BTreeSet::new(),
+            function: None,
+            loops: vec![],
+        }
+    }
+
+    /// Lower the program to Python, along with the span each line was
+    /// lowered from, or the default span for lines from no form.
+    pub fn program(&mut self, exprs: &[Expr]) -> Result<(String, Vec<Span>), TranspileError> {
+        self.improper_lists = exprs.iter().any(builds_improper_list);
+        let defuns = exprs
+            .iter()
+            .filter_map(|expr| match expr.kind {
+                ExprKind::Defun(ref defun) => Some(defun),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.defuns = defuns
+            .iter()
+            .map(|defun| (defun.name.name.clone(), defun.params.clone()))
+            .collect();
+        for import in &self.analysis.resolution.imports {
+            if let Some(params) = &import.params {
+                self.defuns
+                    .insert(import.local().to_owned(), params.clone());
+            }
+        }
+        self.module_names = module_names(exprs, &self.analysis.resolution.imports);
+        if self.options.trampoline {
+            // The trampoline forwards arguments positionally
+            self.trampolined = tco::mutual_tail_calls(&defuns)
+                .into_iter()
+                .filter(|name| self.defuns[*name].is_simple())
+                .map(str::to_string)
+                .collect();
+        }
+
+        let mut prev_def = false;
+        for expr in exprs {
+            if let ExprKind::Declaim { .. } | ExprKind::Defpackage(_) | ExprKind::InPackage(_) =
+                expr.kind
+            {
+                continue;
+            }
+            let is_def = matches!(expr.kind, ExprKind::Defun(_));
+            if (is_def || prev_def) && !self.lines.is_empty() {
+                self.blank_line();
+            }
+            self.stmt(expr, Ctx::Discard)?;
+            prev_def = is_def;
+        }
+        if let Some(entry) = &self.options.entry {
+            self.blank_line();
+            self.line("if __name__ == \"__main__\":");
+            self.indent += 1;
+            self.line(format!("{}()", builtins::mangle(entry)));
+            self.indent -= 1;
+        }
+
+        let mut out = String::new();
+        let mut spans = vec![];
+        for line in self.lines.drain(..) {
+            out.push_str(&line.text);
+            out.push('\n');
+            spans.push(line.span);
+        }
+        Ok((out, spans))
+    }
+
+    fn stmt(&mut self, expr: &Expr, ctx: Ctx) -> Result<(), TranspileError> {
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        let result = self.stmt_kind(expr, ctx);
+        self.span = outer;
+        result
+    }
+
+    fn stmt_kind(&mut self, expr: &Expr, ctx: Ctx) -> Result<(), TranspileError> {
+        match &expr.kind {
+            ExprKind::Defun(defun) => {
+                self.defun(defun)?;
+                self.finish(ctx, builtins::string_literal(&defun.name.name));
+            }
+            ExprKind::Declaim { .. } if matches!(ctx, Ctx::Discard) => {}
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let mut branches = vec![(&**cond, std::slice::from_ref(&**then))];
+                let mut otherwise = otherwise.as_deref();
+                // Flatten `else if` chains into `elif`s
+                while let Some(Expr {
+                    kind:
+                        ExprKind::If {
+                            cond,
+                            then,
+                            otherwise: next,
+                        },
+                    ..
+                }) = otherwise
+                {
+                    branches.push((cond, std::slice::from_ref(&**then)));
+                    otherwise = next.as_deref();
+                }
+                self.branches(&branches, otherwise.map(std::slice::from_ref), ctx)?;
+            }
+            ExprKind::Cond(clauses) => {
+                let mut branches = vec![];
+                let mut otherwise = None;
+                for clause in clauses {
+                    if let ExprKind::T = clause.test.kind {
+                        otherwise = Some(clause.body.as_slice());
+                        break;
+                    }
+                    branches.push((&clause.test, clause.body.as_slice()));
+                }
+                self.branches(&branches, otherwise, ctx)?;
+            }
+            ExprKind::Progn(body) => self.body(body, ctx)?,
+            ExprKind::And(operands) | ExprKind::Or(operands) => match self.junction(expr) {
+                Some(value) => self.finish(ctx, value),
+                None => {
+                    let and = matches!(expr.kind, ExprKind::And(_));
+                    self.junction_stmt(and, operands, ctx)?;
+                }
+            },
+            ExprKind::Let {
+                bindings,
+                body,
+                sequential,
+            } => {
+                if *sequential {
+                    self.scopes.push(vec![]);
+                    for (ident, value) in bindings {
+                        let value = self.expr(value)?;
+                        let name = self.bind(ident);
+                        let annotation = self.annotation(ident);
+                        self.line(format!("{name}{annotation} = {value}"));
+                    }
+                } else {
+                    let values = bindings
+                        .iter()
+                        .map(|(_, value)| value.clone())
+                        .collect::<Vec<_>>();
+                    let values = self.args(&values)?;
+                    self.scopes.push(vec![]);
+                    for ((ident, _), value) in bindings.iter().zip(values) {
+                        let name = self.bind(ident);
+                        let annotation = self.annotation(ident);
+                        self.line(format!("{name}{annotation} = {value}"));
+                    }
+                }
+                let result = self.body(body, ctx);
+                self.scopes.pop();
+                result?;
+            }
+            ExprKind::Setq(pairs) => {
+                let mut last = "None".to_string();
+                for (name, value) in pairs {
+                    let value = self.expr(value)?;
+                    last = self.assign(name);
+                    self.line(format!("{last} = {value}"));
+                }
+                self.finish(ctx, last);
+            }
+            ExprKind::Defvar { name, value } => {
+                let value = match value {
+                    Some(value) => self.expr(value)?,
+                    None => "None".to_string(),
+                };
+                // Names declared `global` cannot be annotated
+                let annotation = match self.function {
+                    Some(_) => String::new(),
+                    None => self.annotation(name),
+                };
+                let name = self.assign(name);
+                self.line(format!("{name}{annotation} = {value}"));
+                self.finish(ctx, builtins::string_literal(&name));
+            }
+            ExprKind::Loop(body) => {
+                self.line("while True:");
+                self.loops.push(ctx);
+                let result = self.block(|lower| {
+                    for expr in body {
+                        lower.stmt(expr, Ctx::Discard)?;
+                    }
+                    Ok(())
+                });
+                self.loops.pop();
+                result?;
+            }
+            ExprKind::Return(value) => {
+                let value = value.as_deref();
+                match self.loops.last().cloned() {
+                    Some(Ctx::Return) | None => self.stmt_or_nil(value, Ctx::Return)?,
+                    Some(Ctx::Discard) => {
+                        if let Some(value) = value {
+                            self.stmt(value, Ctx::Discard)?;
+                        }
+                        self.line("break");
+                    }
+                    Some(exit @ Ctx::Assign(_)) => {
+                        self.stmt_or_nil(value, exit)?;
+                        self.line("break");
+                    }
+                }
+            }
+            ExprKind::ReturnFrom { value, .. } => {
+                self.stmt_or_nil(value.as_deref(), Ctx::Return)?
+            }
+            ExprKind::Call { func, args } if matches!(ctx, Ctx::Return) => {
+                if !self.tail_call(func, args)? {
+                    let value = self.call(func, args)?;
+                    self.finish(ctx, value);
+                }
+            }
+            _ => {
+                let value = self.expr(expr)?;
+                self.finish(ctx, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn stmt_or_nil(&mut self, expr: Option<&Expr>, ctx: Ctx) -> Result<(), TranspileError> {
+        match expr {
+            Some(expr) => self.stmt(expr, ctx),
+            None => {
+                self.finish(ctx, "None".to_string());
+                Ok(())
+            }
+        }
+    }
+
+    fn body(&mut self, body: &[Expr], ctx: Ctx) -> Result<(), TranspileError> {
+        let Some((last, init)) = body.split_last() else {
+            self.finish(ctx, "None".to_string());
+            return Ok(());
+        };
+        for expr in init {
+            self.stmt(expr, Ctx::Discard)?;
+        }
+        self.stmt(last, ctx)
+    }
+
+    /// Deliver an already lowered value.
+    fn finish(&mut self, ctx: Ctx, value: String) {
+        match ctx {
+            // Only calls can have side effects worth keeping
+            Ctx::Discard if !value.contains('(') => {}
+            Ctx::Discard => self.line(value),
+            Ctx::Return => self.line(format!("return {value}")),
+            Ctx::Assign(name) => self.line(format!("{name} = {value}")),
+        }
+    }
+
+    /// Lower an `if`/`elif`/`else` chain. A branch with an empty body has
+    /// the value of its test, as in `cond`.
+    fn branches(
+        &mut self,
+        branches: &[(&Expr, &[Expr])],
+        otherwise: Option<&[Expr]>,
+        ctx: Ctx,
+    ) -> Result<(), TranspileError> {
+        if branches.is_empty() {
+            return self.body(otherwise.unwrap_or_default(), ctx);
+        }
+
+        for (i, (test, body)) in branches.iter().enumerate() {
+            let (value, hoisted) = self.scratch(|lower| lower.expr(test));
+            let value = value?;
+            // Conditions needing statements cannot be evaluated by an `elif`
+            if i > 0 && (!hoisted.is_empty() || body.is_empty()) {
+                self.line("else:");
+                return self.block(|lower| lower.branches(&branches[i..], otherwise, ctx));
+            }
+            self.lines.extend(hoisted);
+
+            let keyword = if i == 0 { "if" } else { "elif" };
+            if body.is_empty() {
+                let temp = self.temp();
+                self.line(format!("{temp} = {value}"));
+                let test = self.test(test, temp.clone());
+                self.line(format!("{keyword} {test}:"));
+                self.block(|lower| {
+                    lower.finish(ctx.clone(), temp);
+                    Ok(())
+                })?;
+            } else {
+                let test = self.test(test, value);
+                self.line(format!("{keyword} {test}:"));
+                self.block(|lower| lower.body(body, ctx.clone()))?;
+            }
+        }
+
+        match otherwise {
+            Some(body) => {
+                self.line("else:");
+                self.block(|lower| lower.body(body, ctx))
+            }
+            None if !matches!(ctx, Ctx::Discard) => {
+                self.line("else:");
+                self.block(|lower| {
+                    lower.finish(ctx, "None".to_string());
+                    Ok(())
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Lower a form to a Python expression, hoisting any statements it
+    /// needs in front of it.
+    fn expr(&mut self, expr: &Expr) -> Result<String, TranspileError> {
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        let result = self.expr_kind(expr);
+        self.span = outer;
+        result
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) -> Result<String, TranspileError> {
+        Ok(match &expr.kind {
+            ExprKind::Nil
+            | ExprKind::Declaim { .. }
+            | ExprKind::Defpackage(_)
+            | ExprKind::InPackage(_) => "None".to_string(),
+            ExprKind::Require(modules) => {
+                for module in modules {
+                    self.line(format!("import {}", python_name(&module.name)));
+                }
+                "None".to_string()
+            }
+            ExprKind::Import(modules) => {
+                for module in modules {
+                    self.line(format!("import {}", python_name(&module.name)));
+                }
+                "None".to_string()
+            }
+            ExprKind::MethodCall {
+                object,
+                method,
+                args,
+            } => self.python_call(Some(object), &python_name(&method.name), args)?,
+            ExprKind::Attribute { object, attr } => {
+                format!(
+                    "{}.{}",
+                    receiver(&self.expr(object)?),
+                    python_name(&attr.name)
+                )
+            }
+            ExprKind::T => "True".to_string(),
+            ExprKind::Number(n) => n.strip_prefix('+').unwrap_or(n).to_string(),
+            ExprKind::Str(s) => builtins::string_literal(s),
+            ExprKind::Symbol(name) => self.lookup(name),
+            ExprKind::Function(func) => self.function_ref(func),
+            ExprKind::Quote(datum) => self.datum(datum),
+            ExprKind::Call { func, args } => self.call(func, args)?,
+            ExprKind::Funcall { func, args } => {
+                let mut values = self.args(&[std::slice::from_ref(&**func), args].concat())?;
+                let func = values.remove(0);
+                format!("{}({})", atom(&func), values.join(", "))
+            }
+            ExprKind::Lambda { params, body } => self.lambda(params, body)?,
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let nil = Expr {
+                    kind: ExprKind::Nil,
+                    span: expr.span,
+                };
+                let parts = [&**cond, &**then, otherwise.as_deref().unwrap_or(&nil)];
+                match self.pure(&parts) {
+                    Some([value, then, otherwise]) => format!(
+                        "{} if {} else {}",
+                        paren(&then, PREC_TERNARY + 1),
+                        paren(&self.test(cond, value), PREC_TERNARY + 1),
+                        paren(&otherwise, PREC_TERNARY),
+                    ),
+                    _ => self.hoist(expr)?,
+                }
+            }
+            ExprKind::And(_) | ExprKind::Or(_) => match self.junction(expr) {
+                Some(value) => value,
+                None => self.hoist(expr)?,
+            },
+            ExprKind::Progn(body) if body.len() <= 1 => match body.first() {
+                Some(expr) => self.expr(expr)?,
+                None => "None".to_string(),
+            },
+            ExprKind::Setq(pairs) if pairs.len() == 1 => {
+                let (name, value) = &pairs[0];
+                let value = self.expr(value)?;
+                let name = self.assign(name);
+                self.line(format!("{name} = {value}"));
+                name
+            }
+            ExprKind::Return(_) | ExprKind::ReturnFrom { .. } => {
+                self.stmt(expr, Ctx::Discard)?;
+                "None".to_string()
+            }
+            ExprKind::The { ty, value } => {
+                let value = self.expr(value)?;
+                match self.options.checked {
+                    true => {
+                        let value = match is_name(&value) {
+                            true => value,
+                            false => {
+                                let temp = self.temp();
+                                self.line(format!("{temp} = {value}"));
+                                temp
+                            }
+                        };
+                        self.check(ty, &value);
+                        value
+                    }
+                    false => value,
+                }
+            }
+            _ => self.hoist(expr)?,
+        })
+    }
+
+    /// Assert that `value`, a variable, is of type `spec`.
+    fn check(&mut self, spec: &TypeSpec, value: &str) {
+        let Some(test) = type_test(spec, value) else {
+            return;
+        };
+        let spec_or_null = match spec {
+            TypeSpec::OrNull(spec) => spec,
+            spec => spec,
+        };
+        if let TypeSpec::List = spec_or_null {
+            self.prelude_used.extend(["Cons", "Tail"]);
+        }
+        let expected = match spec {
+            TypeSpec::Function => "function".to_string(),
+            spec => Type::from(spec).describe(),
+        };
+        self.line(format!(
+            "assert {test}, f\"The value {{{value}!r}} is not of type {expected}\""
+        ));
+    }
+
+    /// Lower `exprs` as expressions if none of them needs statements.
+    fn pure<const N: usize>(&mut self, exprs: &[&Expr; N]) -> Option<[String; N]> {
+        self.pure_vec(exprs)
+            .map(|values| values.try_into().expect("same length"))
+    }
+
+    fn pure_vec(&mut self, exprs: &[&Expr]) -> Option<Vec<String>> {
+        let (values, hoisted) = self.scratch(|lower| {
+            exprs
+                .iter()
+                .map(|expr| lower.expr(expr))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        match hoisted.is_empty() {
+            true => values.ok(),
+            false => None,
+        }
+    }
+
+    /// Lower `expr` as a statement assigning a temporary.
+    fn hoist(&mut self, expr: &Expr) -> Result<String, TranspileError> {
+        let temp = self.temp();
+        self.stmt(expr, Ctx::Assign(temp.clone()))?;
+        Ok(temp)
+    }
+
+    /// Lower the `and` or `or` form `expr` as an expression, if none of its
+    /// operands needs statements and those tested are booleans or names.
+    fn junction(&mut self, expr: &Expr) -> Option<String> {
+        let (ExprKind::And(operands) | ExprKind::Or(operands)) = &expr.kind else {
+            unreachable!("`expr` is an `and` or `or` form");
+        };
+        let and = matches!(expr.kind, ExprKind::And(_));
+        let values = self.pure_vec(&operands.iter().collect::<Vec<_>>())?;
+        let Some((last, init)) = values.split_last() else {
+            return Some(if and { "True" } else { "None" }.to_string());
+        };
+        let mut value = last.clone();
+        for (operand, tested) in operands.iter().zip(init).rev() {
+            // Python's `and` and `or` agree with Lisp on booleans alone
+            value = match (self.is_boolean(operand), and) {
+                (true, true) => format!(
+                    "{} and {}",
+                    paren(tested, PREC_AND),
+                    paren(&value, PREC_AND)
+                ),
+                (true, false) => {
+                    format!("{} or {}", paren(tested, PREC_OR), paren(&value, PREC_OR))
+                }
+                // Names can be tested and then given without evaluating them
+                // twice
+                (false, _) if !is_name(tested) => return None,
+                (false, true) => format!(
+                    "{} if {} else {tested}",
+                    paren(&value, PREC_TERNARY + 1),
+                    self.test(operand, tested.clone()),
+                ),
+                (false, false) => format!(
+                    "{tested} if {} else {}",
+                    self.test(operand, tested.clone()),
+                    paren(&value, PREC_TERNARY),
+                ),
+            };
+        }
+        Some(value)
+    }
+
+    /// Lower the operands of an `and`, or else an `or`, as statements
+    /// testing each in turn.
+    fn junction_stmt(
+        &mut self,
+        and: bool,
+        operands: &[Expr],
+        ctx: Ctx,
+    ) -> Result<(), TranspileError> {
+        let Some((first, rest)) = operands.split_first() else {
+            self.finish(ctx, if and { "True" } else { "None" }.to_string());
+            return Ok(());
+        };
+        if rest.is_empty() {
+            return self.stmt(first, ctx);
+        }
+        // The variable assigned the value can hold each operand tested
+        let temp = match &ctx {
+            Ctx::Assign(name) => name.clone(),
+            _ => self.temp(),
+        };
+        self.stmt(first, Ctx::Assign(temp.clone()))?;
+        let test = self.test(first, temp.clone());
+        match and {
+            true => self.line(format!("if {test}:")),
+            false => self.line(format!("if not {}:", paren(&test, PREC_NOT))),
+        }
+        self.block(|lower| lower.junction_stmt(and, rest, ctx.clone()))?;
+        if matches!(ctx, Ctx::Return) {
+            self.line("else:");
+            self.block(|lower| {
+                lower.finish(ctx, temp);
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Test of the truth of `value`, the lowered `expr`, for Lisp, to which
+    /// only nil is false, rather than for Python, to which `0` and `""` are.
+    fn test(&mut self, expr: &Expr, value: String) -> String {
+        match self.is_boolean(expr) {
+            true => value,
+            false => {
+                self.prelude_used.insert("cl_true");
+                format!("cl_true({value})")
+            }
+        }
+    }
+
+    /// Whether `expr` is `t` or nil, or a predicate giving one, whose truth
+    /// Python takes as Lisp does.
+    fn is_boolean(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::T | ExprKind::Nil | ExprKind::Quote(Datum::Nil) => true,
+            ExprKind::Call { func, .. } if !self.defuns.contains_key(&func.name) => {
+                builtins::PREDICATES.contains(&func.name.as_str())
+            }
+            ExprKind::And(operands) | ExprKind::Or(operands) => {
+                operands.iter().all(|operand| self.is_boolean(operand))
+            }
+            ExprKind::If {
+                then, otherwise, ..
+            } => {
+                self.is_boolean(then)
+                    && otherwise
+                        .as_deref()
+                        .is_none_or(|otherwise| self.is_boolean(otherwise))
+            }
+            _ => false,
+        }
+    }
+
+    /// Lower arguments, preserving their evaluation order when later ones
+    /// need statements hoisted in front of the call.
+    fn args(&mut self, args: &[Expr]) -> Result<Vec<String>, TranspileError> {
+        let mut values: Vec<String> = vec![];
+        for arg in args {
+            let (value, hoisted) = self.scratch(|lower| lower.expr(arg));
+            let value = value?;
+            if !hoisted.is_empty() {
+                for prev in values.iter_mut() {
+                    if prev.contains('(') {
+                        let temp = self.temp();
+                        self.line(format!("{temp} = {prev}"));
+                        *prev = temp;
+                    }
+                }
+                self.lines.extend(hoisted);
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    fn call(&mut self, func: &Ident, args: &[Expr]) -> Result<String, TranspileError> {
+        if let Some(name) = func.name.strip_prefix("py:") {
+            return self.python_call(None, &python_name(name), args);
+        }
+        let builtin = match self.defuns.contains_key(&func.name) {
+            true => None,
+            false => builtins::lookup(&func.name),
+        };
+        if builtin.is_some() && func.name == "format" {
+            if let Some(call) = self.format(args)? {
+                return Ok(call);
+            }
+        }
+
+        if builtin.is_none() {
+            if let Some(call) = self.keyword_call(func, args)? {
+                return Ok(call);
+            }
+        }
+        if let (Some(_), "not" | "null", [arg]) = (builtin, func.name.as_str(), args) {
+            if self.is_boolean(arg) {
+                let value = self.expr(arg)?;
+                return Ok(format!("not {}", paren(&value, PREC_NOT)));
+            }
+        }
+
+        let args = self.args(args)?;
+        let func = match builtin.map(|builtin| builtin.lowering) {
+            Some(Lowering::Python(name)) => name.to_string(),
+            Some(Lowering::Prelude(name)) => {
+                self.prelude_used.insert(name);
+                name.to_string()
+            }
+            Some(Lowering::Inline(inline)) => return Ok(inline(&args)),
+            Some(Lowering::List { inline, prelude }) => {
+                if !self.improper_lists {
+                    if let Some(expr) = inline(&args) {
+                        return Ok(expr);
+                    }
+                }
+                self.prelude_used.insert(prelude);
+                prelude.to_string()
+            }
+            None => builtins::mangle(&func.name),
+        };
+        Ok(format!("{func}({})", args.join(", ")))
+    }
+
+    /// Lower a call to the Python function `func`, or to the method `func`
+    /// of `object`, passing the keyword arguments after the positional ones
+    /// by name.
+    fn python_call(
+        &mut self,
+        object: Option<&Expr>,
+        func: &str,
+        args: &[Expr],
+    ) -> Result<String, TranspileError> {
+        // Malformed keyword arguments are rejected by the reader
+        let (positional, keywords) = split_keywords(args).unwrap_or((args, vec![]));
+        let exprs = object
+            .into_iter()
+            .chain(positional)
+            .chain(keywords.iter().map(|(_, value)| *value))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut values = self.args(&exprs)?;
+        let func = match object {
+            Some(_) => format!("{}.{func}", receiver(&values.remove(0))),
+            None => func.to_owned(),
+        };
+        let keywords = keywords
+            .iter()
+            .zip(values.split_off(positional.len()))
+            .map(|((name, _), value)| format!("{}={value}", builtins::python_ident(name)));
+        values.extend(keywords);
+        Ok(format!("{func}({})", values.join(", ")))
+    }
+
+    /// Lower a call to a function taking `&key` parameters, passing the
+    /// keyword arguments by name.
+    fn keyword_call(
+        &mut self,
+        func: &Ident,
+        args: &[Expr],
+    ) -> Result<Option<String>, TranspileError> {
+        let Some(params) = self
+            .defuns
+            .get(&func.name)
+            .filter(|params| !params.key.is_empty())
+        else {
+            return Ok(None);
+        };
+        let positional = (params.required.len() + params.optional.len()).min(args.len());
+        let (positional, keywords) = args.split_at(positional);
+        let mut names = vec![];
+        let mut exprs = positional.to_vec();
+        for pair in keywords.chunks(2) {
+            // Malformed keyword arguments are reported by the arity check
+            let [keyword, value] = pair else {
+                return Ok(None);
+            };
+            let ExprKind::Symbol(ref keyword) = keyword.kind else {
+                return Ok(None);
+            };
+            let Some(name) = keyword.strip_prefix(':') else {
+                return Ok(None);
+            };
+            names.push(builtins::mangle(name));
+            exprs.push(value.clone());
+        }
+
+        let mut values = self.args(&exprs)?;
+        let keywords = values.split_off(positional.len());
+        values.extend(
+            names
+                .iter()
+                .zip(keywords)
+                .map(|(name, value)| format!("{name}={value}")),
+        );
+        Ok(Some(format!(
+            "{}({})",
+            builtins::mangle(&func.name),
+            values.join(", ")
+        )))
+    }
+
+    /// Lower `#'f` to a Python callable.
+    fn function_ref(&mut self, func: &Ident) -> String {
+        let builtin = match self.defuns.contains_key(&func.name) {
+            true => None,
+            false => builtins::lookup(&func.name),
+        };
+        match builtin.map(|builtin| builtin.lowering) {
+            Some(Lowering::Python(name)) => name.to_string(),
+            Some(Lowering::Prelude(name) | Lowering::List { prelude: name, .. }) => {
+                self.prelude_used.insert(name);
+                name.to_string()
+            }
+            Some(Lowering::Inline(inline)) => {
+                // Operators have no Python callable, so wrap them in a lambda
+                // taking their usual number of arguments
+                let params = match func.name.as_str() {
+                    "zerop" | "1+" | "1-" => vec!["x".to_string()],
+                    _ => vec!["x".to_string(), "y".to_string()],
+                };
+                format!("lambda {}: {}", params.join(", "), inline(&params))
+            }
+            None => match func.name.strip_prefix("py:") {
+                Some(name) => python_name(name),
+                None => builtins::mangle(&func.name),
+            },
+        }
+    }
+
+    /// Lower a self tail call to a jump back to the top of the function, or
+    /// a tail call within a trampolined group to a `TailCall`.
+    fn tail_call(&mut self, func: &Ident, args: &[Expr]) -> Result<bool, TranspileError> {
+        let Some(function) = &self.function else {
+            return Ok(false);
+        };
+        let is_self = function.name.as_deref() == Some(func.name.as_str());
+
+        if is_self
+            && function.tail_loop
+            && self.loops.is_empty()
+            && args.len() == function.params.len()
+        {
+            let params = function.params.clone();
+            let args = self.args(args)?;
+            if !params.is_empty() {
+                self.line(format!("{} = {}", params.join(", "), args.join(", ")));
+            }
+            self.line("continue");
+            return Ok(true);
+        }
+
+        if function.trampolined && self.trampolined.contains(&func.name) {
+            let args = self.args(args)?;
+            self.prelude_used.insert("TailCall");
+            let target = std::iter::once(trampoline_body(&func.name)).chain(args);
+            self.line(format!(
+                "return TailCall({})",
+                target.collect::<Vec<_>>().join(", ")
+            ));
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Compile a `format` call whose control string is a literal, leaving
+    /// other calls to the runtime `cl_format`.
+    fn format(&mut self, args: &[Expr]) -> Result<Option<String>, TranspileError> {
+        let [dest, control, args @ ..] = args else {
+            return Ok(None);
+        };
+        let ExprKind::Str(ref control_string) = control.kind else {
+            return Ok(None);
+        };
+        let control = ControlString::parse(control_string, control.span)?;
+
+        let dest = match dest.kind {
+            ExprKind::T => Destination::Stdout,
+            ExprKind::Nil => Destination::String,
+            _ => {
+                let mut values = self.args(&[std::slice::from_ref(dest), args].concat())?;
+                let stream = values.remove(0);
+                return Ok(Some(control.lower(
+                    Destination::Stream(atom(&stream)),
+                    &values,
+                    &mut self.prelude_used,
+                )?));
+            }
+        };
+        let values = self.args(args)?;
+        Ok(Some(control.lower(
+            dest,
+            &values,
+            &mut self.prelude_used,
+        )?))
+    }
+
+    fn defun(&mut self, defun: &Defun) -> Result<(), TranspileError> {
+        let name = builtins::mangle(&defun.name.name);
+        let trampolined = self.trampolined.contains(&defun.name.name);
+        let def_name = match trampolined {
+            true => trampoline_body(&defun.name.name),
+            false => name.clone(),
+        };
+        let function = Function {
+            name: Some(defun.name.name.clone()),
+            params: vec![],
+            // Closures made in one pass of the loop would see the variables
+            // assigned by the next
+            tail_loop: defun.params.is_simple()
+                && tco::has_self_tail_call(defun)
+                && !defun.body.iter().any(makes_closure),
+            trampolined,
+            globals: BTreeSet::new(),
+        };
+        let (header, params) = self.function(
+            &def_name,
+            function,
+            &defun.params,
+            defun.doc.as_deref(),
+            &defun.declarations,
+            &defun.body,
+        )?;
+
+        if trampolined {
+            self.prelude_used.insert("cl_trampoline");
+            let returns = self.return_annotation(&defun.name.name);
+            self.line(format!("def {name}({}){returns}:", header.join(", ")));
+            let args = std::iter::once(def_name).chain(params);
+            self.indent += 1;
+            self.line(format!(
+                "return cl_trampoline({})",
+                args.collect::<Vec<_>>().join(", ")
+            ));
+            self.indent -= 1;
+        }
+        Ok(())
+    }
+
+    fn lambda(&mut self, params: &LambdaList, body: &[Expr]) -> Result<String, TranspileError> {
+        if let ([expr], true) = (body, params.is_simple()) {
+            self.scopes.push(vec![]);
+            let names = params
+                .required
+                .iter()
+                .map(|param| self.bind(param))
+                .collect::<Vec<_>>();
+            let outer_loops = std::mem::take(&mut self.loops);
+            let value = self.pure(&[expr]);
+            self.loops = outer_loops;
+            self.scopes.pop();
+            if let Some([value]) = value {
+                return Ok(match names.is_empty() {
+                    true => format!("lambda: {value}"),
+                    false => format!("lambda {}: {value}", names.join(", ")),
+                });
+            }
+        }
+
+        self.temps += 1;
+        let name = format!("_lambda{}", self.temps);
+        let function = Function {
+            name: None,
+            params: vec![],
+            tail_loop: false,
+            trampolined: false,
+            globals: BTreeSet::new(),
+        };
+        self.function(&name, function, params, None, &[], body)?;
+        Ok(name)
+    }
+
+    /// Emit a `def`, returning its Python parameter list and the names of
+    /// its required parameters.
+    fn function(
+        &mut self,
+        def_name: &str,
+        mut function: Function,
+        params: &LambdaList,
+        doc: Option<&str>,
+        declarations: &[Declaration],
+        body: &[Expr],
+    ) -> Result<(Vec<String>, Vec<String>), TranspileError> {
+        self.scopes.push(vec![]);
+        let (header, defaults) = self.params(params)?;
+        let required = self.scopes.last().expect("pushed above")[..params.required.len()]
+            .iter()
+            .map(|(_, name)| name.clone())
+            .collect::<Vec<_>>();
+        function.params = required.clone();
+        let tail_loop = function.tail_loop;
+        // A trampolined body returns `TailCall`s as well
+        let returns = match (&function.name, function.trampolined) {
+            (Some(name), false) => self.return_annotation(name),
+            _ => String::new(),
+        };
+        let outer_function = self.function.replace(function);
+        let outer_loops = std::mem::take(&mut self.loops);
+
+        self.line(format!("def {def_name}({}){returns}:", header.join(", ")));
+        self.indent += 1;
+        let (result, lines) = self.scratch(|lower| {
+            for (name, default) in defaults {
+                match default {
+                    Some(default) => {
+                        lower.line(format!("if {name} is None:"));
+                        lower.block(|lower| lower.stmt(default, Ctx::Assign(name)))?;
+                    }
+                    // `&rest` parameter
+                    None => lower.line(format!("{name} = list({name})")),
+                }
+            }
+            if lower.options.checked {
+                for (param, spec) in declarations {
+                    let name = lower.lookup(&param.name);
+                    lower.check(spec, &name);
+                }
+            }
+            match tail_loop {
+                true => {
+                    lower.line("while True:");
+                    lower.block(|lower| lower.body(body, Ctx::Return))
+                }
+                false => lower.body(body, Ctx::Return),
+            }
+        });
+        let function = std::mem::replace(&mut self.function, outer_function)
+            .expect("function is set while lowering its body");
+        if let Some(doc) = doc {
+            self.line(builtins::string_literal(doc));
+        }
+        if !function.globals.is_empty() {
+            let globals = function.globals.into_iter().collect::<Vec<_>>();
+            self.line(format!("global {}", globals.join(", ")));
+        }
+        self.lines.extend(lines);
+        self.indent -= 1;
+
+        self.loops = outer_loops;
+        self.scopes.pop();
+        result?;
+        Ok((header, required))
+    }
+
+    /// Bind the parameters of a lambda list, returning the Python parameter
+    /// list and the parameters to set up at the top of the body.
+    fn params<'p>(
+        &mut self,
+        params: &'p LambdaList,
+    ) -> Result<(Vec<String>, Vec<Setup<'p>>), TranspileError> {
+        let mut header = vec![];
+        for param in &params.required {
+            let name = self.bind(param);
+            header.push(format!("{name}{}", self.annotation(param)));
+        }
+        let mut prologue = vec![];
+        for (name, default) in &params.optional {
+            let (param, setup) = self.defaulted(name, default.as_ref(), false)?;
+            header.push(param);
+            prologue.extend(setup);
+        }
+        if let Some(rest) = &params.rest {
+            let name = self.bind(rest);
+            let annotation = match self.binding_type(rest) {
+                Some(Type::List(elem)) => self.hint(&elem),
+                _ => String::new(),
+            };
+            header.push(format!("*{name}{annotation}"));
+            prologue.push((name, None));
+        } else if !params.key.is_empty() {
+            header.push("*".to_string());
+        }
+        for (name, default) in &params.key {
+            let (param, setup) = self.defaulted(name, default.as_ref(), true)?;
+            header.push(param);
+            prologue.extend(setup);
+        }
+        if params.allow_other_keys {
+            header.push("**_other_keys".to_string());
+        }
+        Ok((header, prologue))
+    }
+
+    /// Bind an `&optional` or `&key` parameter. Constant defaults go in the
+    /// parameter list; others are evaluated in the body when the argument is
+    /// missing, in which case passing nil explicitly also selects them.
+    fn defaulted<'p>(
+        &mut self,
+        name: &Ident,
+        default: Option<&'p Expr>,
+        key: bool,
+    ) -> Result<(String, Option<Setup<'p>>), TranspileError> {
+        let ident = name;
+        // Callers pass `&key` arguments by name, so it is kept
+        let name = match key {
+            true => self.bind_as(ident, false),
+            false => self.bind(ident),
+        };
+        let (value, setup) = match default.map(|default| &default.kind) {
+            Some(ExprKind::Number(_) | ExprKind::Str(_) | ExprKind::T) => {
+                let value = self.expr(default.expect("matched a default"))?;
+                (value, None)
+            }
+            None | Some(ExprKind::Nil) => ("None".to_string(), None),
+            Some(_) => ("None".to_string(), Some((name.clone(), default))),
+        };
+        let annotation = match (self.binding_type(ident), value.as_str()) {
+            (Some(ty), "None") => self.hint(&ty.nullable()),
+            (Some(ty), _) => self.hint(&ty),
+            (None, _) => String::new(),
+        };
+        Ok(match annotation.is_empty() {
+            true => (format!("{name}={value}"), setup),
+            false => (format!("{name}{annotation} = {value}"), setup),
+        })
+    }
+
+    /// Inferred type of the variable bound at `ident` with `--type-hints`,
+    /// or otherwise its declared type.
+    fn binding_type(&self, ident: &Ident) -> Option<Type> {
+        let analysis = self.analysis;
+        match self.options.type_hints {
+            true => analysis.types.binding(&analysis.resolution, ident),
+            false => analysis.types.declared(&analysis.resolution, ident),
+        }
+        .cloned()
+    }
+
+    /// Annotation such as `: int` for the variable bound at `ident`.
+    fn annotation(&mut self, ident: &Ident) -> String {
+        match self.binding_type(ident) {
+            Some(ty) => self.hint(&ty),
+            None => String::new(),
+        }
+    }
+
+    /// Return annotation such as ` -> int` for the `defun` named `name`.
+    fn return_annotation(&mut self, name: &str) -> String {
+        if !self.options.type_hints {
+            return String::new();
+        }
+        match self.analysis.types.function(name) {
+            Some(Type::Function(_, ret)) => match ret.python(&mut self.typing_used) {
+                Some(ret) => format!(" -> {ret}"),
+                None => String::new(),
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn hint(&mut self, ty: &Type) -> String {
+        match ty.python(&mut self.typing_used) {
+            Some(ty) => format!(": {ty}"),
+            None => String::new(),
+        }
+    }
+
+    /// Lower quoted data. Symbols become strings, proper lists become Python
+    /// lists and improper lists become chains of `Cons` cells.
+    fn datum(&mut self, datum: &Datum) -> String {
+        match datum {
+            Datum::Nil => "None".to_string(),
+            Datum::Number(n) => n.strip_prefix('+').unwrap_or(n).to_string(),
+            Datum::Str(s) => builtins::string_literal(s),
+            Datum::Symbol(s) if s == "t" => "True".to_string(),
+            Datum::Symbol(s) => builtins::string_literal(s),
+            Datum::Quote(datum) => format!("[\"quote\", {}]", self.datum(datum)),
+            Datum::List(items, tail) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.datum(item))
+                    .collect::<Vec<_>>();
+                match tail.as_deref() {
+                    None | Some(Datum::Nil) => format!("[{}]", items.join(", ")),
+                    Some(tail) if tail.is_list() => {
+                        format!("[{}, *{}]", items.join(", "), self.datum(tail))
+                    }
+                    Some(tail) => {
+                        self.prelude_used.insert("Cons");
+                        let tail = self.datum(tail);
+                        items
+                            .into_iter()
+                            .rev()
+                            .fold(tail, |cdr, car| format!("Cons({car}, {cdr})"))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bind a new variable in the innermost scope, renaming it if it would
+    /// clobber a variable of an enclosing scope, or hide a name of the
+    /// module.
+    fn bind(&mut self, ident: &Ident) -> String {
+        self.bind_as(ident, true)
+    }
+
+    fn bind_as(&mut self, ident: &Ident, avoid_module_names: bool) -> String {
+        let base = builtins::mangle(&ident.name);
+        let taken = |name: &str| {
+            (avoid_module_names && self.module_names.contains(name))
+                || self
+                    .scopes
+                    .iter()
+                    .flatten()
+                    .any(|(_, python)| python == name)
+        };
+        let mut name = base.clone();
+        let mut n = 0;
+        while taken(&name) {
+            n += 1;
+            name = format!("{base}_{n}");
+        }
+        self.scopes
+            .last_mut()
+            .expect("bindings are made inside a scope")
+            .push((ident.name.clone(), name.clone()));
+        name
+    }
+
+    fn lookup(&self, name: &str) -> String {
+        if let Some(keyword) = name.strip_prefix(':') {
+            return builtins::string_literal(keyword);
+        }
+        if let Some(name) = name.strip_prefix("py:") {
+            return python_name(name);
+        }
+        self.local(name).unwrap_or_else(|| builtins::mangle(name))
+    }
+
+    fn local(&self, name: &str) -> Option<String> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(lisp, _)| lisp == name)
+            .map(|(_, python)| python.clone())
+    }
+
+    /// Python name to assign for `setq` of `ident`, declaring it global
+    /// when assigned from inside a function.
+    fn assign(&mut self, ident: &Ident) -> String {
+        if let Some(name) = self.local(&ident.name) {
+            return name;
+        }
+        let name = builtins::mangle(&ident.name);
+        if let Some(function) = &mut self.function {
+            function.globals.insert(name.clone());
+        }
+        name
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("_t{}", self.temps)
+    }
+
+    fn line(&mut self, text: impl AsRef<str>) {
+        let text = format!("{}{}", "    ".repeat(self.indent), text.as_ref());
+        self.lines.push(Line {
+            text,
+            span: self.span,
+        });
+    }
+
+    fn blank_line(&mut self) {
+        self.lines.push(Line {
+            text: String::new(),
+            span: Span::default(),
+        });
+    }
+
+    /// Run `f` one level deeper, emitting `pass` if it emits nothing.
+    fn block(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), TranspileError>,
+    ) -> Result<(), TranspileError> {
+        self.indent += 1;
+        let start = self.lines.len();
+        let result = f(self);
+        if self.lines.len() == start {
+            self.line("pass");
+        }
+        self.indent -= 1;
+        result
+    }
+
+    /// Run `f`, returning the lines it emits instead of emitting them.
+    fn scratch<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> (T, Vec<Line>) {
+        let outer = std::mem::take(&mut self.lines);
+        let result = f(self);
+        let lines = std::mem::replace(&mut self.lines, outer);
+        (result, lines)
+    }
+}
+
+/// Python test that `value` is of type `spec`, unless any value is.
+fn type_test(spec: &TypeSpec, value: &str) -> Option<String> {
+    Some(match spec {
+        TypeSpec::Integer => format!("isinstance({value}, int)"),
+        TypeSpec::Float => format!("isinstance({value}, (int, float))"),
+        TypeSpec::String => format!("isinstance({value}, str)"),
+        TypeSpec::Boolean => format!("isinstance({value}, bool)"),
+        TypeSpec::List => format!("isinstance({value}, (list, Tail, Cons))"),
+        TypeSpec::Function => format!("callable({value})"),
+        TypeSpec::Null => format!("{value} is None"),
+        TypeSpec::T => return None,
+        TypeSpec::OrNull(spec) => format!("{value} is None or {}", type_test(spec, value)?),
+    })
+}
+
+/// `value` as the object of an attribute access, where `5.real` would read
+/// as a float.
+fn receiver(value: &str) -> String {
+    match value.chars().all(|c| c.is_ascii_digit()) {
+        true => format!("({value})"),
+        false => atom(value),
+    }
+}
+
+/// Python names bound at the top level of the module: its globals and
+/// functions, the definitions it imports, the Python modules, and the
+/// builtins and runtime helpers the lowered code calls.
+fn module_names(exprs: &[Expr], imports: &[resolve::Import]) -> BTreeSet<String> {
+    let mut names = imports
+        .iter()
+        .map(|import| builtins::mangle(import.local()))
+        .chain(builtins::PYTHON_NAMES.iter().map(|name| name.to_string()))
+        .chain(builtins::runtime_names().map(str::to_owned))
+        .collect::<BTreeSet<_>>();
+    for expr in exprs {
+        match &expr.kind {
+            ExprKind::Defun(Defun { name, .. }) | ExprKind::Defvar { name, .. } => {
+                names.insert(builtins::mangle(&name.name));
+            }
+            ExprKind::Import(modules) | ExprKind::Require(modules) => {
+                names.extend(modules.iter().map(|module| {
+                    let python = python_name(&module.name);
+                    python.split('.').next().unwrap_or_default().to_owned()
+                }));
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Python name of a dotted name given by `py:name`, `import` or `.`.
+fn python_name(name: &str) -> String {
+    name.split('.')
+        .map(builtins::python_ident)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn is_name(value: &str) -> bool {
+    value.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !value.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Name of the `def` holding the body of a trampolined function.
+fn trampoline_body(name: &str) -> String {
+    format!("_{}_body", builtins::mangle(name))
+}
+
+/// Whether `expr` contains a `lambda` or `defun`, closing over the
+/// variables around it.
+fn makes_closure(expr: &Expr) -> bool {
+    let mut closure = false;
+    expr.walk(&mut |expr| {
+        closure |= matches!(expr.kind, ExprKind::Lambda { .. } | ExprKind::Defun(_));
+    });
+    closure
+}
+
+/// Whether `expr` contains a dotted pair with a non-list tail, or conses
+/// onto something that is not a list.
+fn builds_improper_list(expr: &Expr) -> bool {
+    let mut improper = false;
+    expr.walk(&mut |expr| match &expr.kind {
+        ExprKind::Quote(datum) => improper |= is_improper(datum),
+        ExprKind::Call { func, args } if func.name == "cons" => {
+            improper |= match args.get(1).map(|cdr| &cdr.kind) {
+                Some(ExprKind::Number(_) | ExprKind::Str(_) | ExprKind::T) => true,
+                Some(ExprKind::Quote(datum)) => !datum.is_list(),
+                _ => false,
+            }
+        }
+        _ => {}
+    });
+    improper
+}
+
+fn is_improper(datum: &Datum) -> bool {
+    match datum {
+        Datum::List(items, tail) => {
+            !datum.is_list()
+                || items.iter().any(is_improper)
+                || tail.as_deref().is_some_and(is_improper)
+        }
+        Datum::Quote(datum) => is_improper(datum),
+        _ => false,
+    }
+}
@@ -0,0 +1,83 @@
+//! Packages, which map onto the modules of the files in them.
+//!
+//! The `in-package` of a file names the package its definitions belong to.
+//! Symbols qualified by that package, or by the standard `cl` package, name
+//! the same things as the plain symbols and lose their qualifier before
+//! analysis. Those qualified by another package of the program are imported
+//! from the module defining them, as set up by [`crate::project`].
+use crate::expr::{Expr, ExprKind, Ident, Package};
+
+/// Names of the standard package, whose symbols are the builtins.
+const STANDARD: [&str; 3] = ["cl", "common-lisp", "cl-user"];
+
+pub fn is_standard(package: &str) -> bool {
+    STANDARD.contains(&package)
+}
+
+/// Package named by the first top-level `in-package` of `exprs`.
+pub fn current(exprs: &[Expr]) -> Option<&str> {
+    exprs.iter().find_map(|expr| match &expr.kind {
+        ExprKind::InPackage(name) => Some(name.name.as_str()),
+        _ => None,
+    })
+}
+
+/// Packages defined by the top-level `defpackage`s of `exprs`.
+pub fn defined(exprs: &[Expr]) -> impl Iterator<Item = &Package> {
+    exprs.iter().filter_map(|expr| match &expr.kind {
+        ExprKind::Defpackage(package) => Some(package),
+        _ => None,
+    })
+}
+
+/// Package, name and whether it is external of a symbol qualified as
+/// `pkg:name`, or internal as `pkg::name`. Package names are lowercased.
+pub fn split(symbol: &str) -> Option<(String, &str, bool)> {
+    let (package, rest) = symbol
+        .split_once(':')
+        .filter(|(package, _)| !package.is_empty())?;
+    let (name, external) = match rest.strip_prefix(':') {
+        Some(name) => (name, false),
+        None => (rest, true),
+    };
+    Some((package.to_lowercase(), name, external))
+}
+
+/// Drop the qualifiers of symbols in the standard package or `package`, and
+/// the `require`s of `packages`, which are imported rather than loaded.
+pub fn unqualify(exprs: &mut Vec<Expr>, package: Option<&str>, packages: &[&str]) {
+    for expr in exprs.iter_mut() {
+        unqualify_expr(expr, package);
+        if let ExprKind::Require(modules) = &mut expr.kind {
+            modules.retain(|module| !packages.contains(&module.name.as_str()));
+        }
+    }
+    exprs.retain(|expr| !matches!(&expr.kind, ExprKind::Require(modules) if modules.is_empty()));
+}
+
+fn unqualify_expr(expr: &mut Expr, package: Option<&str>) {
+    let unqualify = |name: &mut String| {
+        if let Some((qualifier, local, _)) = split(name) {
+            if is_standard(&qualifier) || Some(qualifier.as_str()) == package {
+                *name = local.to_owned();
+            }
+        }
+    };
+    let ident = |ident: &mut Ident| unqualify(&mut ident.name);
+    match &mut expr.kind {
+        ExprKind::Symbol(name) => unqualify(name),
+        ExprKind::Function(func) | ExprKind::Call { func, .. } => ident(func),
+        ExprKind::Defun(defun) => ident(&mut defun.name),
+        ExprKind::Defvar { name, .. } | ExprKind::ReturnFrom { name, .. } => ident(name),
+        ExprKind::Let { bindings, .. } | ExprKind::Setq(bindings) => {
+            bindings.iter_mut().for_each(|(name, _)| ident(name))
+        }
+        ExprKind::Declaim { inline, notinline } => {
+            inline.iter_mut().chain(notinline).for_each(ident)
+        }
+        _ => {}
+    }
+    for child in expr.children_mut() {
+        unqualify_expr(child, package);
+    }
+}
@@ -0,0 +1,436 @@
+//! Programs made of a directory tree of sources, each transpiled to a module
+//! of a Python package mirroring the tree.
+//!
+//! Every source sees the top-level `defun`s and `defvar`s of the others in
+//! its package, the exports of the packages it uses and, qualified by their
+//! package, those of any other package. The modules referring to them
+//! import them. A manifest in the output
+//! directory records a hash of what each module was built from: its source,
+//! the options and the definitions it imported, so that a rebuild only
+//! transpiles the sources for which one of them changed.
+use crate::builtins::{self, RUNTIME, RUNTIME_MODULE};
+use crate::diagnostic::Diagnostic;
+use crate::expr::Package;
+use crate::resolve::Import;
+use crate::sourcemap::SourceMap;
+use crate::{Options, Pythonify, TranspileError};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File of the output directory recording how its modules were built.
+pub const MANIFEST: &str = ".lisp-desu-manifest";
+
+/// A source of the program and what became of it.
+#[derive(Debug)]
+pub struct File {
+    /// Path of the source, within the source directory.
+    pub path: PathBuf,
+    /// Dotted name of its module.
+    pub module: String,
+    pub src: String,
+    pub status: Status,
+}
+
+#[derive(Debug)]
+pub enum Status {
+    /// Transpiled, with the warnings found.
+    Built(Vec<Diagnostic>),
+    /// Left alone, as nothing it was built from changed.
+    Unchanged,
+    /// Not written, because of the errors among the diagnostics.
+    Failed(Vec<Diagnostic>),
+}
+
+/// Transpile the sources under `root` to modules under `out`, rebuilding
+/// only those whose source, options or imports changed since the last
+/// build, and removing the modules of sources that are gone.
+pub fn build(root: &Path, out: &Path, options: &Options) -> Result<Vec<File>, TranspileError> {
+    let mut options = options.clone();
+    options.library = true;
+
+    let mut files = vec![];
+    for path in sources(root, Path::new(""))? {
+        let full = root.join(&path);
+        let src = fs::read_to_string(&full).map_err(|err| TranspileError::IoError(full, err))?;
+        files.push(File {
+            module: module_name(&path),
+            path,
+            src,
+            status: Status::Built(vec![]),
+        });
+    }
+
+    // Packages and definitions of every module, the first one winning
+    let mut packages = vec![];
+    let mut definitions = vec![];
+    let mut defined = HashMap::new();
+    let mut in_package = HashMap::new();
+    for file in &mut files {
+        let source = root.join(&file.path).display().to_string();
+        let found = match Pythonify::new(&file.src)
+            .with_path(&source)
+            .definitions(&file.module)
+        {
+            Ok(found) => found,
+            Err(err) => {
+                file.status = Status::Failed(err.into_diagnostics()?);
+                continue;
+            }
+        };
+        let mut errors = vec![];
+        for package in found.packages {
+            let name = &package.name;
+            match packages
+                .iter()
+                .find(|(_, other): &&(String, Package)| other.name.name == name.name)
+            {
+                Some((module, _)) => errors.push(Diagnostic::error(
+                    name.span,
+                    format!("package `{}` is already defined in `{module}`", name.name),
+                )),
+                None => packages.push((file.module.clone(), package)),
+            }
+        }
+        for (import, span) in found.names {
+            let key = (
+                found.package.clone(),
+                import.name.clone(),
+                import.params.is_some(),
+            );
+            match defined.get(&key) {
+                Some(module) if *module != file.module => {
+                    errors.push(Diagnostic::error(
+                        span,
+                        format!("`{}` is already defined in `{module}`", import.name),
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    defined.insert(key, file.module.clone());
+                    definitions.push((found.package.clone(), import));
+                }
+            }
+        }
+        in_package.insert(file.module.clone(), found.package);
+        if !errors.is_empty() {
+            file.status = Status::Failed(errors);
+        }
+    }
+    let packages = packages
+        .into_iter()
+        .map(|(_, package)| package)
+        .collect::<Vec<_>>();
+
+    let manifest = Manifest::read(out);
+    let mut built = Manifest::default();
+    let mut runtime = false;
+    for file in &mut files {
+        if matches!(file.status, Status::Failed(_)) {
+            continue;
+        }
+        let target = out.join(module_path(&file.module));
+        let package = in_package[&file.module].as_deref();
+        let visible = visible(&definitions, &packages, package, &file.module);
+        let signatures = visible
+            .iter()
+            .map(|import| {
+                (
+                    dependency(import),
+                    (import.module.as_str(), signature(import)),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let key = |dependencies: &BTreeSet<String>| {
+            let mut text = format!("{}\n{options:?}\n{}\n", env!("CARGO_PKG_VERSION"), file.src);
+            // Packages decide what is visible, exported and required
+            text.push_str(&format!("{packages:?}\n"));
+            for dependency in dependencies {
+                match signatures.get(dependency) {
+                    Some((module, signature)) => {
+                        text.push_str(&format!("{dependency} {module} {signature}\n"))
+                    }
+                    None => text.push_str(&format!("{dependency} undefined\n")),
+                }
+            }
+            hash(&text)
+        };
+        if let Some(entry) = manifest.modules.get(&file.module) {
+            if entry.hash == key(&entry.dependencies) && target.exists() {
+                built.modules.insert(file.module.clone(), entry.clone());
+                runtime |= entry.runtime;
+                file.status = Status::Unchanged;
+                continue;
+            }
+        }
+
+        let source = root.join(&file.path).display().to_string();
+        let transpiled = Pythonify::with_options(&file.src, options.clone())
+            .with_path(&source)
+            .with_imports(&file.module, &visible)
+            .with_packages(&packages)
+            .transpile();
+        let output = match transpiled {
+            Ok(output) => output,
+            Err(err) => {
+                file.status = Status::Failed(err.into_diagnostics()?);
+                continue;
+            }
+        };
+
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).map_err(|err| TranspileError::IoError(dir.to_owned(), err))?;
+        }
+        write(&target, &output.python)?;
+        let map = SourceMap {
+            source,
+            lines: output.spans,
+        };
+        write(&map_path(&target), &map.to_json())?;
+
+        let dependencies = output.imports.iter().map(dependency).collect();
+        let entry = Entry {
+            hash: key(&dependencies),
+            dependencies,
+            runtime: output.imports_runtime,
+        };
+        runtime |= entry.runtime;
+        built.modules.insert(file.module.clone(), entry);
+        file.status = Status::Built(output.warnings);
+    }
+
+    // Modules of sources that are gone
+    for module in manifest.modules.keys() {
+        if !files.iter().any(|file| file.module == *module) {
+            let target = out.join(module_path(module));
+            let _ = fs::remove_file(map_path(&target));
+            let _ = fs::remove_file(target);
+        }
+    }
+
+    fs::create_dir_all(out).map_err(|err| TranspileError::IoError(out.to_owned(), err))?;
+    let packages = files
+        .iter()
+        .map(|file| module_path(&file.module))
+        .filter_map(|path| Some(path.parent()?.to_owned()))
+        .collect::<BTreeSet<_>>();
+    for package in packages {
+        for dir in package.ancestors() {
+            let init = out.join(dir).join("__init__.py");
+            if !init.exists() {
+                write(&init, "")?;
+            }
+        }
+    }
+    if runtime {
+        write(&out.join(RUNTIME_MODULE).with_extension("py"), RUNTIME)?;
+    }
+    built.write(out)?;
+    Ok(files)
+}
+
+/// Paths of the sources under `root.join(dir)`, relative to `root`, in
+/// order.
+fn sources(root: &Path, dir: &Path) -> Result<Vec<PathBuf>, TranspileError> {
+    let full = root.join(dir);
+    let io_error = |err| TranspileError::IoError(full.clone(), err);
+    let mut entries = fs::read_dir(&full)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    entries.sort();
+
+    let mut sources = vec![];
+    for entry in entries {
+        let Some(name) = entry.file_name() else {
+            continue;
+        };
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = dir.join(name);
+        if entry.is_dir() {
+            sources.extend(self::sources(root, &path)?);
+        } else if entry.extension().is_some_and(|ext| ext == "lisu") {
+            sources.push(path);
+        }
+    }
+    Ok(sources)
+}
+
+/// Dotted name of the module transpiled from the source at `path`.
+fn module_name(path: &Path) -> String {
+    path.with_extension("")
+        .components()
+        .map(|component| builtins::python_ident(&component.as_os_str().to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Path of `module` within the output directory.
+fn module_path(module: &str) -> PathBuf {
+    let mut path = module.split('.').collect::<PathBuf>();
+    path.set_extension("py");
+    path
+}
+
+fn map_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_owned();
+    path.push(".map");
+    PathBuf::from(path)
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), TranspileError> {
+    fs::write(path, contents).map_err(|err| TranspileError::IoError(path.to_owned(), err))
+}
+
+/// Definitions of other modules visible to a module of `package`: those of
+/// the same package and the exports of the packages it uses by their names,
+/// and those of other packages qualified by theirs, as `pkg:name` when
+/// exported and `pkg::name` in any case.
+fn visible(
+    definitions: &[(Option<String>, Import)],
+    packages: &[Package],
+    package: Option<&str>,
+    module: &str,
+) -> Vec<Import> {
+    let find = |name: &str| packages.iter().find(|package| package.name.name == name);
+    let exports = |name: &str, import: &Import| {
+        find(name).is_some_and(|package| {
+            package
+                .exports
+                .iter()
+                .any(|export| export.name == import.name.to_lowercase())
+        })
+    };
+    let uses = package
+        .and_then(find)
+        .map(|package| &package.uses[..])
+        .unwrap_or_default();
+
+    let mut visible = vec![];
+    for (owner, import) in definitions {
+        if import.module == module {
+            continue;
+        }
+        let used = owner.as_deref().is_some_and(|owner| {
+            uses.iter().any(|name| name.name == owner) && exports(owner, import)
+        });
+        if owner.as_deref() == package || used {
+            visible.push(import.clone());
+        }
+    }
+    for (owner, import) in definitions {
+        let Some(owner) = owner.as_deref().filter(|owner| Some(*owner) != package) else {
+            continue;
+        };
+        let mut qualified = vec![format!("{owner}::{}", import.name)];
+        if exports(owner, import) {
+            qualified.push(format!("{owner}:{}", import.name));
+        }
+        for alias in qualified {
+            visible.push(Import {
+                alias: Some(alias),
+                ..import.clone()
+            });
+        }
+    }
+    visible
+}
+
+/// Name of `import` in the manifest: `#'f` for a function, `x` for a global.
+fn dependency(import: &Import) -> String {
+    match import.params {
+        Some(_) => format!("#'{}", import.local()),
+        None => import.local().to_owned(),
+    }
+}
+
+/// What modules importing `import` depend on: its parameters, and which
+/// are passed by keyword.
+fn signature(import: &Import) -> String {
+    let Some(params) = &import.params else {
+        return String::new();
+    };
+    let mut words = vec![];
+    words.extend(params.required.iter().map(|name| name.name.clone()));
+    if !params.optional.is_empty() {
+        words.push("&optional".to_owned());
+        words.extend(params.optional.iter().map(|(name, _)| name.name.clone()));
+    }
+    if let Some(rest) = &params.rest {
+        words.push(format!("&rest {}", rest.name));
+    }
+    if !params.key.is_empty() {
+        words.push("&key".to_owned());
+        words.extend(params.key.iter().map(|(name, _)| name.name.clone()));
+    }
+    if params.allow_other_keys {
+        words.push("&allow-other-keys".to_owned());
+    }
+    format!("({})", words.join(" "))
+}
+
+/// 64-bit FNV-1a hash of `text`, stable across runs and platforms.
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Modules of the last build, a line each: the module, the hash it was built
+/// from, whether it imports the runtime, and its dependencies.
+#[derive(Debug, Default)]
+struct Manifest {
+    modules: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    hash: u64,
+    dependencies: BTreeSet<String>,
+    runtime: bool,
+}
+
+impl Manifest {
+    /// The manifest in `out`, or an empty one if it is missing or cannot be
+    /// read, rebuilding everything.
+    fn read(out: &Path) -> Manifest {
+        let mut manifest = Manifest::default();
+        let Ok(text) = fs::read_to_string(out.join(MANIFEST)) else {
+            return manifest;
+        };
+        for line in text.lines() {
+            let mut words = line.split(' ');
+            let (Some(module), Some(hash), Some(runtime)) =
+                (words.next(), words.next(), words.next())
+            else {
+                continue;
+            };
+            let Ok(hash) = u64::from_str_radix(hash, 16) else {
+                continue;
+            };
+            let entry = Entry {
+                hash,
+                dependencies: words.map(str::to_owned).collect(),
+                runtime: runtime == "runtime",
+            };
+            manifest.modules.insert(module.to_owned(), entry);
+        }
+        manifest
+    }
+
+    fn write(&self, out: &Path) -> Result<(), TranspileError> {
+        let mut text = String::new();
+        for (module, entry) in &self.modules {
+            let runtime = if entry.runtime { "runtime" } else { "-" };
+            text.push_str(&format!("{module} {:016x} {runtime}", entry.hash));
+            for dependency in &entry.dependencies {
+                text.push_str(&format!(" {dependency}"));
+            }
+            text.push('\n');
+        }
+        write(&out.join(MANIFEST), &text)
+    }
+}
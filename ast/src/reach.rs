@@ -0,0 +1,192 @@
+//! Dead code: `defun`s unreachable from the program's entry points, and
+//! forms following an unconditional `return` or `return-from`.
+//!
+//! The entry points are the top-level forms other than definitions, and the
+//! function given by `--entry`, along with the functions exported by the
+//! file's package. A file with neither is a library, whose functions are all
+//! kept, as are those of the modules of a directory build, which other
+//! modules may import. Dead code is warned about by the analysis and removed
+//! at `-O1`.
+use crate::diagnostic::Diagnostic;
+use crate::expr::{Expr, ExprKind};
+use crate::package;
+use crate::resolve::{BindingKind, Resolution, Resolved};
+use lexer::Span;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Default)]
+pub struct Reachability {
+    /// Top-level `defun`s that are never called.
+    pub dead: BTreeSet<String>,
+}
+
+pub fn analyse(
+    exprs: &[Expr],
+    resolution: &Resolution,
+    entry: Option<&str>,
+    library: bool,
+) -> (Reachability, Vec<Diagnostic>) {
+    let mut diagnostics = vec![];
+    for expr in exprs {
+        expr.walk(&mut |expr| {
+            for body in bodies(expr) {
+                if let Some(dead) = dead_tail(body) {
+                    diagnostics.push(Diagnostic::warning(
+                        body[dead].span,
+                        "unreachable code after `return`",
+                    ));
+                }
+            }
+        });
+    }
+
+    let mut defuns = HashMap::new();
+    let mut roots = BTreeSet::new();
+    let mut has_entry_forms = false;
+    for expr in exprs {
+        match &expr.kind {
+            ExprKind::Defun(defun) => {
+                defuns.insert(defun.name.name.as_str(), (defun, callees(expr, resolution)));
+            }
+            kind => {
+                has_entry_forms |= !matches!(
+                    kind,
+                    ExprKind::Defvar { .. }
+                        | ExprKind::Declaim { .. }
+                        | ExprKind::Defpackage(_)
+                        | ExprKind::InPackage(_)
+                        | ExprKind::Require(_)
+                );
+                roots.extend(callees(expr, resolution));
+            }
+        }
+    }
+    if let Some(entry) = entry {
+        match defuns.get(entry) {
+            Some((defun, _)) if !defun.params.required.is_empty() => {
+                diagnostics.push(
+                    Diagnostic::error(
+                        defun.name.span,
+                        format!("entry point `{entry}` must not take required arguments"),
+                    )
+                    .with_note(defun.params.span, "parameters declared here"),
+                );
+            }
+            Some(_) => {}
+            None => diagnostics.push(Diagnostic::error(
+                Span::default(),
+                format!("entry point `{entry}` is not defined"),
+            )),
+        }
+        roots.insert(entry);
+    }
+    if let Some(current) = package::current(exprs) {
+        for exported in package::defined(exprs)
+            .filter(|package| package.name.name == current)
+            .flat_map(|package| &package.exports)
+        {
+            roots.insert(exported.name.as_str());
+        }
+    }
+    if library || entry.is_none() && !has_entry_forms {
+        return (Reachability::default(), diagnostics);
+    }
+
+    let mut reached = BTreeSet::new();
+    let mut stack = roots.into_iter().collect::<Vec<_>>();
+    while let Some(name) = stack.pop() {
+        if !reached.insert(name) {
+            continue;
+        }
+        if let Some((_, callees)) = defuns.get(name) {
+            stack.extend(callees.iter().copied());
+        }
+    }
+
+    let mut reachability = Reachability::default();
+    for expr in exprs {
+        let ExprKind::Defun(ref defun) = expr.kind else {
+            continue;
+        };
+        if !reached.contains(defun.name.name.as_str()) {
+            diagnostics.push(Diagnostic::warning(
+                defun.name.span,
+                format!("function `{}` is never called", defun.name.name),
+            ));
+            reachability.dead.insert(defun.name.name.clone());
+        }
+    }
+    (reachability, diagnostics)
+}
+
+/// Remove the dead code found by [`analyse`].
+pub fn eliminate(exprs: &mut Vec<Expr>, reachability: &Reachability) {
+    exprs.retain(|expr| match expr.kind {
+        ExprKind::Defun(ref defun) => !reachability.dead.contains(&defun.name.name),
+        _ => true,
+    });
+    for expr in exprs {
+        truncate(expr);
+    }
+}
+
+fn truncate(expr: &mut Expr) {
+    for body in bodies_mut(expr) {
+        if let Some(dead) = dead_tail(body) {
+            body.truncate(dead);
+        }
+    }
+    for child in expr.children_mut() {
+        truncate(child);
+    }
+}
+
+/// Index of the first form after an unconditional exit from `body`.
+fn dead_tail(body: &[Expr]) -> Option<usize> {
+    let exit = body
+        .iter()
+        .position(|expr| matches!(expr.kind, ExprKind::Return(_) | ExprKind::ReturnFrom { .. }))?;
+    (exit + 1 < body.len()).then_some(exit + 1)
+}
+
+/// Sequences of forms evaluated one after another in `expr`.
+fn bodies(expr: &Expr) -> Vec<&[Expr]> {
+    match &expr.kind {
+        ExprKind::Defun(defun) => vec![&defun.body],
+        ExprKind::Lambda { body, .. }
+        | ExprKind::Progn(body)
+        | ExprKind::Loop(body)
+        | ExprKind::Let { body, .. } => vec![body],
+        ExprKind::Cond(clauses) => clauses.iter().map(|clause| &clause.body[..]).collect(),
+        _ => vec![],
+    }
+}
+
+fn bodies_mut(expr: &mut Expr) -> Vec<&mut Vec<Expr>> {
+    match &mut expr.kind {
+        ExprKind::Defun(defun) => vec![&mut defun.body],
+        ExprKind::Lambda { body, .. }
+        | ExprKind::Progn(body)
+        | ExprKind::Loop(body)
+        | ExprKind::Let { body, .. } => vec![body],
+        ExprKind::Cond(clauses) => clauses.iter_mut().map(|clause| &mut clause.body).collect(),
+        _ => vec![],
+    }
+}
+
+/// Names of the `defun`s called or referred to with `#'` in `expr`.
+fn callees<'e>(expr: &'e Expr, resolution: &Resolution) -> BTreeSet<&'e str> {
+    let mut callees = BTreeSet::new();
+    expr.walk(&mut |expr| {
+        let func = match &expr.kind {
+            ExprKind::Call { func, .. } | ExprKind::Function(func) => func,
+            _ => return,
+        };
+        if let Some(Resolved::Binding(id)) = resolution.get(func.span) {
+            if resolution.binding(id).kind == BindingKind::Function {
+                callees.insert(func.name.as_str());
+            }
+        }
+    });
+    callees
+}
@@ -0,0 +1,464 @@
+//! Name resolution.
+//!
+//! Works out what each symbol in the program refers to: a parameter, a `let`
+//! binding, a global from `defvar` or `import`, a `defun`, a definition
+//! imported from another module or a builtin. Python names given as
+//! `py:name` are left to Python. Unbound variables, calls to undefined
+//! functions and parameters named twice are reported as errors; unused
+//! bindings and bindings shadowing an enclosing one are warned about.
+use crate::builtins::{self, Builtin};
+use crate::diagnostic::Diagnostic;
+use crate::expr::{Defun, Expr, ExprKind, Ident, LambdaList};
+use lexer::Span;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindingId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Param,
+    Let,
+    Global,
+    Function,
+}
+
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: String,
+    /// Span of the name where it is bound.
+    pub span: Span,
+    pub kind: BindingKind,
+    /// Module defining the binding, for definitions imported from another
+    /// module.
+    pub module: Option<String>,
+}
+
+/// Top-level `defun` or `defvar` of another module of the program.
+#[derive(Debug, Clone)]
+pub struct Import {
+    /// Name in the module defining it.
+    pub name: String,
+    /// Name the importing module refers to it by, when qualified by its
+    /// package as `pkg:name`.
+    pub alias: Option<String>,
+    /// Dotted name of the Python module defining it.
+    pub module: String,
+    /// Lambda list of a function, or `None` for a global.
+    pub params: Option<LambdaList>,
+}
+
+impl Import {
+    /// Name the importing module refers to it by.
+    pub fn local(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Resolved {
+    Binding(BindingId),
+    Builtin(&'static Builtin),
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub bindings: Vec<Binding>,
+    /// What each variable reference, function name and binding site refers
+    /// to, keyed by the span of the symbol.
+    pub references: HashMap<Span, Resolved>,
+    /// Definitions of other modules the program refers to.
+    pub imports: Vec<Import>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolution {
+    /// Resolve `exprs`, which may refer to the definitions of `imports`
+    /// their own do not shadow.
+    pub fn resolve(exprs: &[Expr], imports: &[Import]) -> Resolution {
+        let mut resolver = Resolver::default();
+        resolver.declare_globals(exprs);
+        let imported = imports
+            .iter()
+            .filter_map(|import| Some((resolver.import(import)?, import)))
+            .collect::<Vec<_>>();
+        for expr in exprs {
+            resolver.expr(expr);
+        }
+        resolver.res.imports = imported
+            .into_iter()
+            .filter(|(id, _)| resolver.used[id.0])
+            .map(|(_, import)| import.clone())
+            .collect();
+        resolver.res
+    }
+
+    pub fn get(&self, span: Span) -> Option<Resolved> {
+        self.references.get(&span).copied()
+    }
+
+    pub fn binding(&self, id: BindingId) -> &Binding {
+        &self.bindings[id.0]
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+#[derive(Default)]
+struct Resolver {
+    res: Resolution,
+    /// Local variables in scope, innermost last.
+    scopes: Vec<Vec<(String, BindingId)>>,
+    globals: HashMap<String, BindingId>,
+    functions: HashMap<String, BindingId>,
+    used: Vec<bool>,
+    /// Names of the enclosing `defun`s, which `return-from` can leave.
+    blocks: Vec<String>,
+    /// Number of `loop`s enclosing the current form within its function.
+    loops: usize,
+}
+
+impl Resolver {
+    /// Globals and functions may be referred to before their definition,
+    /// such as from the body of an earlier `defun`.
+    fn declare_globals(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            expr.walk(&mut |expr| match &expr.kind {
+                ExprKind::Defun(defun) => {
+                    if let Some(&prev) = self.functions.get(&defun.name.name) {
+                        let prev = self.res.binding(prev).span;
+                        self.res.diagnostics.push(
+                            Diagnostic::warning(
+                                defun.name.span,
+                                format!("redefinition of function `{}`", defun.name.name),
+                            )
+                            .with_note(prev, "previously defined here"),
+                        );
+                    }
+                    let id = self.bind(&defun.name, BindingKind::Function);
+                    self.functions.insert(defun.name.name.clone(), id);
+                }
+                ExprKind::Defvar { name, .. } if !self.globals.contains_key(&name.name) => {
+                    let id = self.bind(name, BindingKind::Global);
+                    self.globals.insert(name.name.clone(), id);
+                }
+                // `(import os.path)` binds `os`
+                ExprKind::Import(modules) => {
+                    for module in modules {
+                        let name = Ident {
+                            name: module.name.split('.').next().unwrap_or_default().to_owned(),
+                            span: module.span,
+                        };
+                        if !self.globals.contains_key(&name.name) {
+                            let id = self.bind(&name, BindingKind::Global);
+                            self.globals.insert(name.name, id);
+                        }
+                    }
+                }
+                _ => {}
+            });
+        }
+    }
+
+    /// Bind `import`, unless the program defines its name itself.
+    fn import(&mut self, import: &Import) -> Option<BindingId> {
+        let (names, kind) = match import.params {
+            Some(_) => (&mut self.functions, BindingKind::Function),
+            None => (&mut self.globals, BindingKind::Global),
+        };
+        if names.contains_key(import.local()) {
+            return None;
+        }
+        let id = BindingId(self.res.bindings.len());
+        names.insert(import.local().to_owned(), id);
+        self.res.bindings.push(Binding {
+            name: import.local().to_owned(),
+            span: Span::default(),
+            kind,
+            module: Some(import.module.clone()),
+        });
+        self.used.push(false);
+        Some(id)
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Symbol(name) => self.variable(name, expr.span),
+            ExprKind::Function(func) => self.function(func),
+            ExprKind::Call { func, args } => {
+                self.function(func);
+                self.exprs(args);
+            }
+            ExprKind::Defun(defun) => self.defun(defun),
+            ExprKind::Declaim { inline, notinline } => {
+                for func in inline.iter().chain(notinline) {
+                    self.function(func);
+                }
+            }
+            ExprKind::Lambda { params, body } => {
+                let blocks = std::mem::take(&mut self.blocks);
+                let loops = std::mem::take(&mut self.loops);
+                self.scope(params, |resolver| resolver.exprs(body));
+                self.blocks = blocks;
+                self.loops = loops;
+            }
+            ExprKind::Let {
+                bindings,
+                body,
+                sequential,
+            } => {
+                self.scopes.push(vec![]);
+                for (name, value) in bindings {
+                    if *sequential {
+                        self.expr(value);
+                    } else {
+                        // Values of a parallel `let` cannot see its bindings
+                        let scope = self.scopes.pop();
+                        self.expr(value);
+                        self.scopes.extend(scope);
+                    }
+                    self.bind_local(name, BindingKind::Let);
+                }
+                self.exprs(body);
+                self.pop_scope();
+            }
+            ExprKind::Setq(pairs) => {
+                for (name, value) in pairs {
+                    self.expr(value);
+                    self.assign(name);
+                }
+            }
+            ExprKind::Defvar { name, value } => {
+                if let Some(&id) = self.globals.get(&name.name) {
+                    self.res.references.insert(name.span, Resolved::Binding(id));
+                }
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Loop(body) => {
+                self.loops += 1;
+                self.exprs(body);
+                self.loops -= 1;
+            }
+            ExprKind::Return(value) => {
+                if self.loops == 0 {
+                    self.error(expr.span, "`return` outside of a `loop`");
+                }
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            ExprKind::ReturnFrom { name, value } => {
+                if !self.blocks.contains(&name.name) {
+                    self.error(
+                        name.span,
+                        format!("`return-from` has no enclosing function `{}`", name.name),
+                    );
+                }
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            _ => self.exprs(expr.children()),
+        }
+    }
+
+    fn exprs<'e>(&mut self, exprs: impl IntoIterator<Item = &'e Expr>) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn defun(&mut self, defun: &Defun) {
+        let blocks = std::mem::replace(&mut self.blocks, vec![defun.name.name.clone()]);
+        let loops = std::mem::take(&mut self.loops);
+        self.scope(&defun.params, |resolver| {
+            for (name, _) in &defun.declarations {
+                resolver.declaration(defun, name);
+            }
+            resolver.exprs(&defun.body)
+        });
+        self.blocks = blocks;
+        self.loops = loops;
+    }
+
+    /// Declarations name parameters of the `defun` they are in.
+    fn declaration(&mut self, defun: &Defun, name: &Ident) {
+        let param = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.iter().find(|(bound, _)| *bound == name.name));
+        match param {
+            Some(&(_, id)) => {
+                self.res.references.insert(name.span, Resolved::Binding(id));
+            }
+            None => self.error(
+                name.span,
+                format!(
+                    "`{}` is declared but is not a parameter of `{}`",
+                    name.name, defun.name.name
+                ),
+            ),
+        }
+    }
+
+    fn variable(&mut self, name: &str, span: Span) {
+        // Keywords evaluate to themselves, and Python names are left to Python
+        if name.starts_with(':') || name.starts_with("py:") {
+            return;
+        }
+        match self.lookup(name) {
+            Some(id) => {
+                self.used[id.0] = true;
+                self.res.references.insert(span, Resolved::Binding(id));
+            }
+            None => self.error(span, format!("unbound variable `{name}`")),
+        }
+    }
+
+    fn function(&mut self, func: &Ident) {
+        if func.name.starts_with("py:") {
+            return;
+        }
+        if let Some(&id) = self.functions.get(&func.name) {
+            self.used[id.0] = true;
+            self.res.references.insert(func.span, Resolved::Binding(id));
+        } else if let Some(builtin) = builtins::lookup(&func.name) {
+            self.res
+                .references
+                .insert(func.span, Resolved::Builtin(builtin));
+        } else {
+            self.error(func.span, format!("undefined function `{}`", func.name));
+        }
+    }
+
+    fn assign(&mut self, name: &Ident) {
+        match self.lookup(&name.name) {
+            Some(id) => match &self.res.binding(id).module {
+                // The assignment would only rebind the importing module's name
+                Some(module) => {
+                    let message = format!(
+                        "cannot assign to `{}`, which is imported from `{module}`",
+                        name.name
+                    );
+                    self.error(name.span, message);
+                }
+                None => {
+                    self.res.references.insert(name.span, Resolved::Binding(id));
+                }
+            },
+            None => {
+                self.res.diagnostics.push(Diagnostic::warning(
+                    name.span,
+                    format!("assignment to undefined variable `{}`", name.name),
+                ));
+                // Treated as a global from here on, as `setq` lowers to one
+                let id = self.bind(name, BindingKind::Global);
+                self.used[id.0] = true;
+                self.globals.insert(name.name.clone(), id);
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<BindingId> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(bound, _)| bound == name)
+            .map(|&(_, id)| id)
+            .or_else(|| self.globals.get(name).copied())
+    }
+
+    /// Run `f` in a new scope binding the parameters of `params`. Default
+    /// values can refer to the parameters before them.
+    fn scope(&mut self, params: &LambdaList, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(vec![]);
+        for name in &params.required {
+            self.bind_local(name, BindingKind::Param);
+        }
+        for (name, default) in &params.optional {
+            if let Some(default) = default {
+                self.expr(default);
+            }
+            self.bind_local(name, BindingKind::Param);
+        }
+        if let Some(rest) = &params.rest {
+            self.bind_local(rest, BindingKind::Param);
+        }
+        for (name, default) in &params.key {
+            if let Some(default) = default {
+                self.expr(default);
+            }
+            self.bind_local(name, BindingKind::Param);
+        }
+        f(self);
+        self.pop_scope();
+    }
+
+    fn bind_local(&mut self, name: &Ident, kind: BindingKind) {
+        let outer = self
+            .scopes
+            .iter()
+            .flatten()
+            .rev()
+            .find(|(bound, _)| *bound == name.name)
+            .map(|&(_, id)| id);
+        let scope = self.scopes.last().expect("locals are bound inside a scope");
+        let twice = scope.iter().any(|(bound, _)| *bound == name.name);
+        if let (Some(outer), true, BindingKind::Param) = (outer, twice, kind) {
+            let outer = self.res.binding(outer).span;
+            self.res.diagnostics.push(
+                Diagnostic::error(name.span, format!("duplicate parameter `{}`", name.name))
+                    .with_note(outer, "previous parameter here"),
+            );
+        } else if let Some(outer) = outer {
+            let outer = self.res.binding(outer).span;
+            self.res.diagnostics.push(
+                Diagnostic::warning(
+                    name.span,
+                    format!("`{}` shadows an outer binding", name.name),
+                )
+                .with_note(outer, "previous binding here"),
+            );
+        }
+        let id = self.bind(name, kind);
+        self.scopes
+            .last_mut()
+            .expect("locals are bound inside a scope")
+            .push((name.name.clone(), id));
+    }
+
+    fn bind(&mut self, name: &Ident, kind: BindingKind) -> BindingId {
+        let id = BindingId(self.res.bindings.len());
+        self.res.bindings.push(Binding {
+            name: name.name.clone(),
+            span: name.span,
+            kind,
+            module: None,
+        });
+        self.res.references.insert(name.span, Resolved::Binding(id));
+        self.used.push(false);
+        id
+    }
+
+    /// Leave the innermost scope, warning about bindings it never used.
+    fn pop_scope(&mut self) {
+        for (name, id) in self.scopes.pop().unwrap_or_default() {
+            if !self.used[id.0] && !name.starts_with('_') {
+                let span = self.res.binding(id).span;
+                self.res.diagnostics.push(Diagnostic::warning(
+                    span,
+                    format!("unused variable `{name}`"),
+                ));
+            }
+        }
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.res.diagnostics.push(Diagnostic::error(span, message));
+    }
+}
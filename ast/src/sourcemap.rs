@@ -0,0 +1,107 @@
+//! Maps from the lines of a generated module back to the Lisp forms they
+//! were lowered from, written next to the module as `<module>.py.map`.
+//!
+//! The map is a JSON object holding the path of the Lisp source and an entry
+//! per line of Python, either `null` for lines lowered from no form, such as
+//! the runtime, or the span as `[start_row, start_col, end_row, end_col]`:
+//!
+//! ```json
+//! {"version": 1, "source": "fact.lisu", "lines": [null, [1, 1, 3, 20]]}
+//! ```
+use crate::json::{self, Json};
+use lexer::Span;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Path of the Lisp source, as given when transpiling.
+    pub source: String,
+    /// Span of each line of Python, or the default span for lines lowered
+    /// from no form.
+    pub lines: Vec<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceMapError {
+    /// The map is not valid JSON.
+    Json,
+    /// The map is JSON, but missing or mistyping `field`.
+    Field(&'static str),
+    UnsupportedVersion(u64),
+}
+
+const VERSION: u64 = 1;
+
+impl SourceMap {
+    /// Span of the 1-based Python `line`, unless it was lowered from no form.
+    pub fn span(&self, line: usize) -> Option<Span> {
+        let span = *self.lines.get(line.checked_sub(1)?)?;
+        (span != Span::default()).then_some(span)
+    }
+
+    /// Serialise, with a line of JSON per line of Python.
+    pub fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\"version\": {VERSION}, \"source\": {}, \"lines\": [",
+            json::string(&self.source)
+        );
+        for (i, span) in self.lines.iter().enumerate() {
+            out.push_str(if i == 0 { "\n  " } else { ",\n  " });
+            match *span == Span::default() {
+                true => out.push_str("null"),
+                false => out.push_str(&format!(
+                    "[{}, {}, {}, {}]",
+                    span.start_row, span.start_col, span.end_row, span.end_col
+                )),
+            }
+        }
+        out.push_str("\n]}\n");
+        out
+    }
+
+    pub fn from_json(json: &str) -> Result<SourceMap, SourceMapError> {
+        let value = Json::parse(json).ok_or(SourceMapError::Json)?;
+        let Json::Object(_) = value else {
+            return Err(SourceMapError::Json);
+        };
+        let field = |name: &'static str| value.get(name).ok_or(SourceMapError::Field(name));
+
+        match field("version")?.as_u64() {
+            Some(VERSION) => {}
+            Some(version) => return Err(SourceMapError::UnsupportedVersion(version)),
+            None => return Err(SourceMapError::Field("version")),
+        }
+        let Json::String(source) = field("source")? else {
+            return Err(SourceMapError::Field("source"));
+        };
+        let Json::Array(entries) = field("lines")? else {
+            return Err(SourceMapError::Field("lines"));
+        };
+        let lines = entries
+            .iter()
+            .map(|entry| match entry {
+                Json::Null => Some(Span::default()),
+                Json::Array(numbers) => {
+                    let numbers = numbers
+                        .iter()
+                        .map(|number| Some(number.as_u64()? as usize))
+                        .collect::<Option<Vec<_>>>()?;
+                    match numbers[..] {
+                        [start_row, start_col, end_row, end_col] => Some(Span {
+                            start_row,
+                            start_col,
+                            end_row,
+                            end_col,
+                        }),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(SourceMapError::Field("lines"))?;
+        Ok(SourceMap {
+            source: source.clone(),
+            lines,
+        })
+    }
+}
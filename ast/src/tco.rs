@@ -0,0 +1,109 @@
+//! Tail call analysis for `defun`s.
+//!
+//! Self tail calls are rewritten by the lowering pass into a `while True:`
+//! loop that reassigns the parameters. Groups of functions that tail call
+//! each other can additionally be run through the runtime's trampoline.
+use crate::expr::{Defun, Expr, ExprKind};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Names of the functions called in tail position of `defun`'s body.
+///
+/// Tail positions are followed through `if`, `cond`, `progn`, `let` and
+/// `return-from` the function itself, but not into `loop`s, whose exits the
+/// lowering pass implements with `break`.
+pub fn tail_callees(defun: &Defun) -> BTreeSet<&str> {
+    let mut callees = BTreeSet::new();
+    if let Some(last) = defun.body.last() {
+        collect_tail_calls(last, &defun.name.name, &mut callees);
+    }
+    for expr in &defun.body {
+        collect_return_froms(expr, &defun.name.name, &mut callees);
+    }
+    callees
+}
+
+pub fn has_self_tail_call(defun: &Defun) -> bool {
+    tail_callees(defun).contains(defun.name.name.as_str())
+}
+
+/// Names of the functions that tail call each other in a cycle of at least
+/// two functions, and so need the trampoline to run in constant stack space.
+pub fn mutual_tail_calls<'a>(defuns: &[&'a Defun]) -> BTreeSet<&'a str> {
+    let graph = defuns
+        .iter()
+        .map(|defun| (defun.name.name.as_str(), tail_callees(defun)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut mutual = BTreeSet::new();
+    for (&name, callees) in &graph {
+        // Reachable from `name` through tail calls to other functions
+        let mut seen = BTreeSet::new();
+        let mut stack = callees
+            .iter()
+            .copied()
+            .filter(|callee| *callee != name)
+            .collect::<Vec<_>>();
+        while let Some(callee) = stack.pop() {
+            if !seen.insert(callee) {
+                continue;
+            }
+            if let Some(next) = graph.get(callee) {
+                stack.extend(next.iter().copied());
+            }
+        }
+        if seen.contains(name) {
+            mutual.insert(name);
+        }
+    }
+    mutual
+}
+
+fn collect_tail_calls<'e>(expr: &'e Expr, name: &str, callees: &mut BTreeSet<&'e str>) {
+    match &expr.kind {
+        ExprKind::Call { func, .. } => {
+            callees.insert(&func.name);
+        }
+        ExprKind::If {
+            then, otherwise, ..
+        } => {
+            collect_tail_calls(then, name, callees);
+            if let Some(otherwise) = otherwise {
+                collect_tail_calls(otherwise, name, callees);
+            }
+        }
+        ExprKind::Cond(clauses) => {
+            for clause in clauses {
+                if let Some(last) = clause.body.last() {
+                    collect_tail_calls(last, name, callees);
+                }
+            }
+        }
+        ExprKind::Progn(body) | ExprKind::Let { body, .. } => {
+            if let Some(last) = body.last() {
+                collect_tail_calls(last, name, callees);
+            }
+        }
+        ExprKind::ReturnFrom {
+            name: block,
+            value: Some(value),
+        } if block.name == name => collect_tail_calls(value, name, callees),
+        _ => {}
+    }
+}
+
+/// `(return-from name (f ...))` is a tail call wherever it appears, as long
+/// as it is not inside a `loop` or a nested function.
+fn collect_return_froms<'e>(expr: &'e Expr, name: &str, callees: &mut BTreeSet<&'e str>) {
+    match &expr.kind {
+        ExprKind::ReturnFrom {
+            name: block,
+            value: Some(value),
+        } if block.name == name => collect_tail_calls(value, name, callees),
+        ExprKind::Loop(_) | ExprKind::Lambda { .. } | ExprKind::Defun(_) => {}
+        _ => {
+            for child in expr.children() {
+                collect_return_froms(child, name, callees);
+            }
+        }
+    }
+}
@@ -0,0 +1,1078 @@
+//! Type inference.
+//!
+//! A unification based pass inferring `int`, `float`, `str`, `bool`, list and
+//! function types. Each `defun` is inferred before the functions calling it
+//! and its type generalised, so functions such as `(defun id (x) x)` can be
+//! called with different types. Lisp is dynamically typed, so only obvious
+//! mistakes are errors, such as `(+ "a" 1)` or passing a string to a
+//! function that does arithmetic on its parameter; values that could have
+//! several types are typed `Any` instead.
+use crate::diagnostic::Diagnostic;
+use crate::expr::{Datum, Defun, Expr, ExprKind, Ident, LambdaList, TypeSpec};
+use crate::resolve::{BindingId, BindingKind, Resolution, Resolved};
+use lexer::Span;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+    Bool,
+    /// `nil`, which fits anywhere a value may be missing.
+    Null,
+    /// A value of the inner type, or `nil`.
+    Nullable(Box<Type>),
+    List(Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    Any,
+    /// Not yet known.
+    Var(usize),
+}
+
+impl Type {
+    pub fn nullable(self) -> Type {
+        match self {
+            Type::Null | Type::Any | Type::Nullable(_) => self,
+            ty => Type::Nullable(Box::new(ty)),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    fn may_be_numeric(&self) -> bool {
+        match self {
+            Type::Nullable(inner) => inner.may_be_numeric(),
+            ty => ty.is_numeric() || matches!(ty, Type::Any | Type::Var(_)),
+        }
+    }
+
+    /// Name used in diagnostics.
+    pub fn describe(&self) -> String {
+        match self {
+            Type::Int => "integer".to_string(),
+            Type::Float => "float".to_string(),
+            Type::Str => "string".to_string(),
+            Type::Bool => "boolean".to_string(),
+            Type::Null => "nil".to_string(),
+            Type::Nullable(ty) => format!("{} or nil", ty.describe()),
+            Type::List(_) => "list".to_string(),
+            Type::Function(..) => "function".to_string(),
+            Type::Any | Type::Var(_) => "any value".to_string(),
+        }
+    }
+
+    /// Python annotation, or `None` when nothing is known about the type.
+    /// Names that need importing from `typing` are added to `imports`.
+    pub fn python(&self, imports: &mut BTreeSet<&'static str>) -> Option<String> {
+        Some(match self {
+            Type::Int => "int".to_string(),
+            Type::Float => "float".to_string(),
+            Type::Str => "str".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Null => "None".to_string(),
+            Type::Nullable(ty) => format!("{} | None", ty.python(imports)?),
+            Type::List(ty) => format!("list[{}]", ty.python_or_any(imports)),
+            Type::Function(params, ret) => {
+                imports.insert("Callable");
+                let params = params
+                    .iter()
+                    .map(|param| param.python_or_any(imports))
+                    .collect::<Vec<_>>();
+                format!(
+                    "Callable[[{}], {}]",
+                    params.join(", "),
+                    ret.python_or_any(imports)
+                )
+            }
+            Type::Any | Type::Var(_) => return None,
+        })
+    }
+
+    fn python_or_any(&self, imports: &mut BTreeSet<&'static str>) -> String {
+        self.python(imports).unwrap_or_else(|| {
+            imports.insert("Any");
+            "Any".to_string()
+        })
+    }
+}
+
+impl From<&TypeSpec> for Type {
+    fn from(spec: &TypeSpec) -> Type {
+        match spec {
+            TypeSpec::Integer => Type::Int,
+            TypeSpec::Float => Type::Float,
+            TypeSpec::String => Type::Str,
+            TypeSpec::Boolean => Type::Bool,
+            TypeSpec::List => Type::List(Box::new(Type::Any)),
+            TypeSpec::Function => Type::Any,
+            TypeSpec::Null => Type::Null,
+            TypeSpec::T => Type::Any,
+            TypeSpec::OrNull(spec) => Type::from(&**spec).nullable(),
+        }
+    }
+}
+
+/// Inferred types, for backends emitting annotations.
+#[derive(Debug, Default)]
+pub struct Types {
+    bindings: HashMap<BindingId, Type>,
+    /// Types given by `declare`.
+    declared: HashMap<BindingId, Type>,
+    functions: HashMap<String, Type>,
+}
+
+impl Types {
+    /// Type of the parameter, `let` binding or global bound at `ident`.
+    pub fn binding(&self, resolution: &Resolution, ident: &Ident) -> Option<&Type> {
+        match resolution.get(ident.span)? {
+            Resolved::Binding(id) => self.bindings.get(&id),
+            Resolved::Builtin(_) => None,
+        }
+    }
+
+    /// Declared type of the parameter bound at `ident`.
+    pub fn declared(&self, resolution: &Resolution, ident: &Ident) -> Option<&Type> {
+        match resolution.get(ident.span)? {
+            Resolved::Binding(id) => self.declared.get(&id),
+            Resolved::Builtin(_) => None,
+        }
+    }
+
+    /// Function type of the `defun` named `name`.
+    pub fn function(&self, name: &str) -> Option<&Type> {
+        self.functions.get(name)
+    }
+}
+
+pub fn infer(exprs: &[Expr], resolution: &Resolution) -> (Types, Vec<Diagnostic>) {
+    let mut infer = Infer {
+        resolution,
+        subst: vec![],
+        bindings: HashMap::new(),
+        declared: HashMap::new(),
+        defuns: HashMap::new(),
+        order: vec![],
+        functions: HashMap::new(),
+        numeric: vec![],
+        returns: vec![],
+        loops: vec![],
+        diagnostics: vec![],
+    };
+    for expr in exprs {
+        expr.walk(&mut |expr| {
+            if let ExprKind::Defun(ref defun) = expr.kind {
+                infer.defuns.insert(defun.name.name.clone(), defun);
+                infer.order.push(defun);
+            }
+        });
+    }
+
+    for defun in infer.order.clone() {
+        infer.defun(defun);
+    }
+    for expr in exprs {
+        infer.expr(expr);
+    }
+    infer.finish()
+}
+
+/// Type of a `defun`, with the type variables it is generic over.
+#[derive(Debug, Clone)]
+enum Scheme {
+    /// Being inferred; recursive calls share its type.
+    InProgress(Type),
+    Done(Vec<usize>, Type),
+}
+
+/// Arguments of an arithmetic operator, which must be numbers.
+struct Numeric {
+    op: String,
+    args: Vec<(Type, Span)>,
+    result: Type,
+}
+
+struct Infer<'a> {
+    resolution: &'a Resolution,
+    /// What each type variable has been unified with.
+    subst: Vec<Option<Type>>,
+    bindings: HashMap<BindingId, Type>,
+    declared: HashMap<BindingId, Type>,
+    defuns: HashMap<String, &'a Defun>,
+    /// Every `defun`, in source order.
+    order: Vec<&'a Defun>,
+    functions: HashMap<String, Scheme>,
+    /// Arithmetic whose operands were not all known when it was inferred.
+    numeric: Vec<Numeric>,
+    /// Types returned by the enclosing functions, innermost last.
+    returns: Vec<Type>,
+    /// Types returned from the enclosing `loop`s, innermost last.
+    loops: Vec<Type>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Infer<'a> {
+    fn expr(&mut self, expr: &'a Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Nil
+            | ExprKind::Declaim { .. }
+            | ExprKind::Defpackage(_)
+            | ExprKind::InPackage(_)
+            | ExprKind::Require(_)
+            | ExprKind::Import(_) => Type::Null,
+            // Python objects are not typed
+            ExprKind::MethodCall { .. } | ExprKind::Attribute { .. } => {
+                for child in expr.children() {
+                    self.expr(child);
+                }
+                Type::Any
+            }
+            ExprKind::T => Type::Bool,
+            ExprKind::Number(n) => number(n),
+            ExprKind::Str(_) => Type::Str,
+            // Keywords lower to strings
+            ExprKind::Symbol(name) if name.starts_with(':') => Type::Str,
+            ExprKind::Symbol(_) => match self.resolution.get(expr.span) {
+                Some(Resolved::Binding(id)) => self.binding(id),
+                _ => Type::Any,
+            },
+            ExprKind::Function(func) => match self.defuns.contains_key(&func.name) {
+                true => self.function(&func.name),
+                false => Type::Any,
+            },
+            ExprKind::Quote(datum) => datum_type(datum),
+            ExprKind::Call { func, args } => self.call(func, args),
+            ExprKind::Funcall { func, args } => {
+                let func = self.expr(func);
+                let args = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+                let ret = self.fresh();
+                let call = Type::Function(args, Box::new(ret.clone()));
+                match self.unify(&func, &call) {
+                    true => ret,
+                    false => Type::Any,
+                }
+            }
+            ExprKind::Defun(defun) => {
+                self.defun(defun);
+                Type::Str
+            }
+            ExprKind::Lambda { params, body } => {
+                let params = self.params(params);
+                let returned = self.fresh();
+                self.returns.push(returned);
+                let loops = std::mem::take(&mut self.loops);
+                let ret = self.body(body);
+                self.loops = loops;
+                let returned = self.returns.pop().expect("pushed above");
+                let ret = self.join(&ret, &returned);
+                Type::Function(params, Box::new(ret))
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.expr(cond);
+                let then = self.expr(then);
+                let otherwise = match otherwise {
+                    Some(otherwise) => self.expr(otherwise),
+                    None => Type::Null,
+                };
+                self.join(&then, &otherwise)
+            }
+            ExprKind::Cond(clauses) => {
+                let mut ty = None;
+                let mut exhaustive = false;
+                for clause in clauses {
+                    let test = self.expr(&clause.test);
+                    let value = match clause.body.is_empty() {
+                        true => test,
+                        false => self.body(&clause.body),
+                    };
+                    ty = Some(match ty {
+                        Some(ty) => self.join(&ty, &value),
+                        None => value,
+                    });
+                    exhaustive |= matches!(clause.test.kind, ExprKind::T);
+                }
+                match (ty, exhaustive) {
+                    (Some(ty), true) => ty,
+                    (Some(ty), false) => self.join(&ty, &Type::Null),
+                    (None, _) => Type::Null,
+                }
+            }
+            ExprKind::Progn(body) => self.body(body),
+            ExprKind::Let { bindings, body, .. } => {
+                for (name, value) in bindings {
+                    let ty = self.expr(value);
+                    self.bind(name, ty);
+                }
+                self.body(body)
+            }
+            ExprKind::Setq(pairs) => {
+                let mut ty = Type::Null;
+                for (name, value) in pairs {
+                    ty = self.expr(value);
+                    self.assign(name, &ty);
+                }
+                ty
+            }
+            ExprKind::Defvar { name, value } => {
+                if let Some(value) = value {
+                    let ty = self.expr(value);
+                    self.assign(name, &ty);
+                }
+                Type::Str
+            }
+            // The operands are tested rather than joined, so they are not
+            // unified with each other
+            ExprKind::And(operands) => {
+                let types = operands.iter().map(|operand| self.expr(operand));
+                match types.collect::<Vec<_>>().as_slice() {
+                    [] => Type::Bool,
+                    [ty] => ty.clone(),
+                    [.., last] => last.clone().nullable(),
+                }
+            }
+            ExprKind::Or(operands) => {
+                let types = operands.iter().map(|operand| self.expr(operand));
+                let types = types.collect::<Vec<_>>();
+                let Some(last) = types.last() else {
+                    return Type::Null;
+                };
+                let last = self.resolve(last);
+                match types.iter().all(|ty| self.resolve(ty) == last) {
+                    true => last,
+                    false => Type::Any,
+                }
+            }
+            ExprKind::Loop(body) => {
+                self.loops.push(Type::Null);
+                for expr in body {
+                    self.expr(expr);
+                }
+                self.loops.pop().expect("pushed above")
+            }
+            ExprKind::Return(value) => {
+                let ty = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Null,
+                };
+                if let Some(returned) = self.loops.pop() {
+                    let returned = self.join(&returned, &ty);
+                    self.loops.push(returned);
+                }
+                Type::Any
+            }
+            ExprKind::ReturnFrom { value, .. } => {
+                let ty = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Null,
+                };
+                if let Some(returned) = self.returns.pop() {
+                    let returned = self.join(&returned, &ty);
+                    self.returns.push(returned);
+                }
+                Type::Any
+            }
+            ExprKind::The { ty, value } => {
+                let declared = Type::from(ty);
+                let ty = self.expr(value);
+                let expected = article(&declared.describe());
+                self.expect(value.span, "the", &expected, &ty, &declared);
+                declared
+            }
+        }
+    }
+
+    fn body(&mut self, body: &'a [Expr]) -> Type {
+        let mut ty = Type::Null;
+        for expr in body {
+            ty = self.expr(expr);
+            // The forms after an unconditional exit are never evaluated, and
+            // the body has no value of its own
+            if let ExprKind::Return(_) | ExprKind::ReturnFrom { .. } = expr.kind {
+                return self.fresh();
+            }
+        }
+        ty
+    }
+
+    fn defun(&mut self, defun: &'a Defun) {
+        if self.functions.contains_key(&defun.name.name) {
+            return;
+        }
+        let ret = self.fresh();
+        let params = self.params(&defun.params);
+        for (name, spec) in &defun.declarations {
+            self.declare(name, spec);
+        }
+        let ty = Type::Function(params, Box::new(ret.clone()));
+        let name = defun.name.name.clone();
+        self.functions
+            .insert(name.clone(), Scheme::InProgress(ty.clone()));
+
+        let returned = self.fresh();
+        self.returns.push(returned);
+        let loops = std::mem::take(&mut self.loops);
+        let value = self.body(&defun.body);
+        self.loops = loops;
+        let returned = self.returns.pop().expect("pushed above");
+        let value = self.join(&value, &returned);
+        self.unify(&ret, &value);
+
+        let generic = self.generic_vars(&name, &ty);
+        self.functions.insert(name, Scheme::Done(generic, ty));
+    }
+
+    /// Bind the parameters of a lambda list to fresh types, returning the
+    /// types of the positional ones.
+    fn params(&mut self, params: &'a LambdaList) -> Vec<Type> {
+        let mut positional = vec![];
+        for name in &params.required {
+            let ty = self.fresh();
+            self.bind(name, ty.clone());
+            positional.push(ty);
+        }
+        for (name, default) in params.optional.iter().chain(&params.key) {
+            let ty = self.fresh();
+            if let Some(default) = default {
+                let default = self.expr(default);
+                self.unify(&ty, &default);
+            }
+            self.bind(name, ty.clone());
+            if params.key.iter().all(|(key, _)| key.span != name.span) {
+                positional.push(ty);
+            }
+        }
+        if let Some(rest) = &params.rest {
+            let elem = self.fresh();
+            self.bind(rest, Type::List(Box::new(elem)));
+        }
+        positional
+    }
+
+    fn declare(&mut self, name: &Ident, spec: &TypeSpec) {
+        let declared = Type::from(spec);
+        let Some(Resolved::Binding(id)) = self.resolution.get(name.span) else {
+            return;
+        };
+        let ty = self.binding(id);
+        if !self.unify(&ty, &declared) {
+            let ty = self.resolve_deep(&ty);
+            self.diagnostics.push(Diagnostic::error(
+                name.span,
+                format!(
+                    "`{}` is declared {} but its default is {}",
+                    name.name,
+                    article(&declared.describe()),
+                    article(&ty.describe())
+                ),
+            ));
+        }
+        self.declared.insert(id, declared);
+    }
+
+    fn call(&mut self, func: &'a Ident, args: &'a [Expr]) -> Type {
+        if let Some(&defun) = self.defuns.get(&func.name) {
+            return self.defun_call(defun, args);
+        }
+        let name = func.name.as_str();
+        match name {
+            "+" | "-" | "*" | "/" | "mod" | "1+" | "1-" | "max" | "min" | "abs" | "random" => {
+                self.arithmetic(name, args)
+            }
+            "<" | ">" | "<=" | ">=" | "=" | "/=" | "zerop" => {
+                self.arithmetic(name, args);
+                Type::Bool
+            }
+            "eq" | "eql" | "equal" | "not" | "null" => {
+                self.exprs(args);
+                Type::Bool
+            }
+            "car" | "nth" | "cdr" | "reverse" => {
+                let elem = self.fresh();
+                let list = Type::List(Box::new(elem.clone()));
+                let (index, list_arg) = match (name, args) {
+                    ("nth", [n, list]) => (Some(n), list),
+                    (_, [list]) => (None, list),
+                    _ => {
+                        self.exprs(args);
+                        return Type::Any;
+                    }
+                };
+                if let Some(index) = index {
+                    self.arithmetic(name, std::slice::from_ref(index));
+                }
+                let ty = self.expr(list_arg);
+                if let Type::Null = self.resolve(&ty) {
+                    return Type::Null;
+                }
+                if !self.unify(&list, &ty) {
+                    self.mismatch(list_arg.span, name, "a list", &ty);
+                    return Type::Any;
+                }
+                match name {
+                    "car" | "nth" => elem,
+                    _ => list,
+                }
+            }
+            "cons" => {
+                let [car, cdr] = args else {
+                    self.exprs(args);
+                    return Type::Any;
+                };
+                let car = self.expr(car);
+                let cdr = self.expr(cdr);
+                let list = Type::List(Box::new(car));
+                match self.resolve(&cdr) {
+                    Type::Null => list,
+                    _ if self.unify(&list, &cdr) => list,
+                    Type::List(_) | Type::Var(_) => Type::List(Box::new(Type::Any)),
+                    // An improper list
+                    _ => Type::Any,
+                }
+            }
+            "list" | "append" => {
+                let mut elem: Option<Type> = None;
+                for arg in args {
+                    let ty = self.expr(arg);
+                    let ty = match name {
+                        "list" => ty,
+                        _ => match self.resolve(&ty) {
+                            Type::List(elem) => *elem,
+                            Type::Null => continue,
+                            _ => Type::Any,
+                        },
+                    };
+                    elem = Some(match elem {
+                        Some(elem) => self.join(&elem, &ty),
+                        None => ty,
+                    });
+                }
+                Type::List(Box::new(elem.unwrap_or_else(|| self.fresh())))
+            }
+            "length" => {
+                for arg in args {
+                    let ty = self.expr(arg);
+                    match self.resolve(&ty) {
+                        Type::Int | Type::Float | Type::Bool | Type::Function(..) => {
+                            self.mismatch(arg.span, name, "a list or string", &ty)
+                        }
+                        _ => {}
+                    }
+                }
+                Type::Int
+            }
+            "concatenate" => {
+                let [result_type, seqs @ ..] = args else {
+                    return Type::Any;
+                };
+                let is_string = matches!(
+                    result_type.kind,
+                    ExprKind::Quote(Datum::Symbol(ref ty)) if ty == "string"
+                );
+                if !is_string {
+                    self.exprs(args);
+                    return Type::Any;
+                }
+                for seq in seqs {
+                    let ty = self.expr(seq);
+                    self.expect(seq.span, name, "strings", &ty, &Type::Str);
+                }
+                Type::Str
+            }
+            "parse-integer" => {
+                for arg in args {
+                    let ty = self.expr(arg);
+                    self.expect(arg.span, name, "a string", &ty, &Type::Str);
+                }
+                Type::Int
+            }
+            "print" | "princ" => match args {
+                [arg] => self.expr(arg),
+                _ => {
+                    self.exprs(args);
+                    Type::Any
+                }
+            },
+            "read-line" | "write-to-string" => {
+                self.exprs(args);
+                Type::Str
+            }
+            "format" => {
+                self.exprs(args);
+                match args.first().map(|dest| &dest.kind) {
+                    Some(ExprKind::Nil) => Type::Str,
+                    _ => Type::Null,
+                }
+            }
+            "terpri" | "finish-output" => {
+                self.exprs(args);
+                Type::Null
+            }
+            _ => {
+                self.exprs(args);
+                Type::Any
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &'a [Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    /// Check the arguments of a call to a `defun` against its parameters.
+    fn defun_call(&mut self, defun: &'a Defun, args: &'a [Expr]) -> Type {
+        let Type::Function(params, ret) = self.function(&defun.name.name) else {
+            unreachable!("defuns have function types");
+        };
+        let positional = defun
+            .params
+            .required
+            .iter()
+            .chain(defun.params.optional.iter().map(|(name, _)| name))
+            .zip(params);
+        let mut args = args.iter();
+        for ((name, param), arg) in positional.zip(args.by_ref()) {
+            let ty = self.expr(arg);
+            self.param_mismatch(defun, name, &param, arg, &ty);
+        }
+
+        let rest = args.as_slice();
+        let mut keywords = rest.chunks_exact(2);
+        if !defun.params.key.is_empty() {
+            for pair in keywords.by_ref() {
+                let ty = self.expr(&pair[1]);
+                let ExprKind::Symbol(ref keyword) = pair[0].kind else {
+                    continue;
+                };
+                let param = defun
+                    .params
+                    .key
+                    .iter()
+                    .find(|(name, _)| Some(name.name.as_str()) == keyword.strip_prefix(':'));
+                if let Some((name, _)) = param {
+                    if let Some(param) = self.binding_of(name) {
+                        self.param_mismatch(defun, name, &param, &pair[1], &ty);
+                    }
+                }
+            }
+            self.exprs(keywords.remainder());
+        } else {
+            self.exprs(rest);
+        }
+        *ret
+    }
+
+    fn param_mismatch(&mut self, defun: &Defun, name: &Ident, param: &Type, arg: &Expr, ty: &Type) {
+        let ty = self.resolve_deep(ty);
+        // Blame the call rather than the arithmetic in the body
+        let expected = match self.resolve(param) {
+            Type::Var(_) if self.is_numeric_var(param) && !ty.may_be_numeric() => {
+                "a number".to_string()
+            }
+            _ if self.unify(param, &ty) => return,
+            param => article(&self.resolve_deep(&param).describe()),
+        };
+        self.diagnostics.push(
+            Diagnostic::error(
+                arg.span,
+                format!(
+                    "`{}` expects {expected} for `{}`, got {}",
+                    defun.name.name,
+                    name.name,
+                    ty.describe()
+                ),
+            )
+            .with_note(name.span, format!("`{}` is declared here", name.name)),
+        );
+    }
+
+    /// Whether `ty` is still unknown but used as an operand of arithmetic.
+    fn is_numeric_var(&self, ty: &Type) -> bool {
+        let ty = self.resolve(ty);
+        self.numeric
+            .iter()
+            .flat_map(|numeric| &numeric.args)
+            .any(|(arg, _)| self.resolve(arg) == ty)
+    }
+
+    /// Arithmetic on `args`, whose result is a float if any of them is.
+    fn arithmetic(&mut self, op: &str, args: &'a [Expr]) -> Type {
+        let mut operands = vec![];
+        for arg in args {
+            let ty = self.expr(arg);
+            let resolved = self.resolve(&ty);
+            match resolved {
+                Type::Int | Type::Float | Type::Any | Type::Var(_) => operands.push((ty, arg.span)),
+                Type::Nullable(ref inner) if inner.may_be_numeric() => {
+                    operands.push((ty, arg.span))
+                }
+                _ => self.mismatch(arg.span, op, "numbers", &ty),
+            }
+        }
+        let result = self.fresh();
+        self.numeric.push(Numeric {
+            op: op.to_string(),
+            args: operands,
+            result: result.clone(),
+        });
+        self.settle_numeric();
+        self.resolve(&result)
+    }
+
+    /// Work out the results of arithmetic whose operands have become known,
+    /// reporting operands that turned out not to be numbers.
+    fn settle_numeric(&mut self) {
+        let mut progress = true;
+        while progress {
+            progress = false;
+            let numeric = std::mem::take(&mut self.numeric);
+            for constraint in numeric {
+                let mut failed = false;
+                let mut known = true;
+                let mut any = false;
+                let mut float = constraint.op == "/";
+                for (arg, span) in &constraint.args {
+                    let ty = match self.resolve_deep(arg) {
+                        Type::Nullable(inner) => *inner,
+                        ty => ty,
+                    };
+                    match ty {
+                        Type::Int => {}
+                        Type::Float => float = true,
+                        Type::Var(_) => known = false,
+                        Type::Any => any = true,
+                        ty => {
+                            self.mismatch(*span, &constraint.op, "numbers", &ty);
+                            failed = true;
+                        }
+                    }
+                }
+                let result = match (any, known, float) {
+                    (true, _, _) => Some(Type::Any),
+                    (_, false, _) => None,
+                    (_, _, true) => Some(Type::Float),
+                    _ => Some(Type::Int),
+                };
+                match (failed, result) {
+                    (true, _) => {
+                        self.unify(&constraint.result, &Type::Any);
+                    }
+                    (false, Some(result)) => {
+                        self.unify(&constraint.result, &result);
+                        progress = true;
+                    }
+                    (false, None) => self.numeric.push(constraint),
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> (Types, Vec<Diagnostic>) {
+        self.settle_numeric();
+        let bindings = std::mem::take(&mut self.bindings)
+            .into_iter()
+            .map(|(id, ty)| (id, self.resolve_deep(&ty)))
+            .collect();
+        let functions = std::mem::take(&mut self.functions)
+            .into_iter()
+            .map(|(name, scheme)| match scheme {
+                Scheme::InProgress(ty) | Scheme::Done(_, ty) => (name, self.resolve_deep(&ty)),
+            })
+            .collect();
+        let types = Types {
+            bindings,
+            declared: std::mem::take(&mut self.declared),
+            functions,
+        };
+        (types, self.diagnostics)
+    }
+
+    /// Type of a call to `name`, instantiating its generic type variables.
+    fn function(&mut self, name: &str) -> Type {
+        if !self.functions.contains_key(name) {
+            let defun = self.defuns[name];
+            self.defun(defun);
+        }
+        match self.functions[name].clone() {
+            Scheme::InProgress(ty) => ty,
+            Scheme::Done(generic, ty) => {
+                let fresh = generic
+                    .into_iter()
+                    .map(|var| (var, self.fresh()))
+                    .collect::<HashMap<_, _>>();
+                let ty = self.resolve_deep(&ty);
+                instantiate(&ty, &fresh)
+            }
+        }
+    }
+
+    /// Type variables of the type `ty` of `name` that the program does not
+    /// constrain elsewhere: not shared with a global, an enclosing function
+    /// or pending arithmetic.
+    fn generic_vars(&mut self, name: &str, ty: &Type) -> Vec<usize> {
+        let mut pinned = HashSet::new();
+        let constrained = self
+            .numeric
+            .iter()
+            .flat_map(|numeric| {
+                numeric
+                    .args
+                    .iter()
+                    .map(|(ty, _)| ty)
+                    .chain(std::iter::once(&numeric.result))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let outer = self
+            .functions
+            .iter()
+            .filter_map(|(function, scheme)| match scheme {
+                Scheme::InProgress(ty) if function != name => Some(ty.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let globals = self
+            .bindings
+            .iter()
+            .filter(|(id, _)| self.resolution.binding(**id).kind == BindingKind::Global)
+            .map(|(_, ty)| ty.clone())
+            .collect::<Vec<_>>();
+        for ty in constrained.iter().chain(&outer).chain(&globals) {
+            free_vars(&self.resolve_deep(ty), &mut pinned);
+        }
+
+        let mut vars = HashSet::new();
+        free_vars(&self.resolve_deep(ty), &mut vars);
+        let mut vars = vars.difference(&pinned).copied().collect::<Vec<_>>();
+        vars.sort();
+        vars
+    }
+
+    fn binding(&mut self, id: BindingId) -> Type {
+        match self.bindings.get(&id) {
+            Some(ty) => ty.clone(),
+            None => {
+                // A global used before its `defvar` is inferred
+                let ty = self.fresh();
+                self.bindings.insert(id, ty.clone());
+                ty
+            }
+        }
+    }
+
+    fn binding_of(&mut self, ident: &Ident) -> Option<Type> {
+        match self.resolution.get(ident.span)? {
+            Resolved::Binding(id) => Some(self.binding(id)),
+            Resolved::Builtin(_) => None,
+        }
+    }
+
+    fn bind(&mut self, ident: &Ident, ty: Type) {
+        if let Some(Resolved::Binding(id)) = self.resolution.get(ident.span) {
+            self.bindings.insert(id, ty);
+        }
+    }
+
+    /// Assign to a variable, which becomes `Any` if it is given values of
+    /// different types. A variable holding nil, such as an accumulator,
+    /// widens to whatever is assigned to it.
+    fn assign(&mut self, ident: &Ident, ty: &Type) {
+        let Some(current) = self.binding_of(ident) else {
+            return;
+        };
+        let widened = match self.resolve(&current) {
+            Type::Null => Some(ty.clone().nullable()),
+            _ if self.unify(&current, ty) => None,
+            _ => Some(Type::Any),
+        };
+        if let (Some(ty), Some(Resolved::Binding(id))) = (widened, self.resolution.get(ident.span))
+        {
+            self.bindings.insert(id, ty);
+        }
+    }
+
+    /// Report an operand of `op` that is not `expected`.
+    fn expect(&mut self, span: Span, op: &str, what: &str, ty: &Type, expected: &Type) {
+        if !self.unify(ty, expected) {
+            self.mismatch(span, op, what, ty);
+        }
+    }
+
+    fn mismatch(&mut self, span: Span, op: &str, expected: &str, ty: &Type) {
+        let ty = self.resolve_deep(ty);
+        self.diagnostics.push(Diagnostic::error(
+            span,
+            format!("`{op}` expects {expected}, got {}", ty.describe()),
+        ));
+    }
+
+    /// Type of a value that is one of `a` or `b`, such as the branches of an
+    /// `if`.
+    fn join(&mut self, a: &Type, b: &Type) -> Type {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Null, _) => b.clone().nullable(),
+            (_, Type::Null) => a.clone().nullable(),
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Type::Float,
+            _ if self.unify(a, b) => a.clone(),
+            _ => Type::Any,
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() - 1)
+    }
+
+    /// Follow type variables until reaching a type that is not one, or one
+    /// that is not yet known.
+    fn resolve(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(var) = ty {
+            match &self.subst[var] {
+                Some(next) => ty = next.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    fn resolve_deep(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Nullable(ty) => self.resolve_deep(&ty).nullable(),
+            Type::List(ty) => Type::List(Box::new(self.resolve_deep(&ty))),
+            Type::Function(params, ret) => Type::Function(
+                params
+                    .iter()
+                    .map(|param| self.resolve_deep(param))
+                    .collect(),
+                Box::new(self.resolve_deep(&ret)),
+            ),
+            ty => ty,
+        }
+    }
+
+    /// Unify `a` and `b`, leaving the substitution unchanged if they cannot
+    /// be.
+    fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        let snapshot = self.subst.clone();
+        let unified = self.unify_inner(a, b);
+        if !unified {
+            self.subst = snapshot;
+        }
+        unified
+    }
+
+    fn unify_inner(&mut self, a: &Type, b: &Type) -> bool {
+        // A variable holding an integer widens to a float
+        for (var, other) in [(a, b), (b, a)] {
+            if let Type::Var(var) = *var {
+                if self.resolve(&Type::Var(var)) == Type::Int && self.resolve(other) == Type::Float
+                {
+                    self.widen(var);
+                    return true;
+                }
+            }
+        }
+
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Var(x), Type::Var(y)) if x == y => true,
+            (Type::Var(x), ty) | (ty, Type::Var(x)) => {
+                let mut vars = HashSet::new();
+                free_vars(&self.resolve_deep(&ty), &mut vars);
+                if vars.contains(&x) {
+                    return false;
+                }
+                self.subst[x] = Some(ty);
+                true
+            }
+            (Type::Any, _) | (_, Type::Any) | (Type::Null, _) | (_, Type::Null) => true,
+            (Type::Nullable(a), b) | (b, Type::Nullable(a)) => self.unify_inner(&a, &b),
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) => true,
+            (Type::List(a), Type::List(b)) => self.unify_inner(&a, &b),
+            (Type::Function(pa, ra), Type::Function(pb, rb)) => {
+                pa.len() == pb.len()
+                    && pa.iter().zip(&pb).all(|(a, b)| self.unify_inner(a, b))
+                    && self.unify_inner(&ra, &rb)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Make the variable chain ending in an integer end in a float instead.
+    fn widen(&mut self, mut var: usize) {
+        while let Some(Type::Var(next)) = self.subst[var] {
+            var = next;
+        }
+        self.subst[var] = Some(Type::Float);
+    }
+}
+
+fn number(text: &str) -> Type {
+    match text.contains(['.', 'e', 'E']) {
+        true => Type::Float,
+        false => Type::Int,
+    }
+}
+
+fn datum_type(datum: &Datum) -> Type {
+    match datum {
+        Datum::Nil => Type::Null,
+        Datum::Number(n) => number(n),
+        Datum::Str(_) => Type::Str,
+        Datum::Symbol(s) if s == "t" => Type::Bool,
+        Datum::Symbol(_) => Type::Str,
+        Datum::Quote(_) => Type::List(Box::new(Type::Any)),
+        Datum::List(items, _) if !datum.is_list() || items.is_empty() => Type::Any,
+        Datum::List(items, _) => {
+            let first = datum_type(&items[0]);
+            let same = items.iter().all(|item| datum_type(item) == first);
+            Type::List(Box::new(if same { first } else { Type::Any }))
+        }
+    }
+}
+
+fn free_vars(ty: &Type, vars: &mut HashSet<usize>) {
+    match ty {
+        Type::Var(var) => {
+            vars.insert(*var);
+        }
+        Type::Nullable(ty) | Type::List(ty) => free_vars(ty, vars),
+        Type::Function(params, ret) => {
+            for param in params {
+                free_vars(param, vars);
+            }
+            free_vars(ret, vars);
+        }
+        _ => {}
+    }
+}
+
+fn instantiate(ty: &Type, fresh: &HashMap<usize, Type>) -> Type {
+    match ty {
+        Type::Var(var) => fresh.get(var).cloned().unwrap_or(Type::Var(*var)),
+        Type::Nullable(ty) => Type::Nullable(Box::new(instantiate(ty, fresh))),
+        Type::List(ty) => Type::List(Box::new(instantiate(ty, fresh))),
+        Type::Function(params, ret) => Type::Function(
+            params
+                .iter()
+                .map(|param| instantiate(param, fresh))
+                .collect(),
+            Box::new(instantiate(ret, fresh)),
+        ),
+        ty => ty.clone(),
+    }
+}
+
+fn article(noun: &str) -> String {
+    match noun.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        true => format!("an {noun}"),
+        false => format!("a {noun}"),
+    }
+}
@@ -0,0 +1,92 @@
+//! Tests of arity checking.
+use ast::arity;
+use ast::Pythonify;
+
+/// Messages of checking the calls in `src`.
+fn errors(src: &str) -> Vec<String> {
+    let (exprs, _) = Pythonify::new(src).analyse().expect("reads");
+    arity::check(&exprs, &[])
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect()
+}
+
+#[test]
+fn checks_required_arguments() {
+    let src = "(defun f (a b) (+ a b))\n(print (f 1))\n(print (f 1 2))\n(print (f 1 2 3))";
+    assert_eq!(
+        errors(src),
+        [
+            "`f` expects 2 arguments, got 1",
+            "`f` expects 2 arguments, got 3"
+        ]
+    );
+}
+
+#[test]
+fn checks_optional_arguments() {
+    let src = "(defun f (a &optional b) (list a b))\n(f)\n(f 1)\n(f 1 2)\n(f 1 2 3)";
+    assert_eq!(
+        errors(src),
+        [
+            "`f` expects 1 to 2 arguments, got 0",
+            "`f` expects 1 to 2 arguments, got 3"
+        ]
+    );
+}
+
+#[test]
+fn checks_rest_arguments() {
+    let src = "(defun f (a &rest r) (cons a r))\n(f)\n(f 1)\n(f 1 2 3 4)";
+    assert_eq!(errors(src), ["`f` expects at least 1 argument, got 0"]);
+}
+
+#[test]
+fn checks_keyword_arguments() {
+    let src = "\
+(defun f (&key a) a)
+(f :a 1)
+(f :b 1)
+(f :a)
+(f 1 2)";
+    assert_eq!(
+        errors(src),
+        [
+            "unknown keyword argument `:b` to `f`",
+            "odd number of keyword arguments to `f`",
+            "expected a keyword argument to `f`"
+        ]
+    );
+    assert!(errors("(defun f (&key a &allow-other-keys) a)\n(f :b 1)").is_empty());
+}
+
+#[test]
+fn notes_where_the_function_is_defined() {
+    let src = "(defun f (a) a)\n(f)";
+    let (exprs, _) = Pythonify::new(src).analyse().expect("reads");
+    let diagnostics = arity::check(&exprs, &[]);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].notes[0].message, "`f` is defined here");
+}
+
+#[test]
+fn checks_builtin_arguments() {
+    let src = "(print (1+))\n(print (car 1 2))\n(print (mod 7))\n(print (list))\n(print (+ 1 2 3))";
+    assert_eq!(
+        errors(src),
+        [
+            "`1+` expects 1 argument, got 0",
+            "`car` expects 1 argument, got 2",
+            "`mod` expects 2 arguments, got 1"
+        ]
+    );
+    assert_eq!(
+        errors("(format)"),
+        ["`format` expects at least 2 arguments, got 0"]
+    );
+}
+
+#[test]
+fn checks_functions_named_like_builtins_by_their_lambda_list() {
+    assert!(errors("(defun car (a b) (+ a b))\n(print (car 1 2))").is_empty());
+}
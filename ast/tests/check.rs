@@ -0,0 +1,42 @@
+//! Tests of checking sources without transpiling them.
+use ast::{Pythonify, TranspileError};
+
+fn messages(result: Result<Vec<ast::diagnostic::Diagnostic>, TranspileError>) -> Vec<String> {
+    let diagnostics = match result {
+        Ok(diagnostics) => diagnostics,
+        Err(err) => err.into_diagnostics().expect("reports diagnostics"),
+    };
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.message.clone())
+        .collect()
+}
+
+#[test]
+fn returns_warnings_of_valid_sources() {
+    let src = "(defun f (x unused) (+ x 1))\n(print (f 1 2))\n";
+    let result = Pythonify::new(src).check();
+    assert!(result.is_ok());
+    assert_eq!(messages(result), ["unused variable `unused`"]);
+}
+
+#[test]
+fn reports_lowering_errors_with_warnings() {
+    let src = "(defun f (x) 1)\n(format t \"~q\" (f 1))\n";
+    let result = Pythonify::new(src).check();
+    assert!(matches!(result, Err(TranspileError::Diagnostics(_))));
+    assert_eq!(
+        messages(result),
+        [
+            "unused variable `x`",
+            "invalid `format` control string: unknown directive `~q`"
+        ]
+    );
+}
+
+#[test]
+fn reports_read_errors() {
+    let result = Pythonify::new("(if)\n").check();
+    assert!(result.is_err());
+    assert_eq!(messages(result).len(), 1);
+}
@@ -0,0 +1,46 @@
+//! Tests of the dumps printed by `--emit`.
+use ast::emit::Stage;
+use ast::Pythonify;
+
+fn emit(src: &str, stage: Stage) -> String {
+    Pythonify::new(src).emit(stage).expect("emits")
+}
+
+#[test]
+fn tokens() {
+    let expected = "\
+1:1-1:1      OpenParen        \"(\"
+1:2-1:2      Ident            \"f\"
+1:4-1:6      Literal          \"\\\"a\\\"\"
+1:7-1:7      CloseParen       \")\"
+1:9-1:11     LineComment      \"; c\"
+";
+    assert_eq!(emit("(f \"a\") ; c\n", Stage::Tokens), expected);
+}
+
+#[test]
+fn sexpr() {
+    assert_eq!(
+        emit("(print  '(a . b)\n  (list 1 ()))", Stage::Sexpr),
+        "(print '(a . b) (list 1 ()))\n"
+    );
+}
+
+#[test]
+fn ast() {
+    let expected = "\
+Defun neg 1:1-1:21
+  params 1:12-1:14
+    required n
+  body
+    Call - 1:16-1:20
+      Symbol n 1:19-1:19
+Call print 2:1-2:15
+  Call neg 2:8-2:14
+    Number 3 2:13-2:13
+";
+    assert_eq!(
+        emit("(defun neg (n) (- n))\n(print (neg 3))", Stage::Ast),
+        expected
+    );
+}
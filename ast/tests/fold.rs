@@ -0,0 +1,86 @@
+//! Snapshot tests of constant folding. Set `UPDATE_SNAPSHOTS=1` to write the
+//! snapshots in `tests/snapshots` from the current output.
+use ast::{Options, Prelude, Pythonify};
+use std::{env, fs, path::Path};
+
+fn transpile(src: &str, opt_level: u8) -> String {
+    let options = Options {
+        prelude: Prelude::File,
+        opt_level,
+        ..Options::default()
+    };
+    Pythonify::with_options(src, options)
+        .transpile()
+        .expect("transpiles")
+        .python
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.py"));
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() || !path.exists() {
+        fs::write(&path, actual).expect("writes snapshot");
+        return;
+    }
+    let expected = fs::read_to_string(&path).expect("reads snapshot");
+    assert_eq!(expected, actual, "snapshot {} differs", path.display());
+}
+
+#[test]
+fn arithmetic() {
+    let src = r#"
+(defvar *size* (* 4 (+ 1 2)))
+(print (+ 1 2 (- 10 3) (1+ 4) (mod -7 3)))
+(print (/ 7 2) (* 1.5 2) (- 5))
+(declaim (notinline area))
+(defun area (r) (* 3.14 r r (/ 1 2)))
+(print (area 2) (/ 1 0))
+"#;
+    assert_snapshot("fold_arithmetic", &transpile(src, 1));
+}
+
+#[test]
+fn strings() {
+    let src = r#"
+(print (concatenate 'string "foo" "bar" "baz"))
+(print (concatenate 'string "foo" (read-line)))
+"#;
+    assert_snapshot("fold_strings", &transpile(src, 1));
+}
+
+#[test]
+fn conditions() {
+    let src = r#"
+(defvar *x* (read-line))
+(print (if t "yes" "no"))
+(print (if nil "yes"))
+(print (if (< 1 2 3) "ordered" "unordered"))
+(print (not nil) (not 1) (null "a"))
+(print (cond (nil 1) ((> 1 2) 2) (t 3) (*x* 4)))
+(print (cond (nil 1) (*x* 2) (t 3) (*x* 4)))
+(print (if 0 "zero" "not zero"))
+(print (if '0 "zero" "not zero") (if '"" "empty" "not empty") (if '1.5 "one" "none"))
+"#;
+    assert_snapshot("fold_conditions", &transpile(src, 1));
+}
+
+#[test]
+fn progn() {
+    let src = r#"
+(print (progn (+ 1 1)))
+(print (if t (progn (print "taken") 1) 2))
+(print (when (> 2 1) "when"))
+"#;
+    assert_snapshot("fold_progn", &transpile(src, 1));
+}
+
+#[test]
+fn no_folding_at_o0() {
+    let src = r#"
+(print (+ 1 2))
+(print (if t "yes" "no"))
+(print (progn (not nil)))
+"#;
+    assert_snapshot("fold_o0", &transpile(src, 0));
+}
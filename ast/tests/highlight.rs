@@ -0,0 +1,76 @@
+//! Tests of the classes given to tokens for highlighting.
+use ast::highlight::{highlight, Class};
+
+/// Classes of the tokens of `src` other than whitespace, with their text.
+fn classes(src: &str) -> Vec<(&str, Class)> {
+    highlight(src)
+        .into_iter()
+        .map(|(range, class)| (&src[range], class))
+        .filter(|(text, _)| !text.trim().is_empty())
+        .collect()
+}
+
+#[test]
+fn symbols_are_classed_by_what_they_name() {
+    let src = "(defvar *n* 2)\n(defun scale (x) (let ((y *n*)) (* x y)))\n(print (scale 3))";
+    let symbols = classes(src)
+        .into_iter()
+        .filter(|(_, class)| !matches!(class, Class::Paren(_)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        symbols,
+        [
+            ("defvar", Class::Keyword),
+            ("*n*", Class::Variable),
+            ("2", Class::Number),
+            ("defun", Class::Keyword),
+            ("scale", Class::Function),
+            ("x", Class::Parameter),
+            ("let", Class::Keyword),
+            ("y", Class::Variable),
+            ("*n*", Class::Variable),
+            ("*", Class::Builtin),
+            ("x", Class::Parameter),
+            ("y", Class::Variable),
+            ("print", Class::Builtin),
+            ("scale", Class::Function),
+            ("3", Class::Number),
+        ]
+    );
+}
+
+#[test]
+fn parens_are_classed_by_depth() {
+    let depths = classes("(a (b (c)) d) ; (x)")
+        .into_iter()
+        .filter_map(|(text, class)| match class {
+            Class::Paren(depth) => Some((text, depth)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        depths,
+        [("(", 0), ("(", 1), ("(", 2), (")", 2), (")", 1), (")", 0)]
+    );
+}
+
+#[test]
+fn unreadable_sources_are_classed_by_the_lexer() {
+    let src = "(list 'if :key \"s\" &rest nil ; note\n  (if";
+    assert_eq!(
+        classes(src),
+        [
+            ("(", Class::Paren(0)),
+            ("list", Class::Symbol),
+            ("'", Class::Quote),
+            ("if", Class::Keyword),
+            (":key", Class::Constant),
+            ("\"s\"", Class::String),
+            ("&rest", Class::Keyword),
+            ("nil", Class::Constant),
+            ("; note", Class::Comment),
+            ("(", Class::Paren(1)),
+            ("if", Class::Keyword),
+        ]
+    );
+}
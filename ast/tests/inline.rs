@@ -0,0 +1,127 @@
+//! Tests of inlining at `-O1`.
+use ast::{Options, Prelude, Pythonify};
+
+fn transpile(src: &str, checked: bool) -> String {
+    let options = Options {
+        prelude: Prelude::File,
+        checked,
+        ..Options::default()
+    };
+    Pythonify::with_options(src, options)
+        .transpile()
+        .expect("transpiles")
+        .python
+}
+
+#[test]
+fn substitutes_local_variables() {
+    let src = "\
+(defun add1 (n) (+ n 1))
+(defun f (x) (print (add1 x)))
+(f (py:int \"2\"))";
+    assert_eq!(
+        transpile(src, false),
+        "def f(x):\n    return print(x + 1)\n\nf(int(\"2\"))\n"
+    );
+}
+
+#[test]
+fn binds_arguments_evaluated_once_and_in_order() {
+    let src = "\
+(defun twice (n) (+ n n))
+(defun swap (a b) (list b a))
+(defun main ()
+  (print (twice (py:input)))
+  (print (swap (py:input) (py:input))))
+(main)";
+    assert_eq!(
+        transpile(src, false),
+        "\
+def main():
+    n = input()
+    _t1 = n + n
+    print(_t1)
+    a = input()
+    b = input()
+    _t2 = [b, a]
+    return print(_t2)
+
+main()
+"
+    );
+}
+
+#[test]
+fn binds_parameters_the_body_assigns() {
+    let src = "\
+(defun next (n) (setq n (+ n 1)) n)
+(defun f (x) (print (next x)) x)
+(f (py:int \"1\"))";
+    let python = transpile(src, false);
+    assert!(python.contains("    n = x\n    n = n + 1\n"), "{python}");
+}
+
+#[test]
+fn binds_variables_the_body_would_capture() {
+    let src = "\
+(defun k (n) (let ((m 2)) (+ n m)))
+(defun f (m) (print (k m)))
+(f (py:int \"1\"))";
+    let python = transpile(src, false);
+    assert!(
+        python.contains("    n = m\n    m_1 = 2\n    _t1 = n + m_1\n"),
+        "{python}"
+    );
+}
+
+#[test]
+fn leaves_calls_outside_functions() {
+    let src = "\
+(defvar n 5)
+(defun add1 (n) (+ n 1))
+(print (add1 (py:int \"2\")))
+(print n)";
+    assert_eq!(
+        transpile(src, false),
+        "\
+n = 5
+
+def add1(n_1):
+    return n_1 + 1
+
+print(add1(int(\"2\")))
+print(n)
+"
+    );
+}
+
+#[test]
+fn keeps_declared_types_when_checked() {
+    let src = "\
+(defun twice (x)
+  (declare (type integer x))
+  (* x 2))
+(defun main () (print (twice (py:int \"5\"))))
+(main)";
+    assert!(!transpile(src, false).contains("assert"));
+    let python = transpile(src, true);
+    assert!(!python.contains("def twice"), "{python}");
+    assert!(
+        python.contains(
+            "    assert isinstance(_t2, int), f\"The value {_t2!r} is not of type integer\"\n    x: int = _t2\n"
+        ),
+        "{python}"
+    );
+}
+
+#[test]
+fn leaves_recursive_and_notinline_functions() {
+    let src = "\
+(defun fact (n) (if (= n 0) 1 (* n (fact (- n 1)))))
+(declaim (notinline add1))
+(defun add1 (n) (+ n 1))
+(print (fact 5) (add1 1))";
+    let python = transpile(src, false);
+    assert!(python.contains("def fact(n):"), "{python}");
+    assert!(python.contains("def add1(n):"), "{python}");
+}
@@ -0,0 +1,77 @@
+//! Tests of calling Python from Lisp.
+use ast::{Pythonify, TranspileError};
+
+fn python(src: &str) -> String {
+    Pythonify::new(src).transpile().expect("transpiles").python
+}
+
+fn error(src: &str) -> String {
+    match Pythonify::new(src).transpile() {
+        Err(TranspileError::SyntaxError(err)) => format!("{:?}", err.kind),
+        other => panic!("expected a syntax error, got {other:?}"),
+    }
+}
+
+#[test]
+fn imports_modules_and_calls_their_functions() {
+    let src = "(import random os.path)\n(print (py:random.randint 1 100))\n\
+               (print (py:os.path.join \"a\" \"b\"))\n";
+    assert_eq!(
+        python(src),
+        "import random\nimport os.path\nprint(random.randint(1, 100))\n\
+         print(os.path.join(\"a\", \"b\"))\n"
+    );
+}
+
+#[test]
+fn imported_modules_are_variables() {
+    assert_eq!(
+        python("(import math)\n(print (. math pi))\n"),
+        "import math\nprint(math.pi)\n"
+    );
+}
+
+#[test]
+fn calls_methods_and_reads_attributes() {
+    let src = "(defvar xs (list 1 2))\n(.append xs 3)\n(print (. (.copy xs) __class__))\n\
+               (print (.bit_length 5))\n";
+    assert_eq!(
+        python(src),
+        "xs = [1, 2]\nxs.append(3)\nprint(xs.copy().__class__)\nprint((5).bit_length())\n"
+    );
+}
+
+#[test]
+fn passes_keyword_arguments_by_name() {
+    let src = "(import json)\n(print (py:json.dumps (list 1) :indent 2 :sort-keys t))\n\
+               (print (.split \"a b\" :maxsplit 1))\n";
+    assert_eq!(
+        python(src),
+        "import json\nprint(json.dumps([1], indent=2, sort_keys=True))\n\
+         print(\"a b\".split(maxsplit=1))\n"
+    );
+}
+
+#[test]
+fn python_functions_are_values() {
+    assert_eq!(
+        python("(print (funcall #'py:len \"abc\"))\n"),
+        "print(len(\"abc\"))\n"
+    );
+}
+
+#[test]
+fn reports_malformed_python_calls() {
+    assert_eq!(
+        error("(py:f 1 :a)\n"),
+        "Malformed { form: \"call\", expected: \"a value after each keyword argument\" }"
+    );
+    assert_eq!(
+        error("(. x 3)\n"),
+        "Malformed { form: \".\", expected: \"a Python name\" }"
+    );
+    assert_eq!(
+        error("(import \"os\")\n"),
+        "Malformed { form: \"import\", expected: \"a Python name\" }"
+    );
+}
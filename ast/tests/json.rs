@@ -0,0 +1,31 @@
+//! Tests of the JSON read from and written for editors and source maps.
+use ast::json::Json;
+
+#[test]
+fn messages_round_trip() {
+    let text = r#"{"id":1,"params":{"text":"(print \"hi\")\n\u00e9 \ud83d\ude00","ok":true,"at":[0,12.5,null]}}"#;
+    let json = Json::parse(text).expect("parses");
+    let params = json.get("params").expect("has params");
+    assert_eq!(json.get("id").and_then(Json::as_u64), Some(1));
+    assert_eq!(
+        params.get("text").and_then(Json::as_str),
+        Some("(print \"hi\")\né 😀")
+    );
+    assert_eq!(Json::parse(&json.to_string()), Some(json));
+}
+
+#[test]
+fn invalid_text_is_rejected() {
+    for text in [
+        "",
+        "{",
+        r#"{"a" 1}"#,
+        "[1,]",
+        "nul",
+        r#""\x""#,
+        r#""\ud83d""#,
+        "1 2",
+    ] {
+        assert_eq!(Json::parse(text), None, "{text}");
+    }
+}
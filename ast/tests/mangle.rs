@@ -0,0 +1,54 @@
+//! Tests of turning Lisp symbols and Python names into Python identifiers.
+use ast::builtins::{mangle, python_ident};
+use std::collections::HashSet;
+
+#[test]
+fn keeps_words_readable() {
+    assert_eq!(mangle("foo"), "foo");
+    assert_eq!(mangle("my-even-p"), "my_even_p");
+    assert_eq!(mangle("*best*"), "_best_");
+    assert_eq!(mangle("util:shout"), "util__shout");
+    assert_eq!(mangle("util::quiet"), "util___quiet");
+    assert_eq!(mangle("if"), "if_");
+}
+
+#[test]
+fn escapes_other_symbols() {
+    assert_eq!(mangle("1+"), "__1_2b_");
+    assert_eq!(mangle("foo_bar"), "__foo_5f_bar");
+    assert_eq!(mangle("string="), "__string_3d_");
+    assert_eq!(mangle("a--b"), "__a_2d__2d_b");
+}
+
+#[test]
+fn gives_different_symbols_different_names() {
+    let symbols = [
+        "foo-bar",
+        "foo_bar",
+        "foo--bar",
+        "*foo-bar*",
+        "_foo_bar_",
+        "foo:bar",
+        "foo::bar",
+        "foo__bar",
+        "foo-_bar",
+        "1+",
+        "_1_2b_",
+        "__1_2b_",
+        "if",
+        "if_",
+        "*if*",
+        "_if_",
+    ];
+    let names = symbols.iter().map(|symbol| mangle(symbol));
+    assert_eq!(names.collect::<HashSet<_>>().len(), symbols.len());
+}
+
+#[test]
+fn leaves_python_names_as_python_spells_them() {
+    assert_eq!(python_ident("__name__"), "__name__");
+    assert_eq!(python_ident("get_terminal_size"), "get_terminal_size");
+    assert_eq!(python_ident("my-module"), "my_module");
+    assert_eq!(python_ident("2d"), "_2d");
+    assert_eq!(python_ident("class"), "class_");
+}
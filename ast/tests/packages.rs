@@ -0,0 +1,117 @@
+//! Tests of packages, within a file and across the modules of a directory.
+use ast::project::{self, Status};
+use ast::{Options, Pythonify};
+use std::fs;
+use support::Scratch;
+
+mod support;
+
+fn python(src: &str) -> String {
+    Pythonify::new(src).transpile().expect("transpiles").python
+}
+
+#[test]
+fn exports_become_all() {
+    let src = "(defpackage #:greet (:use #:cl) (:export #:hello #:*name*))\n\
+               (in-package #:greet)\n\
+               (defvar *name* \"world\")\n\
+               (defun hello () (greet::greeting))\n\
+               (defun greeting () (cl:length *name*))\n\
+               (print (hello))\n";
+    let python = python(src);
+    assert!(python.contains("__all__ = [\"hello\", \"_name_\"]\n"));
+    assert!(python.contains("def hello():\n    return len(_name_ or [])\n"));
+}
+
+#[test]
+fn exported_functions_are_kept() {
+    let src = "(defpackage :lib (:export :twice))\n\
+               (in-package :lib)\n\
+               (defun twice (x) (* 2 x))\n\
+               (print 1)\n";
+    let python = Pythonify::with_options(
+        src,
+        Options {
+            opt_level: 1,
+            ..Options::default()
+        },
+    )
+    .transpile()
+    .expect("transpiles")
+    .python;
+    assert!(python.contains("def twice(x):"));
+}
+
+#[test]
+fn require_imports_python_modules() {
+    assert_eq!(
+        python("(require :math \"json\")\n"),
+        "import math\nimport json\n"
+    );
+}
+
+#[test]
+fn uninterned_symbols_are_names() {
+    assert_eq!(python("(print #:foo)\n"), "print(\"foo\")\n");
+}
+
+#[test]
+fn reports_malformed_package_forms() {
+    assert!(Pythonify::new("(in-package 3)\n").transpile().is_err());
+    assert!(Pythonify::new("(defpackage :p (:shadow :x))\n")
+        .transpile()
+        .is_err());
+}
+
+#[test]
+fn imports_across_packages() {
+    let dir = Scratch::new("packages");
+    let (src, out) = (dir.join("src"), dir.join("out"));
+    fs::create_dir_all(&src).expect("creates scratch directory");
+    let write = |name: &str, text: &str| fs::write(src.join(name), text).expect("writes source");
+    write(
+        "packages.lisu",
+        "(defpackage #:util (:export #:shout))\n(defpackage #:app (:use #:util))\n",
+    );
+    write(
+        "util.lisu",
+        "(in-package #:util)\n(defun shout (s) s)\n(defun quiet (s) s)\n",
+    );
+    write(
+        "main.lisu",
+        "(in-package #:app)\n(print (shout \"a\"))\n(print (util:shout \"b\"))\n\
+         (print (util::quiet \"c\"))\n",
+    );
+    write(
+        "other.lisu",
+        "(print (quiet \"d\"))\n(print (util:quiet \"e\"))\n",
+    );
+
+    let files = project::build(&src, &out, &Options::default()).expect("builds");
+    let main = fs::read_to_string(out.join("main.py")).expect("writes main");
+    assert!(
+        main.starts_with("from .util import quiet as util___quiet, shout, shout as util__shout\n")
+    );
+    let util = fs::read_to_string(out.join("util.py")).expect("writes util");
+    assert!(util.starts_with("__all__ = [\"shout\"]\n"));
+
+    // Neither internal nor unqualified names of another package are visible
+    let other = files
+        .iter()
+        .find(|file| file.module == "other")
+        .expect("has other");
+    let Status::Failed(ref diagnostics) = other.status else {
+        panic!("`other` cannot see `quiet`");
+    };
+    let messages = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "undefined function `quiet`",
+            "undefined function `util:quiet`"
+        ]
+    );
+}
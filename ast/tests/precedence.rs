@@ -0,0 +1,33 @@
+//! Tests of the precedence of lowered Python expressions.
+use ast::builtins::{atom, or_empty, paren, precedence, PREC_ADD, PREC_ATOM, PREC_MUL, PREC_OR};
+
+#[test]
+fn finds_the_loosest_top_level_operator() {
+    assert_eq!(precedence("a + b * c"), PREC_ADD);
+    assert_eq!(precedence("(a + b) * c"), PREC_MUL);
+    assert_eq!(precedence("f(a or b)"), PREC_ATOM);
+    assert_eq!(precedence("x or []"), PREC_OR);
+}
+
+#[test]
+fn ignores_operators_in_strings() {
+    assert_eq!(precedence(r#""a + b""#), PREC_ATOM);
+    assert_eq!(precedence(r#"f("a\"b + c")"#), PREC_ATOM);
+}
+
+#[test]
+fn closes_strings_ending_in_escaped_backslashes() {
+    assert_eq!(precedence(r#"len("\\" or []) + x"#), PREC_ADD);
+    assert_eq!(precedence(r#"'\\' + x"#), PREC_ADD);
+    assert_eq!(
+        paren(r#"len("\\" or []) + x"#, PREC_MUL),
+        r#"(len("\\" or []) + x)"#
+    );
+    assert_eq!(atom(r#""\\" + x"#), r#"("\\" + x)"#);
+}
+
+#[test]
+fn finds_list_displays_holding_escaped_backslashes() {
+    assert_eq!(or_empty(r#"["\\", "]"]"#), r#"["\\", "]"]"#);
+    assert_eq!(or_empty(r#"["\\"] + x"#), r#"(["\\"] + x or [])"#);
+}
@@ -0,0 +1,112 @@
+//! Tests of transpiling directory trees of sources to packages.
+use ast::project::{self, Status, MANIFEST};
+use ast::Options;
+use std::fs;
+use std::path::Path;
+use support::Scratch;
+
+mod support;
+
+/// Fresh directory for the test named `name`, with a source tree to fill.
+fn scratch(name: &str) -> Scratch {
+    let dir = Scratch::new(name);
+    fs::create_dir_all(dir.join("src/util")).expect("creates scratch directory");
+    dir
+}
+
+fn write(path: &Path, src: &str) {
+    fs::write(path, src).expect("writes source");
+}
+
+/// Status of each module built, by module name.
+fn statuses(root: &Path, out: &Path) -> Vec<(String, &'static str)> {
+    project::build(root, out, &Options::default())
+        .expect("builds")
+        .into_iter()
+        .map(|file| {
+            let status = match file.status {
+                Status::Built(_) => "built",
+                Status::Unchanged => "unchanged",
+                Status::Failed(_) => "failed",
+            };
+            (file.module, status)
+        })
+        .collect()
+}
+
+#[test]
+fn mirrors_the_tree_and_imports_across_modules() {
+    let dir = scratch("mirror");
+    let (src, out) = (dir.join("src"), dir.join("out"));
+    write(
+        &src.join("util/text.lisu"),
+        "(defun shout (s) (concatenate 'string s \"!\"))\n",
+    );
+    write(&src.join("main.lisu"), "(print (shout \"hi\"))\n");
+
+    assert_eq!(
+        statuses(&src, &out),
+        [
+            ("main".to_owned(), "built"),
+            ("util.text".to_owned(), "built")
+        ]
+    );
+    let main = fs::read_to_string(out.join("main.py")).expect("writes main");
+    assert!(main.starts_with("from .util.text import shout\n"));
+    assert!(out.join("__init__.py").exists());
+    assert!(out.join("util/__init__.py").exists());
+    assert!(out.join("util/text.py.map").exists());
+    assert!(out.join(MANIFEST).exists());
+}
+
+#[test]
+fn rebuilds_only_what_changed() {
+    let dir = scratch("incremental");
+    let (src, out) = (dir.join("src"), dir.join("out"));
+    write(&src.join("lib.lisu"), "(defun twice (x) (* 2 x))\n");
+    write(&src.join("main.lisu"), "(print (twice 2))\n");
+    write(&src.join("other.lisu"), "(print 1)\n");
+    statuses(&src, &out);
+    assert!(statuses(&src, &out)
+        .iter()
+        .all(|(_, status)| *status == "unchanged"));
+
+    // A new body does not rebuild the modules importing it
+    write(&src.join("lib.lisu"), "(defun twice (x) (+ x x))\n");
+    assert_eq!(
+        statuses(&src, &out),
+        [
+            ("lib".to_owned(), "built"),
+            ("main".to_owned(), "unchanged"),
+            ("other".to_owned(), "unchanged")
+        ]
+    );
+
+    // A new lambda list does
+    write(&src.join("lib.lisu"), "(defun twice (x y) (+ x y))\n");
+    assert_eq!(
+        statuses(&src, &out),
+        [
+            ("lib".to_owned(), "built"),
+            ("main".to_owned(), "failed"),
+            ("other".to_owned(), "unchanged")
+        ]
+    );
+
+    fs::remove_file(src.join("other.lisu")).expect("removes source");
+    statuses(&src, &out);
+    assert!(!out.join("other.py").exists());
+}
+
+#[test]
+fn reports_definitions_made_twice() {
+    let dir = scratch("twice");
+    let (src, out) = (dir.join("src"), dir.join("out"));
+    write(&src.join("a.lisu"), "(defun f () 1)\n");
+    write(&src.join("b.lisu"), "(defun f () 2)\n");
+    let files = project::build(&src, &out, &Options::default()).expect("builds");
+    let Status::Failed(ref diagnostics) = files[1].status else {
+        panic!("`b` defines `f` again");
+    };
+    assert_eq!(diagnostics[0].message, "`f` is already defined in `a`");
+}
@@ -0,0 +1,115 @@
+//! Tests of name resolution.
+use ast::diagnostic::Severity;
+use ast::resolve::{BindingKind, Resolution, Resolved};
+use ast::Pythonify;
+use lexer::Span;
+
+/// Diagnostics of resolving `src`, as `severity: message`.
+fn diagnostics(src: &str) -> Vec<String> {
+    let (exprs, _) = Pythonify::new(src).analyse().expect("reads");
+    Resolution::resolve(&exprs, &[])
+        .diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let severity = match diagnostic.severity {
+                Severity::Error => "error",
+                _ => "warning",
+            };
+            format!("{severity}: {}", diagnostic.message)
+        })
+        .collect()
+}
+
+#[test]
+fn reports_unbound_variables() {
+    assert_eq!(diagnostics("(print y)"), ["error: unbound variable `y`"]);
+    assert_eq!(
+        diagnostics("(print (lambda (x) (+ x y)))"),
+        ["error: unbound variable `y`"]
+    );
+}
+
+#[test]
+fn reports_undefined_functions() {
+    assert_eq!(
+        diagnostics("(defun f (x) (g x))"),
+        ["error: undefined function `g`"]
+    );
+}
+
+#[test]
+fn binds_let_in_parallel_and_let_star_in_sequence() {
+    assert_eq!(
+        diagnostics("(print (let ((a 1) (b a)) b))"),
+        [
+            "error: unbound variable `a`",
+            "warning: unused variable `a`"
+        ]
+    );
+    assert!(diagnostics("(print (let* ((a 1) (b a)) b))").is_empty());
+}
+
+#[test]
+fn sees_globals_and_functions_defined_later() {
+    let src = "(defun f () (g *n*))\n(defun g (n) n)\n(defvar *n* 1)\n(print (f))";
+    assert!(diagnostics(src).is_empty());
+}
+
+#[test]
+fn leaves_python_names_to_python() {
+    assert!(diagnostics("(print (py:len (py:list)))").is_empty());
+}
+
+#[test]
+fn warns_of_unused_and_shadowing_bindings() {
+    assert_eq!(
+        diagnostics("(defun f (x) (let ((x 2)) x))"),
+        [
+            "warning: `x` shadows an outer binding",
+            "warning: unused variable `x`"
+        ]
+    );
+}
+
+#[test]
+fn reports_duplicate_parameters() {
+    assert_eq!(
+        diagnostics("(defun f (x x) x)\n(print (f 1 2))"),
+        [
+            "error: duplicate parameter `x`",
+            "warning: unused variable `x`"
+        ]
+    );
+    assert_eq!(
+        diagnostics("(print (funcall (lambda (a &optional a) a) 1))"),
+        [
+            "error: duplicate parameter `a`",
+            "warning: unused variable `a`"
+        ]
+    );
+}
+
+#[test]
+fn resolves_uses_to_the_innermost_binding() {
+    let src = "(defun f (x) (let ((x 2)) x))";
+    let (_, analysis) = Pythonify::new(src).analyse().expect("reads");
+    let resolution = &analysis.resolution;
+    let Some(Resolved::Binding(id)) = resolution.get(Span::new(1, 1, 27, 27)) else {
+        panic!("`x` is resolved to a binding");
+    };
+    let binding = resolution.binding(id);
+    assert_eq!(binding.name, "x");
+    assert!(matches!(binding.kind, BindingKind::Let));
+    assert_eq!(binding.span, Span::new(1, 1, 21, 21));
+}
+
+#[test]
+fn resolves_builtins() {
+    let (_, analysis) = Pythonify::new("(print (car nil))")
+        .analyse()
+        .expect("reads");
+    assert!(matches!(
+        analysis.resolution.get(Span::new(1, 1, 9, 11)),
+        Some(Resolved::Builtin(_))
+    ));
+}
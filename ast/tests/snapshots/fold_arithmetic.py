@@ -0,0 +1,8 @@
+_size_ = 12
+print(17)
+print(3.5, 3.0, -5)
+
+def area(r):
+    return 3.14 * r * r * 0.5
+
+print(area(2), 1 / 0)
@@ -0,0 +1,14 @@
+from lisp_desu_runtime import cl_true
+_x_ = input()
+print("yes")
+print(None)
+print("ordered")
+print(True, None, None)
+print(3)
+if cl_true(_x_):
+    _t1 = 2
+else:
+    _t1 = 3
+print(_t1)
+print("zero")
+print("zero", "empty", "one")
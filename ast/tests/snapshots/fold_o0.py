@@ -0,0 +1,3 @@
+print(1 + 2)
+print("yes" if True else "no")
+print(not None)
@@ -0,0 +1,5 @@
+print(2)
+print("taken")
+_t1 = 1
+print(_t1)
+print("when")
@@ -0,0 +1,3 @@
+from lisp_desu_runtime import cl_concatenate
+print("foobarbaz")
+print(cl_concatenate("string", "foo", input()))
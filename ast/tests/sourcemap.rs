@@ -0,0 +1,43 @@
+//! Tests of the spans recorded for generated lines and their source maps.
+use ast::sourcemap::SourceMap;
+use ast::{Options, Prelude, Pythonify};
+
+fn transpile(src: &str) -> ast::Output {
+    let options = Options {
+        prelude: Prelude::File,
+        opt_level: 0,
+        ..Options::default()
+    };
+    Pythonify::with_options(src, options)
+        .transpile()
+        .expect("transpiles")
+}
+
+#[test]
+fn lines_map_to_their_forms() {
+    let src = "(defun inverse (n)\n  (/ 1 n))\n\n(print (inverse 0))\n";
+    let output = transpile(src);
+    let lines = output.python.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), output.spans.len());
+
+    let row_of = |text: &str| {
+        let line = lines.iter().position(|line| line.trim() == text).unwrap();
+        output.spans[line].start_row
+    };
+    assert_eq!(row_of("def inverse(n):"), 1);
+    assert_eq!(row_of("return 1 / n"), 2);
+    assert_eq!(row_of("print(inverse(0))"), 4);
+    assert_eq!(row_of(""), 0);
+}
+
+#[test]
+fn json_round_trip() {
+    let output = transpile("(print (+ 1 2))\n(print \"done\")\n");
+    let map = SourceMap {
+        source: "dir/\"quoted\".lisu".to_owned(),
+        lines: output.spans,
+    };
+    assert_eq!(SourceMap::from_json(&map.to_json()), Ok(map.clone()));
+    assert_eq!(map.span(2).map(|span| span.start_row), Some(2));
+    assert_eq!(map.span(3), None);
+}
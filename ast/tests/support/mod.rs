@@ -0,0 +1,33 @@
+//! Helpers shared by the tests of the workspace, which the tests of other
+//! crates include by path.
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Fresh directory for a test, removed when dropped, even if the test
+/// fails.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    /// Empty directory for the test named `name`.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("lisp-desu-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("creates scratch directory");
+        Self(dir)
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,59 @@
+//! Tests of the errors of malformed special forms.
+use ast::expr::SyntaxErrorKind;
+use ast::{Pythonify, TranspileError};
+
+fn error(src: &str) -> (&'static str, &'static str) {
+    match Pythonify::new(src).transpile() {
+        Err(TranspileError::SyntaxError(err)) => match err.kind {
+            SyntaxErrorKind::Malformed { form, expected } => (form, expected),
+            kind => panic!("expected a malformed form, got {kind:?}"),
+        },
+        other => panic!("expected a syntax error, got {other:?}"),
+    }
+}
+
+#[test]
+fn rejects_setf_of_places() {
+    for src in [
+        "(defvar *x* (list 1))\n(setf (car *x*) 2)",
+        "(defvar *x* (list 1))\n(setf (nth 0 *x*) 2)",
+        "(defvar *h* nil)\n(setf (gethash :k *h*) 2)",
+    ] {
+        assert_eq!(
+            error(src),
+            (
+                "setf",
+                "a variable, as places such as `(car x)` are not supported"
+            ),
+            "{src:?}"
+        );
+    }
+}
+
+#[test]
+fn sets_variables_with_setf() {
+    let python = Pythonify::new("(defvar *x* 1)\n(setf *x* 2)\n(print *x*)")
+        .transpile()
+        .expect("transpiles")
+        .python;
+    assert_eq!(python, "_x_ = 1\n_x_ = 2\nprint(_x_)\n");
+}
+
+#[test]
+fn names_the_form_as_written() {
+    assert_eq!(
+        error("(setf *x*)"),
+        ("setf", "pairs of variables and values")
+    );
+    assert_eq!(
+        error("(setq *x*)"),
+        ("setq", "pairs of variables and values")
+    );
+    assert_eq!(error("(print (let* x))"), ("let*", "a binding list"));
+    assert_eq!(error("(print (let* ((1 2)) 3))"), ("let*", "a symbol"));
+    assert_eq!(
+        error("(print (let ((a 1 2)) a))"),
+        ("let", "a name and a value")
+    );
+    assert_eq!(error("(defparameter 1 2)"), ("defparameter", "a symbol"));
+}
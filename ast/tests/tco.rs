@@ -0,0 +1,90 @@
+//! Tests of the tail call analysis.
+use ast::expr::{Defun, Expr, ExprKind};
+use ast::tco::{has_self_tail_call, mutual_tail_calls, tail_callees};
+use ast::Pythonify;
+use std::collections::BTreeSet;
+
+fn defuns(src: &str) -> Vec<Defun> {
+    let (exprs, _) = Pythonify::new(src).analyse().expect("analyses");
+    exprs
+        .into_iter()
+        .filter_map(|expr: Expr| match expr.kind {
+            ExprKind::Defun(defun) => Some(defun),
+            _ => None,
+        })
+        .collect()
+}
+
+fn callees(src: &str) -> Vec<String> {
+    let defuns = defuns(src);
+    tail_callees(&defuns[0])
+        .into_iter()
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn follows_cond_let_and_progn() {
+    let src = "\
+(defun walk (n)
+  (cond ((= n 0) (done n))
+        ((< n 0) nil)
+        (t (let ((m (- n 1)))
+             (progn (print m) (walk m))))))
+(defun done (n) n)";
+    assert_eq!(callees(src), ["done", "walk"]);
+    assert!(has_self_tail_call(&defuns(src)[0]));
+}
+
+#[test]
+fn follows_let_star_and_if() {
+    let src = "\
+(defun count-down (n acc)
+  (let* ((m (- n 1))
+         (acc (+ acc 1)))
+    (if (< m 0) acc (count-down m acc))))";
+    assert_eq!(callees(src), ["count-down"]);
+}
+
+#[test]
+fn skips_calls_that_are_not_last() {
+    let src = "\
+(defun depth (n)
+  (let ((inner (depth (- n 1))))
+    (print (depth n))
+    (+ 1 inner)))";
+    assert_eq!(callees(src), ["+"]);
+    assert!(!has_self_tail_call(&defuns(src)[0]));
+}
+
+#[test]
+fn finds_return_from_outside_loops() {
+    let src = "\
+(defun search (xs)
+  (when (null xs)
+    (return-from search (give-up)))
+  (loop (return-from search (inside-loop)))
+  (search (cdr xs)))
+(defun give-up () nil)
+(defun inside-loop () nil)";
+    assert_eq!(callees(src), ["give-up", "search"]);
+}
+
+#[test]
+fn groups_functions_tail_calling_each_other() {
+    let src = "\
+(defun my-even-p (n)
+  (cond ((= n 0) t)
+        (t (let ((m (- n 1))) (my-odd-p m)))))
+(defun my-odd-p (n)
+  (if (= n 0) nil (my-even-p (- n 1))))
+(defun check (n) (my-even-p n))
+(defun loop-alone (n) (if (= n 0) n (loop-alone (- n 1))))
+(defun not-tail (n) (+ 1 (my-odd-p n)))";
+    let defuns = defuns(src);
+    let defuns = defuns.iter().collect::<Vec<_>>();
+    assert_eq!(
+        mutual_tail_calls(&defuns),
+        BTreeSet::from(["my-even-p", "my-odd-p"])
+    );
+}
@@ -0,0 +1,82 @@
+//! Tests of type inference.
+use ast::types::Type;
+use ast::Pythonify;
+
+/// Messages of the type errors in `src`.
+fn errors(src: &str) -> Vec<String> {
+    let (exprs, analysis) = Pythonify::new(src).analyse().expect("reads");
+    let (_, diagnostics) = ast::types::infer(&exprs, &analysis.resolution);
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect()
+}
+
+/// Inferred return type of the function `name` in `src`.
+fn returns(src: &str, name: &str) -> Type {
+    let (_, analysis) = Pythonify::new(src).analyse().expect("reads");
+    match analysis.types.function(name) {
+        Some(Type::Function(_, ret)) => (**ret).clone(),
+        ty => panic!("`{name}` has a function type: {ty:?}"),
+    }
+}
+
+#[test]
+fn reports_mixing_strings_and_numbers() {
+    assert_eq!(
+        errors("(print (+ \"a\" 1))"),
+        ["`+` expects numbers, got string"]
+    );
+}
+
+#[test]
+fn reports_length_of_numbers() {
+    assert_eq!(
+        errors("(print (length 5))"),
+        ["`length` expects a list or string, got integer"]
+    );
+}
+
+#[test]
+fn reports_arguments_of_the_wrong_type() {
+    let src = "(defun twice (x) (* x 2))\n(print (twice 2))\n(print (twice \"a\"))";
+    let errors = errors(src);
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(errors[0].starts_with("`twice` expects"), "{errors:?}");
+}
+
+#[test]
+fn generalises_functions() {
+    let src = "(defun id (x) x)\n(print (+ (id 1) 2))\n(print (length (id \"ab\")))";
+    assert!(errors(src).is_empty());
+}
+
+#[test]
+fn types_values_that_may_differ_as_any() {
+    assert!(errors("(print (list 1 \"a\" 2.0))").is_empty());
+    assert_eq!(returns("(defun f (x) (or x \"none\"))", "f"), Type::Any);
+    assert_eq!(returns("(defun f (x) (if x 1 2.0))", "f"), Type::Float);
+}
+
+#[test]
+fn does_not_unify_the_operands_of_and() {
+    let src = "(defun f (x) (and x (= x 0)))\n(print (f 1))";
+    assert!(errors(src).is_empty());
+    assert_eq!(returns(src, "f"), Type::Bool.nullable());
+}
+
+#[test]
+fn does_not_unify_the_operands_of_or() {
+    let src = "(defun f (x) (or x 0))\n(print (f \"a\"))\n(print (f 2))";
+    assert!(errors(src).is_empty());
+}
+
+#[test]
+fn widens_variables_initialised_to_nil_on_setq() {
+    let src = "(defun f (x) (let ((acc nil)) (setq acc (+ x 1)) (* acc 2)))\n(print (f 1))";
+    assert!(errors(src).is_empty(), "{:?}", errors(src));
+    let src = "(defvar *best* nil)\n(setq *best* 3)\n(print (+ *best* 1))";
+    assert!(errors(src).is_empty(), "{:?}", errors(src));
+    let src = "(defvar *name* nil)\n(setq *name* \"a\")\n(print (+ *name* 1))";
+    assert_eq!(errors(src), ["`+` expects numbers, got string or nil"]);
+}
@@ -1,6 +1,4 @@
 #![allow(dead_code)]
-use std::io::stdout;
-use std::io::Write;
 use std::{fmt::Display, str::Chars};
 use strum::EnumString;
 
@@ -13,7 +11,7 @@ pub struct Token {
     pub len: usize,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Span {
     pub start_row: usize,
     pub start_col: usize,
@@ -38,6 +36,9 @@ pub enum TokenKind {
     Whitespace,
     Comma,
 
+    /// '.' on its own, as in a dotted pair
+    Dot,
+
     /// '('
     OpenParen,
     /// ')'
@@ -86,7 +87,8 @@ fn is_whitespace(c: char) -> bool {
 }
 
 fn is_end_ident(c: char) -> bool {
-    matches!(c, '(' | ')') || is_whitespace(c)
+    // '\0' is returned when peeking past the end of the source
+    matches!(c, '(' | ')' | '\0') || is_whitespace(c)
 }
 
 fn is_string_literal(c: char) -> bool {
@@ -113,14 +115,17 @@ impl Span {
         }
     }
 
+    /// Span of the token of `len` bytes starting at `start_chars`, whose
+    /// columns count characters, and the row and column after it.
     pub fn make_span(
         start_row: usize,
         start_col: usize,
         start_chars: Chars,
         len: usize,
     ) -> (Self, usize, usize) {
-        let newlines = start_chars.clone().take(len).filter(|c| *c == '\n').count();
-        let last_char = start_chars.clone().take(len).last().unwrap();
+        let start_chars = start_chars.as_str()[..len].chars();
+        let newlines = start_chars.clone().filter(|c| *c == '\n').count();
+        let last_char = start_chars.clone().last().unwrap();
 
         let end_row = match newlines {
             0 | 1 => start_row + newlines,
@@ -128,7 +133,6 @@ impl Span {
         };
 
         let end_col = start_chars
-            .take(len)
             .fold((start_col - 1, '\0'), |(acc, prev), c| match prev {
                 '\n' => (1, c),
                 _ => (acc + 1, c),
@@ -171,13 +175,18 @@ impl<'a> Cursor<'a> {
         let kind = match next_char {
             ';' => self.consume_line_comment(),
             '\'' | '`' => Backquote,
+            // Lambda list keywords such as `&optional` are identifiers
+            '&' if !is_end_ident(self.peak()) => self.consume_ident(),
             '&' => And,
+            // Longer symbols such as `<=` and `>=` are identifiers
+            '=' | '<' | '>' if !is_end_ident(self.peak()) => self.consume_ident(),
             '=' => Eq,
             '<' => OpenAngleBracket,
             '>' => CloseAngleBracket,
             '(' => OpenParen,
             ')' => CloseParen,
             ',' => Comma,
+            '.' if is_end_ident(self.peak()) => Dot,
 
             '0'..='9' => self.consume_literal(),
             c if is_whitespace(c) => self.consume_whitespace(),
@@ -195,6 +204,11 @@ impl<'a> Cursor<'a> {
         Token { kind, len, span }
     }
 
+    /// Byte offset in the source of the next token.
+    pub fn offset(&self) -> usize {
+        self.src.len() - self.chars.as_str().len()
+    }
+
     fn reset_span(&mut self, advance_rows: usize, col: usize) {
         self.row += advance_rows;
         self.col = col;
@@ -219,20 +233,16 @@ impl<'a> Cursor<'a> {
     }
 
     fn consume_line_comment(&mut self) -> TokenKind {
-        loop {
-            self.next_char();
-            if self.peak() == '\n' {
-                break;
-            }
-        }
+        while self.peak() != '\n' && self.next_char().is_some() {}
         TokenKind::LineComment
     }
 
     fn consume_string_literal(&mut self, start: char) -> TokenKind {
         loop {
-            let c = self.peak();
-
-            self.next_char();
+            // An unterminated string runs to the end of the source
+            let Some(c) = self.next_char() else {
+                return TokenKind::Literal;
+            };
             if start == c {
                 return TokenKind::Literal;
             }
@@ -252,13 +262,21 @@ impl<'a> Cursor<'a> {
     }
 
     fn consume_literal(&mut self) -> TokenKind {
-        loop {
-            match self.peak() {
-                '0'..='9' => {
-                    self.next_char();
-                }
-                _ => return TokenKind::Literal,
-            }
+        self.consume_digits();
+        if self.peak() == '.' && self.peak_nth(1).is_ascii_digit() {
+            self.next_char();
+            self.consume_digits();
+        }
+        // Symbols may start with a digit, like `1+`
+        match is_end_ident(self.peak()) {
+            true => TokenKind::Literal,
+            false => self.consume_ident(),
+        }
+    }
+
+    fn consume_digits(&mut self) {
+        while self.peak().is_ascii_digit() {
+            self.next_char();
         }
     }
 
@@ -277,8 +295,11 @@ impl<'a> Cursor<'a> {
     }
 
     fn peak(&self) -> char {
-        let mut chars = self.chars.clone();
-        chars.next().unwrap_or('\0')
+        self.peak_nth(0)
+    }
+
+    fn peak_nth(&self, n: usize) -> char {
+        self.chars.clone().nth(n).unwrap_or('\0')
     }
 
     fn pos_within_cursor(&self) -> usize {
@@ -300,11 +321,12 @@ impl Token {
             return "";
         }
 
+        // Byte offset of the character at row `r` and column `c`
         let find_idx = |r, c| {
             let mut col = 1;
             let mut row = 1;
 
-            for (idx, char) in src.chars().enumerate() {
+            for (idx, char) in src.char_indices() {
                 if row == r && col == c {
                     return idx;
                 }
@@ -316,13 +338,14 @@ impl Token {
                     _ => col += 1,
                 }
             }
-            src.len() - 1
+            src.char_indices().last().map_or(0, |(idx, _)| idx)
         };
 
         let start = find_idx(self.span.start_row, self.span.start_col);
         let end = find_idx(self.span.end_row, self.span.end_col);
+        let end = end + src[end..].chars().next().map_or(1, char::len_utf8);
 
-        &src[start..=end]
+        &src[start..end]
     }
 }
 
@@ -339,11 +362,3 @@ impl Display for Token {
         )
     }
 }
-
-impl Cursor<'_> {
-    pub fn dbg_token(&self, token: &Token) {
-        let mut stdout = stdout();
-        writeln!(stdout, "{} {:?}", token, token.as_str(self.src)).unwrap();
-        stdout.flush().unwrap();
-    }
-}
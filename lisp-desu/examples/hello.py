@@ -1,2 +1 @@
-
-print("Hello, world!")
\ No newline at end of file
+print("Hello, world!")
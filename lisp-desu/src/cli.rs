@@ -0,0 +1,443 @@
+//! Command-line arguments.
+use ast::emit::Stage;
+use ast::{Options, Prelude};
+use parser::pretty;
+use std::fmt::{self, Display};
+
+pub const USAGE: &str = "\
+Usage: lisp-desu <COMMAND> [OPTIONS]
+
+Commands:
+  build <INPUT> [-o <OUTPUT>]    Transpile INPUT to a Python module, or a directory
+                                 of sources to a package
+  run <INPUT> [-- <ARGS>...]     Transpile INPUT and run it with python3, or $PYTHON
+  check <INPUTS>...              Report diagnostics without writing anything
+  fmt <FILES>...                 Reformat sources in place
+  repl                           Read, transpile and run forms interactively
+  lsp                            Serve the Language Server Protocol over stdio
+  highlight <INPUT>              Print INPUT highlighted as HTML or for the terminal
+  trace                          Rewrite a Python traceback from stdin to Lisp locations
+
+Transpiling options (build, run, check, repl):
+      --prelude=<inline|file>    Paste the runtime into the module, or write it next to it
+      --trampoline               Run mutually tail recursive functions through a trampoline
+      --type-hints               Annotate the module with the inferred types
+      --checked                  Assert declared types at runtime
+  -O0, -O1                       Optimisation level [default: 1]
+      --inline-threshold=<N>     Largest function body inlined at -O1 [default: 8]
+      --entry <FUNCTION>         Function called when the module is run as a script
+      --emit=<STAGES>            Print tokens, sexpr, ast and/or py to stdout (build, run)
+
+Building options (build):
+      --watch                    Rebuild whenever a source changes, until interrupted (Linux)
+
+Checking options (check):
+      --deny-warnings            Fail on warnings as well as errors
+
+Highlighting options (highlight):
+      --format=<html|ansi>       Markup to highlight with [default: ansi]
+
+Formatting options (fmt):
+      --check                    Fail if any file would change, without changing it
+      --width=<N>                Line width [default: 80]
+
+  -o, --output <OUTPUT>          Module, or directory, to write [default: INPUT with a
+                                 .py extension]
+  -h, --help                     Print help
+  -V, --version                  Print version
+
+INPUT, OUTPUT and FILES may be `-` for stdin and stdout. A module written to stdout
+has no source map, and its runtime is not written.
+";
+
+#[derive(Debug)]
+pub enum Command {
+    Build(Build),
+    /// Build, then run the module with the arguments.
+    Run(Build, Vec<String>),
+    Check {
+        inputs: Vec<String>,
+        options: Options,
+        /// Whether warnings fail the check.
+        deny_warnings: bool,
+    },
+    Fmt {
+        paths: Vec<String>,
+        check: bool,
+        width: usize,
+    },
+    Repl(Options),
+    Lsp,
+    Highlight {
+        input: String,
+        format: Format,
+    },
+    Trace,
+    Help,
+    Version,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Transpile {
+    pub input: String,
+    pub options: Options,
+    /// Stages printed by `--emit`.
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub transpile: Transpile,
+    pub output: Option<String>,
+    /// Whether to rebuild whenever a source changes.
+    pub watch: bool,
+}
+
+/// Markup printed by `highlight`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    /// SGR escapes, for terminals.
+    Ansi,
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    MissingCommand,
+    UnknownCommand(String),
+    UnknownFlag(String),
+    /// A flag taking a value was last.
+    MissingValue(String),
+    /// A flag taking no value was given one.
+    UnexpectedValue(String),
+    UnexpectedArgument(String),
+    MissingInput,
+    InvalidPrelude(String),
+    InvalidThreshold(String),
+    InvalidWidth(String),
+    InvalidStage(String),
+    InvalidFormat(String),
+    /// A directory was built without an output directory.
+    MissingOutputDir,
+    /// `--emit` was given with a directory.
+    EmitDir,
+    /// `--watch` was given with stdin as the input.
+    WatchStdin,
+    /// `--watch` was given where there is no inotify to watch with.
+    WatchUnsupported,
+}
+
+impl Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::MissingCommand => f.write_str("missing command"),
+            ArgsError::UnknownCommand(command) => write!(f, "unknown command `{command}`"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown flag `{flag}`"),
+            ArgsError::MissingValue(flag) => write!(f, "`{flag}` needs a value"),
+            ArgsError::UnexpectedValue(flag) => write!(f, "`{flag}` takes no value"),
+            ArgsError::UnexpectedArgument(arg) => write!(f, "unexpected argument `{arg}`"),
+            ArgsError::MissingInput => f.write_str("missing input path"),
+            ArgsError::InvalidPrelude(value) => {
+                write!(f, "invalid prelude `{value}`, expected `inline` or `file`")
+            }
+            ArgsError::InvalidThreshold(value) => write!(f, "invalid inline threshold `{value}`"),
+            ArgsError::InvalidWidth(value) => write!(f, "invalid width `{value}`"),
+            ArgsError::InvalidStage(value) => write!(
+                f,
+                "invalid stage `{value}`, expected `tokens`, `sexpr`, `ast` or `py`"
+            ),
+            ArgsError::InvalidFormat(value) => {
+                write!(f, "invalid format `{value}`, expected `html` or `ansi`")
+            }
+            ArgsError::MissingOutputDir => {
+                f.write_str("building a directory needs an output directory, given with `-o`")
+            }
+            ArgsError::EmitDir => f.write_str("`--emit` needs a single input file"),
+            ArgsError::WatchStdin => f.write_str("`--watch` needs an input path, not stdin"),
+            ArgsError::WatchUnsupported => {
+                f.write_str("`--watch` is unsupported on this platform, as it needs inotify")
+            }
+        }
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Command, ArgsError> {
+    // Arguments passed through to the program are not ours
+    let ours = match args.iter().position(|arg| arg == "--") {
+        Some(end) => &args[..end],
+        None => args,
+    };
+    if ours.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(Command::Help);
+    }
+    if ours.iter().any(|arg| arg == "-V" || arg == "--version") {
+        return Ok(Command::Version);
+    }
+
+    let (command, args) = args.split_first().ok_or(ArgsError::MissingCommand)?;
+    let mut args = Args { args: args.iter() };
+    match command.as_str() {
+        "build" | "run" => {
+            let mut transpile = Transpile::default();
+            let mut output = None;
+            let mut watch = false;
+            let mut program_args = vec![];
+            while let Some(arg) = args.next() {
+                match arg.flag {
+                    "--" if command == "run" => {
+                        program_args = args.args.by_ref().cloned().collect();
+                    }
+                    "-o" | "--output" if command == "build" => {
+                        output = Some(args.value(&arg)?.to_owned());
+                    }
+                    "--watch" if command == "build" => {
+                        args.no_value(&arg)?;
+                        if !cfg!(target_os = "linux") {
+                            return Err(ArgsError::WatchUnsupported);
+                        }
+                        watch = true;
+                    }
+                    _ => args.transpile(&arg, &mut transpile)?,
+                }
+            }
+            if transpile.input.is_empty() {
+                return Err(ArgsError::MissingInput);
+            }
+            if watch && transpile.input == "-" {
+                return Err(ArgsError::WatchStdin);
+            }
+            let build = Build {
+                transpile,
+                output,
+                watch,
+            };
+            Ok(match command.as_str() {
+                "build" => Command::Build(build),
+                _ => Command::Run(build, program_args),
+            })
+        }
+        "check" => {
+            let mut inputs = vec![];
+            let mut options = Options::default();
+            let mut deny_warnings = false;
+            while let Some(arg) = args.next() {
+                if args.option(&arg, &mut options)? {
+                    continue;
+                }
+                match arg.flag {
+                    "--deny-warnings" => {
+                        args.no_value(&arg)?;
+                        deny_warnings = true;
+                    }
+                    _ if arg.is_positional() => inputs.push(arg.flag.to_owned()),
+                    _ => return Err(args.unknown(&arg)),
+                }
+            }
+            if inputs.is_empty() {
+                return Err(ArgsError::MissingInput);
+            }
+            Ok(Command::Check {
+                inputs,
+                options,
+                deny_warnings,
+            })
+        }
+        "repl" => {
+            let mut options = Options::default();
+            while let Some(arg) = args.next() {
+                match args.option(&arg, &mut options)? {
+                    true => {}
+                    false => return Err(args.unknown(&arg)),
+                }
+            }
+            Ok(Command::Repl(options))
+        }
+        "fmt" => {
+            let mut paths = vec![];
+            let mut check = false;
+            let mut width = pretty::DEFAULT_WIDTH;
+            while let Some(arg) = args.next() {
+                match arg.flag {
+                    "--check" => {
+                        args.no_value(&arg)?;
+                        check = true;
+                    }
+                    "--width" => {
+                        let value = args.value(&arg)?;
+                        width = value
+                            .parse()
+                            .map_err(|_| ArgsError::InvalidWidth(value.to_owned()))?;
+                    }
+                    _ if arg.is_positional() => paths.push(arg.flag.to_owned()),
+                    _ => return Err(args.unknown(&arg)),
+                }
+            }
+            if paths.is_empty() {
+                return Err(ArgsError::MissingInput);
+            }
+            Ok(Command::Fmt {
+                paths,
+                check,
+                width,
+            })
+        }
+        "lsp" => match args.next() {
+            Some(arg) => Err(args.unknown(&arg)),
+            None => Ok(Command::Lsp),
+        },
+        "highlight" => {
+            let mut input = None;
+            let mut format = Format::Ansi;
+            while let Some(arg) = args.next() {
+                match arg.flag {
+                    "--format" => {
+                        format = match args.value(&arg)? {
+                            "html" => Format::Html,
+                            "ansi" => Format::Ansi,
+                            value => return Err(ArgsError::InvalidFormat(value.to_owned())),
+                        }
+                    }
+                    _ if arg.is_positional() && input.is_none() => {
+                        input = Some(arg.flag.to_owned())
+                    }
+                    _ => return Err(args.unknown(&arg)),
+                }
+            }
+            let input = input.ok_or(ArgsError::MissingInput)?;
+            Ok(Command::Highlight { input, format })
+        }
+        "trace" => match args.next() {
+            Some(arg) => Err(args.unknown(&arg)),
+            None => Ok(Command::Trace),
+        },
+        "help" => Ok(Command::Help),
+        "version" => Ok(Command::Version),
+        _ => Err(ArgsError::UnknownCommand(command.to_owned())),
+    }
+}
+
+/// Read `args`, transpiling options alone, into `options`.
+pub fn parse_options(args: &[String], options: &mut Options) -> Result<(), ArgsError> {
+    let mut args = Args { args: args.iter() };
+    while let Some(arg) = args.next() {
+        if !args.option(&arg, options)? {
+            return Err(args.unknown(&arg));
+        }
+    }
+    Ok(())
+}
+
+/// Flag, or positional argument, with the value given after a `=`.
+struct Arg<'a> {
+    flag: &'a str,
+    value: Option<&'a str>,
+}
+
+impl Arg<'_> {
+    fn is_positional(&self) -> bool {
+        self.flag == "-" || !self.flag.starts_with('-')
+    }
+}
+
+struct Args<'a> {
+    args: std::slice::Iter<'a, String>,
+}
+
+impl<'a> Args<'a> {
+    fn next(&mut self) -> Option<Arg<'a>> {
+        let arg = self.args.next()?.as_str();
+        Some(match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => Arg {
+                flag,
+                value: Some(value),
+            },
+            _ => Arg {
+                flag: arg,
+                value: None,
+            },
+        })
+    }
+
+    /// Value of `arg`, given after a `=` or as the next argument.
+    fn value(&mut self, arg: &Arg<'a>) -> Result<&'a str, ArgsError> {
+        match arg.value {
+            Some(value) => Ok(value),
+            None => self
+                .args
+                .next()
+                .map(String::as_str)
+                .ok_or_else(|| ArgsError::MissingValue(arg.flag.to_owned())),
+        }
+    }
+
+    fn no_value(&self, arg: &Arg) -> Result<(), ArgsError> {
+        match arg.value {
+            Some(_) => Err(ArgsError::UnexpectedValue(arg.flag.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    fn unknown(&self, arg: &Arg) -> ArgsError {
+        match arg.is_positional() {
+            true => ArgsError::UnexpectedArgument(arg.flag.to_owned()),
+            false => ArgsError::UnknownFlag(arg.flag.to_owned()),
+        }
+    }
+
+    /// Read `arg` into `transpile`, as a transpiling option, an `--emit` or
+    /// the input.
+    fn transpile(&mut self, arg: &Arg<'a>, transpile: &mut Transpile) -> Result<(), ArgsError> {
+        if self.option(arg, &mut transpile.options)? {
+            return Ok(());
+        }
+        match arg.flag {
+            "--emit" => {
+                let value = self.value(arg)?;
+                for stage in value.split(',') {
+                    let stage = stage
+                        .parse()
+                        .map_err(|_| ArgsError::InvalidStage(stage.to_owned()))?;
+                    transpile.stages.push(stage);
+                }
+                Ok(())
+            }
+            _ if arg.is_positional() && transpile.input.is_empty() => {
+                transpile.input = arg.flag.to_owned();
+                Ok(())
+            }
+            _ => Err(self.unknown(arg)),
+        }
+    }
+
+    /// Read `arg` into `options` if it is a transpiling option.
+    fn option(&mut self, arg: &Arg<'a>, options: &mut Options) -> Result<bool, ArgsError> {
+        match arg.flag {
+            "--prelude" => {
+                options.prelude = match self.value(arg)? {
+                    "inline" => Prelude::Inline,
+                    "file" => Prelude::File,
+                    value => return Err(ArgsError::InvalidPrelude(value.to_owned())),
+                }
+            }
+            "--inline-threshold" => {
+                let value = self.value(arg)?;
+                options.inline_threshold = value
+                    .parse()
+                    .map_err(|_| ArgsError::InvalidThreshold(value.to_owned()))?;
+            }
+            "--entry" => options.entry = Some(self.value(arg)?.to_owned()),
+            "--trampoline" | "--type-hints" | "--checked" | "-O0" | "-O1" => {
+                self.no_value(arg)?;
+                match arg.flag {
+                    "--trampoline" => options.trampoline = true,
+                    "--type-hints" => options.type_hints = true,
+                    "--checked" => options.checked = true,
+                    "-O0" => options.opt_level = 0,
+                    _ => options.opt_level = 1,
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,107 @@
+//! `lisp-desu highlight`: print a source highlighted as HTML, or with ANSI
+//! escapes for the terminal.
+use ast::highlight::{self, Class};
+use lisp_desu::cli::Format;
+
+/// Colours of parentheses, cycled through by depth.
+const RAINBOW: [(&str, &str); 6] = [
+    ("31", "#d73a49"),
+    ("33", "#b08800"),
+    ("32", "#22863a"),
+    ("36", "#1b7c83"),
+    ("34", "#005cc5"),
+    ("35", "#6f42c1"),
+];
+
+/// Stylesheet of the classes of the HTML, which pages may override.
+const STYLE: &str = "\
+.lisp-desu .kw { color: #d73a49; font-weight: bold }
+.lisp-desu .const { color: #005cc5 }
+.lisp-desu .fn { color: #6f42c1 }
+.lisp-desu .builtin { color: #1b7c83 }
+.lisp-desu .var { color: #e36209 }
+.lisp-desu .param { color: #e36209; font-style: italic }
+.lisp-desu .num { color: #005cc5 }
+.lisp-desu .str { color: #032f62 }
+.lisp-desu .comment { color: #6a737d; font-style: italic }
+.lisp-desu .quote { font-weight: bold }
+";
+
+pub fn render(src: &str, format: Format) -> String {
+    match format {
+        Format::Html => html(src),
+        Format::Ansi => ansi(src),
+    }
+}
+
+/// A `<pre>` of spans of classes styled by a `<style>` before it.
+fn html(src: &str) -> String {
+    let mut style = STYLE.to_owned();
+    for (depth, (_, colour)) in RAINBOW.iter().enumerate() {
+        style.push_str(&format!(".lisp-desu .paren{depth} {{ color: {colour} }}\n"));
+    }
+    let mut out = format!("<style>\n{style}</style>\n<pre class=\"lisp-desu\"><code>");
+    for (range, class) in highlight::highlight(src) {
+        let text = escape(&src[range]);
+        match html_class(class) {
+            Some(class) => out.push_str(&format!("<span class=\"{class}\">{text}</span>")),
+            None => out.push_str(&text),
+        }
+    }
+    out.push_str("</code></pre>\n");
+    out
+}
+
+fn html_class(class: Class) -> Option<String> {
+    let name = match class {
+        Class::Plain | Class::Symbol => return None,
+        Class::Paren(depth) => return Some(format!("paren{}", depth % RAINBOW.len())),
+        Class::Keyword => "kw",
+        Class::Constant => "const",
+        Class::Function => "fn",
+        Class::Builtin => "builtin",
+        Class::Variable => "var",
+        Class::Parameter => "param",
+        Class::Number => "num",
+        Class::String => "str",
+        Class::Comment => "comment",
+        Class::Quote => "quote",
+    };
+    Some(name.to_owned())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The source with each token wrapped in the SGR escapes of its class.
+fn ansi(src: &str) -> String {
+    let mut out = String::new();
+    for (range, class) in highlight::highlight(src) {
+        let text = &src[range];
+        match ansi_style(class) {
+            Some(style) => out.push_str(&format!("\x1b[{style}m{text}\x1b[0m")),
+            None => out.push_str(text),
+        }
+    }
+    out
+}
+
+fn ansi_style(class: Class) -> Option<&'static str> {
+    Some(match class {
+        Class::Plain | Class::Symbol => return None,
+        Class::Keyword => "1;31",
+        Class::Constant | Class::Number => "34",
+        Class::Function => "35",
+        Class::Builtin => "36",
+        Class::Variable => "33",
+        Class::Parameter => "3;33",
+        Class::String => "32",
+        Class::Comment => "2;3",
+        Class::Quote => "1",
+        Class::Paren(depth) => RAINBOW[depth % RAINBOW.len()].0,
+    })
+}
@@ -0,0 +1,80 @@
+//! The inotify events `lisp-desu build --watch` rebuilds on.
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+pub const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+pub const IN_MOVED_FROM: u32 = 0x0000_0040;
+pub const IN_MOVED_TO: u32 = 0x0000_0080;
+pub const IN_CREATE: u32 = 0x0000_0100;
+pub const IN_DELETE: u32 = 0x0000_0200;
+/// The watch was removed, as its directory was deleted.
+pub const IN_IGNORED: u32 = 0x0000_8000;
+pub const IN_ISDIR: u32 = 0x4000_0000;
+
+/// Changes that are worth a rebuild: files written or moved in or out, and
+/// files or directories created or deleted.
+pub const MASK: u32 = IN_CLOSE_WRITE | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+
+/// A file or directory changed in a watched directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Watch descriptor of the directory.
+    pub wd: c_int,
+    pub mask: u32,
+    pub name: PathBuf,
+}
+
+/// Events in `buf`, each a `struct inotify_event`: the watch descriptor, the
+/// mask, a cookie and the length of the NUL-padded name that follows.
+pub fn parse_events(mut buf: &[u8]) -> Vec<Event> {
+    let field = |bytes: &[u8], i: usize| {
+        u32::from_ne_bytes(bytes[i * 4..i * 4 + 4].try_into().expect("four bytes"))
+    };
+    let mut events = vec![];
+    while buf.len() >= 16 {
+        let len = field(buf, 3) as usize;
+        let Some(name) = buf.get(16..16 + len) else {
+            break;
+        };
+        let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+        events.push(Event {
+            wd: field(buf, 0) as c_int,
+            mask: field(buf, 1),
+            name: PathBuf::from(OsStr::from_bytes(name)),
+        });
+        buf = &buf[16 + len..];
+    }
+    events
+}
+
+/// Path changed by `event`, if it is a source of `input` or a directory
+/// that may hold some.
+pub fn relevant(event: &Event, dirs: &HashMap<c_int, PathBuf>, input: &Path) -> Option<PathBuf> {
+    let path = dirs.get(&event.wd)?.join(&event.name);
+    if !input.is_dir() {
+        return (path.file_name() == input.file_name()).then(|| input.to_owned());
+    }
+    let hidden = event.name.as_os_str().as_bytes().starts_with(b".");
+    let source = event.mask & IN_ISDIR != 0 || path.extension().is_some_and(|ext| ext == "lisu");
+    (!hidden && source).then_some(path)
+}
+
+/// Stop tracking the directories `event` says are gone: one deleted or
+/// moved out of a watched directory, or one whose watch was removed.
+pub fn forget(event: &Event, dirs: &mut HashMap<c_int, PathBuf>) {
+    if event.mask & IN_IGNORED != 0 {
+        dirs.remove(&event.wd);
+        return;
+    }
+    if event.mask & IN_ISDIR == 0 || event.mask & (IN_DELETE | IN_MOVED_FROM) == 0 {
+        return;
+    }
+    let Some(parent) = dirs.get(&event.wd) else {
+        return;
+    };
+    let gone = parent.join(&event.name);
+    dirs.retain(|_, dir| !dir.starts_with(&gone));
+}
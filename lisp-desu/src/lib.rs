@@ -0,0 +1,5 @@
+//! Parts of the command line tool that are tested on their own.
+pub mod cli;
+#[cfg(target_os = "linux")]
+pub mod inotify;
+pub mod trace;
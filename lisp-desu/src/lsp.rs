@@ -0,0 +1,498 @@
+//! `lisp-desu lsp`: a language server speaking LSP over stdio.
+//!
+//! Documents are synced in full. Each version is checked for diagnostics,
+//! and its analysis, when it could be read, answers requests for
+//! definitions, hovers, document symbols and completions. Positions count
+//! UTF-16 code units, as LSP does by default, where spans count characters.
+use crate::CliError;
+use ast::builtins::BUILTINS;
+use ast::diagnostic::{Diagnostic, Severity};
+use ast::expr::{Expr, ExprKind, SPECIAL_FORMS};
+use ast::json::Json;
+use ast::resolve::{BindingKind, Resolved};
+use ast::{Analysis, Pythonify};
+use lexer::Span;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::panic;
+
+const LAMBDA_LIST_KEYWORDS: &[&str] = &["&optional", "&rest", "&body", "&key", "&allow-other-keys"];
+
+// Error codes, and symbol and completion kinds, of the protocol
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const SYMBOL_MODULE: usize = 2;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_KEYWORD: usize = 14;
+
+pub fn run() -> Result<i32, CliError> {
+    let stdin = io::stdin();
+    let mut server = Server {
+        input: stdin.lock(),
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    server.serve()
+}
+
+struct Server<R> {
+    input: R,
+    /// Open documents, by URI.
+    documents: HashMap<String, Document>,
+    /// Whether the client asked for a shutdown, after which `exit` succeeds.
+    shutdown: bool,
+}
+
+struct Document {
+    text: String,
+    /// Forms and analysis of `text`, unless it could not be read, as spans
+    /// of an earlier version would point into the wrong text.
+    program: Option<(Vec<Expr>, Analysis)>,
+}
+
+impl<R: BufRead> Server<R> {
+    fn serve(&mut self) -> Result<i32, CliError> {
+        loop {
+            let Some(message) = self.receive()? else {
+                return Ok(1);
+            };
+            let method = message.get("method").and_then(Json::as_str);
+            let params = message.get("params").unwrap_or(&Json::Null);
+            let id = message.get("id");
+            match (method, id) {
+                (Some("exit"), _) => return Ok(!self.shutdown as i32),
+                (Some(method), Some(id)) => {
+                    let response = match self.request(method, params) {
+                        Some(result) => Json::object([("id", id.clone()), ("result", result)]),
+                        None => Json::object([
+                            ("id", id.clone()),
+                            (
+                                "error",
+                                Json::object([
+                                    ("code", Json::Number(METHOD_NOT_FOUND)),
+                                    ("message", format!("unknown method `{method}`").into()),
+                                ]),
+                            ),
+                        ]),
+                    };
+                    send(response)?;
+                }
+                (Some(method), None) => self.notification(method, params)?,
+                // Responses to requests we never make
+                (None, _) => {}
+            }
+        }
+    }
+
+    /// Result of the request `method`, or `None` if it is unknown.
+    fn request(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let document = || {
+            let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+            Some((uri, self.documents.get(uri)?))
+        };
+        let at = |document: &Document| position(&document.text, params.get("position")?);
+        Some(match method {
+            "initialize" => Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", 1.into()),
+                        ("definitionProvider", true.into()),
+                        ("hoverProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                        (
+                            "completionProvider",
+                            Json::object([("triggerCharacters", vec!["(".into()].into())]),
+                        ),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object([
+                        ("name", "lisp-desu".into()),
+                        ("version", env!("CARGO_PKG_VERSION").into()),
+                    ]),
+                ),
+            ]),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/definition" => document()
+                .and_then(|(uri, document)| definition(uri, document, at(document)?))
+                .unwrap_or(Json::Null),
+            "textDocument/hover" => document()
+                .and_then(|(_, document)| hover(document, at(document)?))
+                .unwrap_or(Json::Null),
+            "textDocument/documentSymbol" => document()
+                .map(|(_, document)| symbols(document))
+                .unwrap_or(Json::Null),
+            "textDocument/completion" => document()
+                .map(|(_, document)| completions(document))
+                .unwrap_or(Json::Null),
+            _ => return None,
+        })
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Result<(), CliError> {
+        let Some(uri) = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+        else {
+            return Ok(());
+        };
+        let text = match method {
+            "textDocument/didOpen" => params.get("textDocument").and_then(|doc| doc.get("text")),
+            // Documents are synced in full, so the last change holds the text
+            "textDocument/didChange" => match params.get("contentChanges") {
+                Some(Json::Array(changes)) => changes.last().and_then(|change| change.get("text")),
+                _ => None,
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return publish(uri, "", &[]);
+            }
+            _ => None,
+        };
+        let Some(text) = text.and_then(Json::as_str) else {
+            return Ok(());
+        };
+
+        let (diagnostics, program) = analyse(text);
+        let document = Document {
+            text: text.to_owned(),
+            program,
+        };
+        self.documents.insert(uri.to_owned(), document);
+        publish(uri, text, &diagnostics)
+    }
+
+    /// Next message, or `None` once stdin is closed.
+    fn receive(&mut self) -> Result<Option<Json>, CliError> {
+        let io_error = |err| CliError::Io("-".to_owned(), err);
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line).map_err(io_error)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let Some(length) = length else {
+            return Err(io_error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message without a Content-Length",
+            )));
+        };
+        let mut body = vec![0; length];
+        self.input.read_exact(&mut body).map_err(io_error)?;
+        let message = String::from_utf8(body)
+            .ok()
+            .and_then(|body| Json::parse(&body));
+        match message {
+            Some(message) => Ok(Some(message)),
+            None => Err(io_error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message is not JSON",
+            ))),
+        }
+    }
+}
+
+/// Diagnostics of `text`, and its forms and analysis if it can be read.
+///
+/// A bug of the transpiler panicking on some source is reported as a
+/// diagnostic of it rather than taking the server down with every buffer.
+fn analyse(text: &str) -> (Vec<Diagnostic>, Option<(Vec<Expr>, Analysis)>) {
+    let analysed = panic::catch_unwind(|| {
+        let diagnostics = match Pythonify::new(text).check() {
+            Ok(diagnostics) => diagnostics,
+            Err(err) => err.into_diagnostics().unwrap_or_default(),
+        };
+        (diagnostics, Pythonify::new(text).analyse().ok())
+    });
+    analysed.unwrap_or_else(|payload| {
+        let reason = payload
+            .downcast_ref::<&str>()
+            .map(|reason| reason.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let message = format!("internal error checking this document: {reason}");
+        (vec![Diagnostic::error(Span::default(), message)], None)
+    })
+}
+
+fn send(message: Json) -> Result<(), CliError> {
+    let Json::Object(mut fields) = message else {
+        unreachable!("messages are objects");
+    };
+    fields.insert(0, ("jsonrpc".to_owned(), "2.0".into()));
+    let body = Json::Object(fields).to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{body}", body.len())
+        .and_then(|_| stdout.flush())
+        .map_err(|err| CliError::Io("<stdout>".to_owned(), err))
+}
+
+fn publish(uri: &str, text: &str, diagnostics: &[Diagnostic]) -> Result<(), CliError> {
+    let diagnostics = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let severity = match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+            Json::object([
+                ("range", range(text, diagnostic.span)),
+                ("severity", severity.into()),
+                ("source", "lisp-desu".into()),
+                ("message", diagnostic.message.as_str().into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    send(Json::object([
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ]))
+}
+
+/// Where the symbol at `at` is bound.
+fn definition(uri: &str, document: &Document, at: (usize, usize)) -> Option<Json> {
+    let (_, analysis) = document.program.as_ref()?;
+    let Resolved::Binding(id) = resolved_at(analysis, at)? else {
+        return None;
+    };
+    let span = analysis.resolution.binding(id).span;
+    // Definitions imported from other modules have no location here
+    if span == Span::default() {
+        return None;
+    }
+    Some(Json::object([
+        ("uri", uri.into()),
+        ("range", range(&document.text, span)),
+    ]))
+}
+
+/// What the symbol at `at` is: a function's lambda list and docstring, or
+/// the kind of a variable.
+fn hover(document: &Document, at: (usize, usize)) -> Option<Json> {
+    let (exprs, analysis) = document.program.as_ref()?;
+    let text = match resolved_at(analysis, at)? {
+        Resolved::Builtin(builtin) => format!("builtin function `{}`", builtin.name),
+        Resolved::Binding(id) => {
+            let binding = analysis.resolution.binding(id);
+            match binding.kind {
+                BindingKind::Function => match defun_at(exprs, binding.span) {
+                    Some(expr) => signature(&document.text, expr),
+                    None => format!("function `{}`", binding.name),
+                },
+                BindingKind::Param => format!("parameter `{}`", binding.name),
+                BindingKind::Let => format!("variable `{}`", binding.name),
+                BindingKind::Global => format!("global variable `{}`", binding.name),
+            }
+        }
+    };
+    Some(Json::object([(
+        "contents",
+        Json::object([("kind", "markdown".into()), ("value", text.into())]),
+    )]))
+}
+
+/// Lambda list and docstring of the `defun` `expr`, in Markdown.
+fn signature(text: &str, expr: &Expr) -> String {
+    let ExprKind::Defun(defun) = &expr.kind else {
+        return String::new();
+    };
+    let params = source(text, defun.params.span);
+    let mut out = format!("```lisp\n(defun {} {params})\n```", defun.name.name);
+    if let Some(doc) = &defun.doc {
+        out.push_str(&format!("\n\n{doc}"));
+    }
+    out
+}
+
+/// The top-level definitions.
+fn symbols(document: &Document) -> Json {
+    let Some((exprs, _)) = &document.program else {
+        return Json::Array(vec![]);
+    };
+    let symbol = |name: &str, kind: usize, detail: String, expr: &Expr, at: Span| {
+        Json::object([
+            ("name", name.into()),
+            ("detail", detail.into()),
+            ("kind", kind.into()),
+            ("range", range(&document.text, expr.span)),
+            ("selectionRange", range(&document.text, at)),
+        ])
+    };
+    exprs
+        .iter()
+        .filter_map(|expr| match &expr.kind {
+            ExprKind::Defun(defun) => Some(symbol(
+                &defun.name.name,
+                SYMBOL_FUNCTION,
+                source(&document.text, defun.params.span),
+                expr,
+                defun.name.span,
+            )),
+            ExprKind::Defvar { name, .. } => Some(symbol(
+                &name.name,
+                SYMBOL_VARIABLE,
+                String::new(),
+                expr,
+                name.span,
+            )),
+            ExprKind::Defpackage(package) => Some(symbol(
+                &package.name.name,
+                SYMBOL_MODULE,
+                String::new(),
+                expr,
+                package.name.span,
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Names bound by the program, builtins, special forms and lambda list
+/// keywords, left for the client to filter.
+fn completions(document: &Document) -> Json {
+    let mut items = BTreeSet::new();
+    if let Some((_, analysis)) = &document.program {
+        for binding in &analysis.resolution.bindings {
+            let kind = match binding.kind {
+                BindingKind::Function => COMPLETION_FUNCTION,
+                _ => COMPLETION_VARIABLE,
+            };
+            items.insert((binding.name.as_str(), kind));
+        }
+    }
+    items.extend(
+        BUILTINS
+            .iter()
+            .map(|builtin| (builtin.name, COMPLETION_FUNCTION)),
+    );
+    items.extend(SPECIAL_FORMS.iter().map(|form| (*form, COMPLETION_KEYWORD)));
+    items.extend(
+        LAMBDA_LIST_KEYWORDS
+            .iter()
+            .map(|kw| (*kw, COMPLETION_KEYWORD)),
+    );
+    items
+        .into_iter()
+        .map(|(label, kind)| Json::object([("label", label.into()), ("kind", kind.into())]))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// What the symbol at `at` refers to: the innermost reference or binding
+/// site whose span holds it, or ends just before it.
+fn resolved_at(analysis: &Analysis, (row, col): (usize, usize)) -> Option<Resolved> {
+    analysis
+        .resolution
+        .references
+        .iter()
+        .filter(|(span, _)| {
+            (span.start_row, span.start_col) <= (row, col)
+                && (row, col) <= (span.end_row, span.end_col + 1)
+        })
+        .min_by_key(|(span, _)| (span.end_row - span.start_row, span.end_col - span.start_col))
+        .map(|(_, resolved)| *resolved)
+}
+
+/// The top-level `defun` whose name is at `span`.
+fn defun_at(exprs: &[Expr], span: Span) -> Option<&Expr> {
+    let mut found = None;
+    for expr in exprs {
+        expr.walk(&mut |expr| {
+            if matches!(&expr.kind, ExprKind::Defun(defun) if defun.name.span == span) {
+                found = Some(expr);
+            }
+        });
+    }
+    found
+}
+
+/// 1-based row and column of an LSP position in `text`.
+fn position(text: &str, position: &Json) -> Option<(usize, usize)> {
+    let line = position.get("line")?.as_u64()? as usize;
+    let character = position.get("character")?.as_u64()? as usize;
+    // The characters that end by the code unit `character`
+    let chars = text.lines().nth(line).map_or(character, |text| {
+        let mut units = 0;
+        text.chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= character
+            })
+            .count()
+    });
+    Some((line + 1, chars + 1))
+}
+
+/// UTF-16 code units taken by the first `chars` characters of the 1-based
+/// row `row` of `text`.
+fn units(text: &str, row: usize, chars: usize) -> usize {
+    let line = text.lines().nth(row - 1).unwrap_or_default();
+    let units = line.chars().take(chars).map(char::len_utf16).sum::<usize>();
+    units + chars.saturating_sub(line.chars().count())
+}
+
+/// LSP range of `span`, which is 1-based and ends on its last character.
+/// Diagnostics with no location are shown at the start of the document.
+fn range(text: &str, span: Span) -> Json {
+    let point = |line: usize, character: usize| {
+        Json::object([("line", line.into()), ("character", character.into())])
+    };
+    if span == Span::default() {
+        return Json::object([("start", point(0, 0)), ("end", point(0, 0))]);
+    }
+    // Spans of unclosed forms may run past the end of the text
+    let end_row = span.end_row.min(text.lines().count().max(1));
+    let start = units(text, span.start_row, span.start_col - 1);
+    let end = units(text, end_row, span.end_col);
+    Json::object([
+        ("start", point(span.start_row - 1, start)),
+        ("end", point(end_row - 1, end)),
+    ])
+}
+
+/// Text of the source at `span`.
+fn source(text: &str, span: Span) -> String {
+    let mut out = String::new();
+    for (row, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+        if row < span.start_row || row > span.end_row {
+            continue;
+        }
+        let start = if row == span.start_row {
+            span.start_col - 1
+        } else {
+            0
+        };
+        let end = if row == span.end_row {
+            span.end_col
+        } else {
+            usize::MAX
+        };
+        if row > span.start_row {
+            out.push('\n');
+        }
+        out.extend(line.chars().take(end).skip(start));
+    }
+    out
+}
@@ -1,15 +1,35 @@
-#![allow(dead_code)]
-use ast::{Pythonify, TranspileError};
-use parser::ParseError;
-use std::env::args;
+use ast::diagnostic::Diagnostic;
+use ast::project::{self, Status};
+use ast::{Options, Pythonify, TranspileError};
+use lisp_desu::cli::{self, ArgsError, Build, Command, Transpile, USAGE};
+use lisp_desu::trace;
+use parser::{cst, pretty};
+use std::env;
+use std::fmt::{self, Display};
 use std::fs;
+use std::io::{self, Read, Write};
 use std::path::Path;
+use std::process::{self, exit};
+
+mod highlight;
+mod lsp;
+mod repl;
+#[cfg(target_os = "linux")]
+mod watch;
 
 #[derive(Debug)]
 enum CliError {
     Args(ArgsError),
-    Parse(ParseError),
     Transpile(TranspileError),
+    /// Reading or writing the path, `-` for stdin and stdout, or running
+    /// the program failed.
+    Io(String, io::Error),
+}
+
+impl From<ArgsError> for CliError {
+    fn from(value: ArgsError) -> Self {
+        Self::Args(value)
+    }
 }
 
 impl From<TranspileError> for CliError {
@@ -18,64 +38,316 @@ impl From<TranspileError> for CliError {
     }
 }
 
-impl From<std::io::Error> for CliError {
-    fn from(_value: std::io::Error) -> Self {
-        todo!()
+impl Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Args(err) => write!(f, "{err}"),
+            CliError::Transpile(TranspileError::IoError(path, err)) => {
+                write!(f, "{}: {err}", path.display())
+            }
+            CliError::Transpile(err) => write!(f, "{err:?}"),
+            CliError::Io(path, err) => write!(f, "{}: {err}", display_path(path)),
+        }
     }
 }
 
-impl From<ParseError> for CliError {
-    fn from(value: ParseError) -> Self {
-        Self::Parse(value)
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let code = match run(&args) {
+        Ok(code) => code,
+        // Output piped to a program that stopped reading
+        Err(CliError::Io(_, err)) if err.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(err) => {
+            eprintln!("lisp-desu: error: {err}");
+            match err {
+                CliError::Args(_) => {
+                    eprintln!("Try `lisp-desu --help` for more information.");
+                    2
+                }
+                _ => 1,
+            }
+        }
+    };
+    exit(code);
+}
+
+/// Run the command given by `args`, returning the exit code.
+fn run(args: &[String]) -> Result<i32, CliError> {
+    match cli::parse(args)? {
+        Command::Build(build) => self::build(&build),
+        Command::Run(build, args) => run_module(build, &args),
+        Command::Check {
+            inputs,
+            options,
+            deny_warnings,
+        } => check(&inputs, &options, deny_warnings),
+        Command::Fmt {
+            paths,
+            check,
+            width,
+        } => fmt(&paths, check, width),
+        Command::Repl(options) => repl::run(options),
+        Command::Lsp => lsp::run(),
+        Command::Highlight { input, format } => {
+            write_stdout(&highlight::render(&read(&input)?, format))?;
+            Ok(0)
+        }
+        Command::Trace => {
+            write_stdout(&trace::rewrite(&read("-")?))?;
+            Ok(0)
+        }
+        Command::Help => {
+            println!("lisp-desu {}\n", env!("CARGO_PKG_VERSION"));
+            print!("{USAGE}");
+            Ok(0)
+        }
+        Command::Version => {
+            println!("lisp-desu {}", env!("CARGO_PKG_VERSION"));
+            Ok(0)
+        }
     }
 }
 
-#[derive(Debug)]
-enum ArgsError {
-    NotEnoughArgs,
-    MissingInput,
-}
-
-fn main() -> Result<(), CliError> {
-    let args = args().collect::<Vec<String>>();
-    let _program = args.first().expect("Program name should exist");
-    if args.len() < 2 {
-        // TODO
-        todo!("REPL mode");
-        //return Err(CliError::ArgsError(ArgsError::NotEnoughArgs));
-        //eprintln!("{program}: Missing file path");
-        //exit(1);
-    }
-
-    let mut outpath = None;
-    let mut inpath = None;
-    let mut change_outpath = false;
-
-    for arg in &args[1..] {
-        match arg.as_str() {
-            "-o" => change_outpath = true,
-            s => {
-                if change_outpath {
-                    outpath = Some(s);
-                    change_outpath = false;
-                } else {
-                    inpath = Some(s);
-                }
+/// `lisp-desu build`: print the stages asked for by `--emit`, then write the
+/// module.
+fn build(build: &Build) -> Result<i32, CliError> {
+    // Elsewhere, `--watch` is rejected with the other arguments
+    #[cfg(target_os = "linux")]
+    if build.watch {
+        return watch::run(build);
+    }
+    let Transpile {
+        input,
+        options,
+        stages,
+    } = &build.transpile;
+    if Path::new(input).is_dir() {
+        return build_project(build);
+    }
+    let src = read(input)?;
+    let path = display_path(input);
+
+    // Stages are printed in pipeline order, before the module is written
+    let mut stages = stages.clone();
+    stages.sort();
+    stages.dedup();
+    for stage in stages {
+        match Pythonify::with_options(&src, options.clone()).emit(stage) {
+            Ok(dump) => write_stdout(&dump)?,
+            Err(err) => return failed(path, &src, err),
+        }
+    }
+
+    let output = match &build.output {
+        Some(output) => output.clone(),
+        None if input == "-" => "-".to_owned(),
+        None => format!("{}.py", stem(input)),
+    };
+    let transpiler = Pythonify::with_options(&src, options.clone()).with_path(path);
+    let result = match output.as_str() {
+        "-" => match transpiler.transpile() {
+            Ok(output) => {
+                write_stdout(&output.python)?;
+                Ok(output.warnings)
+            }
+            Err(err) => Err(err),
+        },
+        _ => transpiler.output(&output),
+    };
+    match result {
+        Ok(warnings) => {
+            report(path, &src, &warnings);
+            Ok(0)
+        }
+        Err(err) => failed(path, &src, err),
+    }
+}
+
+/// `lisp-desu build <DIR> -o <OUT>`: transpile the sources under the
+/// directory to a package, skipping those that have not changed.
+fn build_project(build: &Build) -> Result<i32, CliError> {
+    let Transpile {
+        input,
+        options,
+        stages,
+    } = &build.transpile;
+    if !stages.is_empty() {
+        return Err(ArgsError::EmitDir.into());
+    }
+    let out = match build.output.as_deref() {
+        Some("-") | None => return Err(ArgsError::MissingOutputDir.into()),
+        Some(out) => out,
+    };
+
+    let files = project::build(Path::new(input), Path::new(out), options)?;
+    let (mut built, mut unchanged, mut failed) = (0, 0, 0);
+    for file in &files {
+        let path = Path::new(input).join(&file.path).display().to_string();
+        match &file.status {
+            Status::Built(warnings) => {
+                report(&path, &file.src, warnings);
+                built += 1;
+            }
+            Status::Unchanged => unchanged += 1,
+            Status::Failed(diagnostics) => {
+                report(&path, &file.src, diagnostics);
+                failed += 1;
             }
         }
     }
+    eprintln!(
+        "{}: {built} built, {unchanged} unchanged, {failed} failed",
+        plural(files.len(), "module")
+    );
+    Ok((failed > 0) as i32)
+}
+
+/// `lisp-desu run`: build the module in a temporary directory and run it
+/// with `args`, exiting as it does.
+fn run_module(mut build: Build, args: &[String]) -> Result<i32, CliError> {
+    let dir = env::temp_dir().join(format!("lisp-desu-{}", process::id()));
+    fs::create_dir_all(&dir).map_err(|err| CliError::Io(dir.display().to_string(), err))?;
+    let module = dir.join(format!("{}.py", stem(&build.transpile.input)));
+    build.output = Some(module.display().to_string());
 
-    let file_path = inpath.ok_or_else(|| CliError::Args(ArgsError::MissingInput))?;
-    let outpath = outpath.map(|v| v.to_owned()).unwrap_or_else(|| {
-        format!(
-            "{}.py",
-            Path::new(file_path).file_stem().unwrap().to_string_lossy()
-        )
+    let result = self::build(&build).and_then(|code| match code {
+        0 => {
+            let python = python();
+            let status = process::Command::new(&python)
+                .arg(&module)
+                .args(args)
+                .status()
+                .map_err(|err| CliError::Io(python, err))?;
+            Ok(status.code().unwrap_or(1))
+        }
+        code => Ok(code),
     });
-    let src = fs::read_to_string(file_path)?;
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// `lisp-desu check`: report the diagnostics of every input without writing
+/// anything, failing if any has errors, or warnings when they are denied.
+fn check(inputs: &[String], options: &Options, deny_warnings: bool) -> Result<i32, CliError> {
+    let mut errors = 0;
+    let mut warnings = 0;
+    for input in inputs {
+        let src = read(input)?;
+        let path = display_path(input);
+        let diagnostics = match Pythonify::with_options(&src, options.clone()).check() {
+            Ok(diagnostics) => diagnostics,
+            Err(err) => err.into_diagnostics()?,
+        };
+        report(path, &src, &diagnostics);
+        let found = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .count();
+        errors += found;
+        warnings += diagnostics.len() - found;
+    }
+    if errors + warnings > 0 {
+        eprintln!(
+            "{} checked: {}, {}",
+            plural(inputs.len(), "file"),
+            plural(errors, "error"),
+            plural(warnings, "warning"),
+        );
+    }
+    Ok((errors > 0 || deny_warnings && warnings > 0) as i32)
+}
+
+/// `lisp-desu fmt`: reformat the files in place, or with `--check` list
+/// those that would change. `-` formats stdin to stdout.
+fn fmt(paths: &[String], check: bool, width: usize) -> Result<i32, CliError> {
+    let mut failed = false;
+    for path in paths {
+        let src = read(path)?;
+        match pretty::format(&src, width) {
+            Ok(formatted) if formatted == src && path != "-" => {}
+            Ok(formatted) if check => {
+                if formatted != src {
+                    eprintln!("{}: would be reformatted", display_path(path));
+                    failed = true;
+                }
+            }
+            Ok(formatted) if path == "-" => write_stdout(&formatted)?,
+            Ok(formatted) => {
+                fs::write(path, formatted).map_err(|err| CliError::Io(path.clone(), err))?
+            }
+            Err(errors) => {
+                let diagnostics = errors
+                    .into_iter()
+                    .map(|error| Diagnostic::error(cst::span(&src, error.range), error.message))
+                    .collect::<Vec<_>>();
+                report(display_path(path), &src, &diagnostics);
+                failed = true;
+            }
+        }
+    }
+    Ok(failed as i32)
+}
+
+/// Report the diagnostics of a failed transpilation, or pass on any other
+/// error.
+fn failed(path: &str, src: &str, err: TranspileError) -> Result<i32, CliError> {
+    let diagnostics = err.into_diagnostics()?;
+    report(path, src, &diagnostics);
+    Ok(1)
+}
 
-    let transpiler = Pythonify::new(&src);
-    transpiler.output(outpath)?;
+fn report(path: &str, src: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(path, src));
+    }
+}
+
+/// Contents of the file at `path`, or of stdin for `-`.
+fn read(path: &str) -> Result<String, CliError> {
+    let result = match path {
+        "-" => {
+            let mut src = String::new();
+            io::stdin().read_to_string(&mut src).map(|_| src)
+        }
+        _ => fs::read_to_string(path),
+    };
+    result.map_err(|err| CliError::Io(path.to_owned(), err))
+}
+
+fn write_stdout(text: &str) -> Result<(), CliError> {
+    io::stdout()
+        .write_all(text.as_bytes())
+        .map_err(|err| CliError::Io("<stdout>".to_owned(), err))
+}
+
+/// `count` and `noun`, pluralised as needed.
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("{count} {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
+
+/// `path` as shown in messages.
+fn display_path(path: &str) -> &str {
+    match path {
+        "-" => "<stdin>",
+        _ => path,
+    }
+}
+
+/// File name of `input` without its extension, `main` for stdin.
+fn stem(input: &str) -> String {
+    match input {
+        "-" => "main".to_owned(),
+        _ => Path::new(input)
+            .file_stem()
+            .map_or("main".into(), |stem| stem.to_string_lossy())
+            .into_owned(),
+    }
+}
 
-    Ok(())
+/// Python interpreter run by `run` and `repl`: `$PYTHON`, or `python3`.
+fn python() -> String {
+    env::var("PYTHON").unwrap_or_else(|_| "python3".to_owned())
 }
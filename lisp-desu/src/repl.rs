@@ -0,0 +1,228 @@
+//! `lisp-desu repl`: read forms from stdin, transpile them along with the
+//! forms entered before, and run the new lines in a Python process kept for
+//! the session.
+use crate::CliError;
+use ast::builtins::{self, RUNTIME, RUNTIME_MODULE};
+use ast::diagnostic::Diagnostic;
+use ast::{Options, Prelude, Pythonify};
+use parser::cst::{self, SyntaxElement, SyntaxKind};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Runs chunks of Python in one namespace. Each chunk is sent as a line with
+/// its length in bytes and the variable holding its value, or `-`, followed
+/// by the code. A line ending in a NUL marks the end of its output.
+const DRIVER: &str = r#"
+import sys, traceback
+env = {}
+exec("from lisp_desu_runtime import *", env)
+while True:
+    header = sys.stdin.buffer.readline()
+    if not header:
+        break
+    size, value = header.decode().split()
+    code = sys.stdin.buffer.read(int(size)).decode()
+    try:
+        exec(compile(code, "<repl>", "exec"), env)
+        if value != "-":
+            print(env["cl_write_to_string"](env[value]))
+    except Exception:
+        traceback.print_exc()
+    sys.stdout.write("\0\n")
+    sys.stdout.flush()
+"#;
+
+/// Forms whose value is not printed.
+const DEFINITIONS: [&str; 9] = [
+    "defun",
+    "defmacro",
+    "defvar",
+    "defparameter",
+    "declaim",
+    "defpackage",
+    "in-package",
+    "require",
+    "import",
+];
+
+pub fn run(mut options: Options) -> Result<i32, CliError> {
+    // Definitions must be kept for later forms, and the runtime is loaded once
+    options.opt_level = 0;
+    options.prelude = Prelude::File;
+    options.entry = None;
+
+    let dir = env::temp_dir().join(format!("lisp-desu-repl-{}", std::process::id()));
+    let io_error = |path: &std::path::Path| {
+        let path = path.display().to_string();
+        move |err| CliError::Io(path, err)
+    };
+    fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+    let runtime = dir.join(RUNTIME_MODULE).with_extension("py");
+    fs::write(&runtime, RUNTIME).map_err(io_error(&runtime))?;
+
+    let python = crate::python();
+    let child = Command::new(&python)
+        .args(["-u", "-c", DRIVER])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| CliError::Io(python.clone(), err));
+    let result = child.and_then(|child| Session::new(child, options).read_eval_print());
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    options: Options,
+    /// Forms run so far, as transpiled.
+    src: String,
+    /// Number of values printed so far.
+    values: usize,
+}
+
+impl Session {
+    fn new(mut child: Child, options: Options) -> Self {
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Self {
+            child,
+            stdin,
+            stdout,
+            options,
+            src: String::new(),
+            values: 0,
+        }
+    }
+
+    fn read_eval_print(mut self) -> Result<i32, CliError> {
+        let mut input = String::new();
+        let mut lines = io::stdin().lock().lines();
+        loop {
+            print!("{}", if input.is_empty() { "* " } else { "  " });
+            let _ = io::stdout().flush();
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let line = line.map_err(|err| CliError::Io("-".to_owned(), err))?;
+            input.push_str(&line);
+            input.push('\n');
+
+            let parse = cst::parse(&input);
+            if parse
+                .errors
+                .iter()
+                .any(|error| error.message.starts_with("unclosed"))
+            {
+                continue;
+            }
+            if !parse.errors.is_empty() {
+                let diagnostics = parse
+                    .errors
+                    .iter()
+                    .map(|error| {
+                        Diagnostic::error(cst::span(&input, error.range.clone()), error.message)
+                    })
+                    .collect::<Vec<_>>();
+                crate::report("<repl>", &input, &diagnostics);
+                input.clear();
+                continue;
+            }
+            let forms = parse
+                .syntax()
+                .children_with_tokens()
+                .filter(|child| !child.kind().is_trivia())
+                .collect::<Vec<_>>();
+            for form in forms {
+                self.eval(&form)?;
+            }
+            input.clear();
+        }
+        println!();
+        drop(self.stdin);
+        let _ = self.child.wait();
+        Ok(0)
+    }
+
+    /// Transpile `form` after the forms run so far and run its lines.
+    fn eval(&mut self, form: &SyntaxElement) -> Result<(), CliError> {
+        let mut text = form.text();
+        let value = match head(form) {
+            Some(head) if DEFINITIONS.contains(&head.as_str()) => None,
+            _ => {
+                self.values += 1;
+                let name = format!("*repl-value-{}*", self.values);
+                // On lines of its own, so diagnostics show the form as entered
+                text = format!("(defvar {name}\n{text}\n)");
+                Some(builtins::mangle(&name))
+            }
+        };
+        let start = self.src.lines().count();
+        let src = format!("{}{text}\n", self.src);
+        let output = match Pythonify::with_options(&src, self.options.clone()).transpile() {
+            Ok(output) => output,
+            Err(err) => {
+                let diagnostics = err.into_diagnostics()?;
+                let errors = diagnostics
+                    .into_iter()
+                    .filter(Diagnostic::is_error)
+                    .collect::<Vec<_>>();
+                crate::report("<repl>", &src, &errors);
+                return Ok(());
+            }
+        };
+        let code = output
+            .python
+            .lines()
+            .zip(&output.spans)
+            .filter(|(_, span)| span.start_row > start)
+            .map(|(line, _)| format!("{line}\n"))
+            .collect::<String>();
+        self.src = src;
+
+        let value = value.as_deref().unwrap_or("-");
+        let sent =
+            write!(self.stdin, "{} {value}\n{code}", code.len()).and_then(|_| self.stdin.flush());
+        sent.map_err(|err| CliError::Io(crate::python(), err))?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = self.stdout.read_line(&mut line);
+            match read.map_err(|err| CliError::Io(crate::python(), err))? {
+                0 => {
+                    return Err(CliError::Io(
+                        crate::python(),
+                        io::ErrorKind::UnexpectedEof.into(),
+                    ))
+                }
+                _ => match line.strip_suffix("\0\n") {
+                    Some("") => return Ok(()),
+                    Some(rest) => {
+                        println!("{rest}");
+                        return Ok(());
+                    }
+                    None => print!("{line}"),
+                },
+            }
+        }
+    }
+}
+
+/// Symbol at the head of `form`, if a list.
+fn head(form: &SyntaxElement) -> Option<String> {
+    let SyntaxElement::Node(node) = form else {
+        return None;
+    };
+    if node.kind() != SyntaxKind::List {
+        return None;
+    }
+    node.significant_children()
+        .nth(1)
+        .filter(|child| child.kind() == SyntaxKind::Symbol)
+        .map(|child| child.text())
+}
@@ -0,0 +1,78 @@
+//! `lisp-desu trace`: rewrite the frames of a Python traceback that point
+//! into generated modules to the Lisp forms they were lowered from.
+use ast::sourcemap::SourceMap;
+use std::collections::HashMap;
+use std::fs;
+
+/// Rewrite `traceback`, loading the source map of each module it mentions.
+/// Frames of modules without a map are left as they are.
+pub fn rewrite(traceback: &str) -> String {
+    let mut maps = HashMap::new();
+    let mut sources = HashMap::new();
+    let mut out = String::new();
+    let mut lines = traceback.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some((path, row, function)) = frame(line) else {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        };
+        let map = maps
+            .entry(path.to_owned())
+            .or_insert_with(|| load(path))
+            .as_ref();
+        let Some((map, span)) = map.and_then(|map| Some((map, map.span(row)?))) else {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        };
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        out.push_str(&format!(
+            "{indent}{}:{}:{}",
+            map.source, span.start_row, span.start_col
+        ));
+        if let Some(function) = function {
+            out.push_str(&format!(", in {function}"));
+        }
+        out.push('\n');
+        // The Python line and the markers under it are replaced by the
+        // Lisp line
+        while lines
+            .next_if(|line| is_frame_detail(line, indent))
+            .is_some()
+        {}
+        let source = sources
+            .entry(map.source.clone())
+            .or_insert_with(|| fs::read_to_string(&map.source).ok());
+        if let Some(text) = source
+            .as_deref()
+            .and_then(|source| source.lines().nth(span.start_row - 1))
+        {
+            out.push_str(&format!("{indent}  {}\n", text.trim()));
+        }
+    }
+    out
+}
+
+/// Path, line and function of a `  File "x.py", line 3, in f` frame.
+fn frame(line: &str) -> Option<(&str, usize, Option<&str>)> {
+    let rest = line.trim_start().strip_prefix("File \"")?;
+    let (path, rest) = rest.split_once("\", line ")?;
+    let (row, function) = match rest.split_once(", in ") {
+        Some((row, function)) => (row, Some(function)),
+        None => (rest, None),
+    };
+    Some((path, row.parse().ok()?, function))
+}
+
+/// Whether `line` shows the code of the frame indented by `indent`.
+fn is_frame_detail(line: &str, indent: &str) -> bool {
+    line.strip_prefix(indent)
+        .is_some_and(|rest| rest.starts_with("  ") && !rest.trim_start().starts_with("File \""))
+}
+
+fn load(path: &str) -> Option<SourceMap> {
+    let json = fs::read_to_string(format!("{path}.map")).ok()?;
+    SourceMap::from_json(&json).ok()
+}
@@ -0,0 +1,160 @@
+//! `lisp-desu build --watch`: build, then rebuild whenever a source changes,
+//! as told by inotify. Errors are reported and the watch goes on.
+use crate::CliError;
+use lisp_desu::cli::Build;
+use lisp_desu::inotify::{self, Event, MASK};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for more changes before rebuilding, as editors and
+/// version control write several files, or one in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+const IN_CLOEXEC: c_int = 0o2000000;
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+}
+
+pub fn run(build: &Build) -> Result<i32, CliError> {
+    let mut once = build.clone();
+    once.watch = false;
+    let input = PathBuf::from(&build.transpile.input);
+    let io_error = |err| CliError::Io("inotify".to_owned(), err);
+
+    // SAFETY: `inotify_init1` takes no pointers, and the descriptor it
+    // returns is owned by `file` alone
+    let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    let events = spawn_reader(file.try_clone().map_err(io_error)?);
+    let mut dirs = HashMap::new();
+
+    eprintln!("Watching {} for changes", input.display());
+    loop {
+        match crate::build(&once) {
+            Ok(_) => {}
+            Err(err) => eprintln!("lisp-desu: error: {err}"),
+        }
+        // Directories made since the last build are watched from now on
+        for dir in watched_dirs(&input) {
+            if !dirs.values().any(|watched| *watched == dir) {
+                let wd = add_watch(&file, &dir).map_err(io_error)?;
+                dirs.insert(wd, dir);
+            }
+        }
+
+        let mut changed = wait(&events, &mut dirs, &input).map_err(io_error)?;
+        // Pick up the rest of the changes made at the same time
+        loop {
+            match events.recv_timeout(DEBOUNCE) {
+                Ok(event) => {
+                    let event = event.map_err(io_error)?;
+                    if let Some(path) = inotify::relevant(&event, &dirs, &input) {
+                        changed = path;
+                    }
+                    inotify::forget(&event, &mut dirs);
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io_error(io::ErrorKind::UnexpectedEof.into()))
+                }
+            }
+        }
+        eprintln!("\n{} changed, rebuilding", changed.display());
+    }
+}
+
+/// Wait for a change to a source, returning its path.
+fn wait(
+    events: &Receiver<io::Result<Event>>,
+    dirs: &mut HashMap<c_int, PathBuf>,
+    input: &Path,
+) -> io::Result<PathBuf> {
+    loop {
+        let event = events
+            .recv()
+            .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))??;
+        let changed = inotify::relevant(&event, dirs, input);
+        inotify::forget(&event, dirs);
+        if let Some(path) = changed {
+            return Ok(path);
+        }
+    }
+}
+
+/// Directories to watch for `input`: the directory holding a file, as
+/// editors often replace a file rather than write to it, or a directory and
+/// those under it.
+fn watched_dirs(input: &Path) -> Vec<PathBuf> {
+    if !input.is_dir() {
+        let parent = match input.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        return vec![parent.to_owned()];
+    }
+    let mut dirs = vec![input.to_owned()];
+    let mut i = 0;
+    while i < dirs.len() {
+        if let Ok(entries) = fs::read_dir(&dirs[i]) {
+            let mut children = entries
+                .filter_map(Result::ok)
+                .filter(|entry| !entry.file_name().as_bytes().starts_with(b"."))
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect::<Vec<_>>();
+            children.sort();
+            dirs.extend(children);
+        }
+        i += 1;
+    }
+    dirs
+}
+
+fn add_watch(inotify: &File, dir: &Path) -> io::Result<c_int> {
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // SAFETY: `path` is a NUL-terminated string outliving the call
+    let wd = unsafe { inotify_add_watch(inotify.as_raw_fd(), path.as_ptr(), MASK) };
+    match wd {
+        wd if wd < 0 => Err(io::Error::last_os_error()),
+        wd => Ok(wd),
+    }
+}
+
+/// Read the events of `inotify` on a thread of their own, so that they can
+/// be waited for with a timeout.
+fn spawn_reader(mut inotify: File) -> Receiver<io::Result<Event>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            let len = match inotify.read(&mut buf) {
+                Ok(len) => len,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
+            for event in inotify::parse_events(&buf[..len]) {
+                if sender.send(Ok(event)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    receiver
+}
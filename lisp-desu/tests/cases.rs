@@ -0,0 +1,335 @@
+//! Golden tests of the transpiler: each `tests/cases/NAME.lisu`, and each
+//! `examples/NAME.lisu` as the case `examples/NAME`, is transpiled and
+//! compared with `NAME.py`, its expected module, and `NAME.err`, its
+//! expected diagnostics. A missing file expects no module, as the source
+//! fails to transpile, or no diagnostics.
+//!
+//! ```console
+//! $ cargo test --test cases [-- [--bless] [--run] [FILTER]...]
+//! ```
+//!
+//! `--bless` writes the expectations from the results instead, and `--run`
+//! also runs each module with `python3` (or `$PYTHON`) and compares what it
+//! prints with `NAME.out`. Only the cases whose names contain a filter, and
+//! none given with `--skip`, are run; `--exact` matches whole names. A case
+//! may give transpiling options, as `lisp-desu build` takes them, on its
+//! first line, as in `; flags: -O0 --trampoline`.
+use ast::builtins::{RUNTIME, RUNTIME_MODULE};
+use ast::{Options, Prelude, Pythonify};
+use lisp_desu::cli;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+struct Config {
+    bless: bool,
+    run: bool,
+    filters: Vec<String>,
+    skips: Vec<String>,
+    exact: bool,
+}
+
+/// Flags of the libtest harness taking a value, which is not a filter.
+const LIBTEST_VALUED: &[&str] = &[
+    "--color",
+    "--format",
+    "--logfile",
+    "--shuffle-seed",
+    "--test-threads",
+    "-Z",
+];
+
+impl Config {
+    /// Whether the case `name` is run.
+    fn selects(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match self.exact {
+            true => name == pattern,
+            false => name.contains(pattern.as_str()),
+        };
+        (self.filters.is_empty() || self.filters.iter().any(matches))
+            && !self.skips.iter().any(matches)
+    }
+}
+
+/// What a case gave, or is expected to.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    python: Option<String>,
+    diagnostics: Option<String>,
+    stdout: Option<String>,
+}
+
+fn main() {
+    let mut config = Config {
+        bless: false,
+        run: false,
+        filters: vec![],
+        skips: vec![],
+        exact: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bless" => config.bless = true,
+            "--run" => config.run = true,
+            "--exact" => config.exact = true,
+            "--skip" => config.skips.extend(args.next()),
+            _ if LIBTEST_VALUED.contains(&arg.as_str()) => {
+                args.next();
+            }
+            _ => match arg.strip_prefix("--skip=") {
+                Some(skip) => config.skips.push(skip.to_owned()),
+                // Flags meant for the other test harnesses of the workspace
+                None if arg.starts_with('-') => {}
+                None => config.filters.push(arg),
+            },
+        }
+    }
+
+    let mut cases = vec![];
+    for dir in [cases_dir(), manifest_dir().join("examples")] {
+        let entries = fs::read_dir(&dir)
+            .unwrap_or_else(|err| panic!("reading {}: {err}", dir.display()))
+            .map(|entry| entry.expect("reads entry").path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "lisu"))
+            .filter(|path| config.selects(&name(path)));
+        cases.extend(entries);
+    }
+    cases.sort_by_key(|case| name(case));
+
+    let plural = if cases.len() == 1 { "" } else { "s" };
+    println!("\nrunning {} case{plural}", cases.len());
+    let mut failed = vec![];
+    for case in &cases {
+        let result = check(case, &config);
+        let status = match &result {
+            Ok(()) if config.bless => "blessed",
+            Ok(()) => "ok",
+            Err(_) => "FAILED",
+        };
+        println!("case {} ... {status}", name(case));
+        if let Err(message) = result {
+            failed.push((name(case), message));
+        }
+    }
+
+    if config.run {
+        let _ = fs::remove_dir_all(scratch_dir());
+    }
+    for (name, message) in &failed {
+        println!("\n---- {name} ----\n{message}");
+    }
+    let passed = cases.len() - failed.len();
+    let result = if failed.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ncase result: {result}. {passed} passed; {} failed\n",
+        failed.len()
+    );
+    if !failed.is_empty() {
+        println!("Run `cargo test --test cases -- --bless` to accept the new results.");
+        process::exit(1);
+    }
+}
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn cases_dir() -> PathBuf {
+    manifest_dir().join("tests/cases")
+}
+
+/// Name of `case`: its path from `tests/cases`, or from the crate for the
+/// examples, without the extension.
+fn name(case: &Path) -> String {
+    let case = case.with_extension("");
+    let relative = case
+        .strip_prefix(cases_dir())
+        .or_else(|_| case.strip_prefix(manifest_dir()))
+        .expect("cases are in the crate");
+    relative.to_string_lossy().into_owned()
+}
+
+/// Run `case`, then compare its outcome with the expected one, or bless it.
+fn check(case: &Path, config: &Config) -> Result<(), String> {
+    let src = fs::read_to_string(case).map_err(|err| err.to_string())?;
+    let options = flags(&src)?;
+    let file_name = case.file_name().expect("case has a name").to_string_lossy();
+    let mut actual = Outcome {
+        python: None,
+        diagnostics: None,
+        stdout: None,
+    };
+    let diagnostics = match Pythonify::with_options(&src, options).transpile() {
+        Ok(output) => {
+            actual.python = Some(output.python);
+            output.warnings
+        }
+        Err(err) => err.into_diagnostics().map_err(|err| format!("{err:?}"))?,
+    };
+    if !diagnostics.is_empty() {
+        let rendered = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(&file_name, &src))
+            .collect();
+        actual.diagnostics = Some(rendered);
+    }
+    if config.run {
+        if let Some(python) = &actual.python {
+            actual.stdout = Some(run(case, python)?);
+        }
+    }
+
+    let expected_path = |ext| case.with_extension(ext);
+    let read = |ext| fs::read_to_string(expected_path(ext)).ok();
+    if config.bless {
+        let mut expectations = vec![("py", &actual.python), ("err", &actual.diagnostics)];
+        if config.run {
+            expectations.push(("out", &actual.stdout));
+        }
+        for (ext, text) in expectations {
+            let path = expected_path(ext);
+            let result = match text {
+                Some(text) => fs::write(&path, text),
+                None if path.exists() => fs::remove_file(&path),
+                None => Ok(()),
+            };
+            result.map_err(|err| format!("{}: {err}", path.display()))?;
+        }
+        return Ok(());
+    }
+
+    let expected = Outcome {
+        python: read("py"),
+        diagnostics: read("err"),
+        // Without `--run`, what the module prints is not known
+        stdout: read("out").filter(|_| config.run && actual.python.is_some()),
+    };
+    if actual == expected {
+        return Ok(());
+    }
+    let mut message = String::new();
+    let parts = [
+        ("module", "py", &expected.python, &actual.python),
+        (
+            "diagnostics",
+            "err",
+            &expected.diagnostics,
+            &actual.diagnostics,
+        ),
+        ("output", "out", &expected.stdout, &actual.stdout),
+    ];
+    for (what, ext, expected, actual) in parts {
+        match (expected, actual) {
+            (expected, actual) if expected == actual => {}
+            (Some(_), None) => {
+                message.push_str(&format!("expected {what}, as in .{ext}, got none\n"))
+            }
+            (None, Some(actual)) => {
+                message.push_str(&format!("unexpected {what}:\n{actual}\n"));
+            }
+            (Some(expected), Some(actual)) => {
+                message.push_str(&format!("{what} differs from .{ext}:\n"));
+                message.push_str(&diff(expected, actual));
+            }
+            (None, None) => unreachable!("outcomes are equal"),
+        }
+    }
+    Err(message)
+}
+
+/// Transpiling options given by the `; flags:` line starting `src`.
+fn flags(src: &str) -> Result<Options, String> {
+    let mut options = Options {
+        // The runtime is the same for every case, so it is left out
+        prelude: Prelude::File,
+        ..Options::default()
+    };
+    let Some(flags) = src
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("; flags:"))
+    else {
+        return Ok(options);
+    };
+    let flags = flags
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    cli::parse_options(&flags, &mut options).map_err(|err| err.to_string())?;
+    Ok(options)
+}
+
+/// What `python`, the module of `case`, prints when run next to the
+/// runtime.
+fn run(case: &Path, python: &str) -> Result<String, String> {
+    let dir = scratch_dir();
+    let module = dir.join(
+        case.with_extension("py")
+            .file_name()
+            .expect("case has a name"),
+    );
+    let write = |path: &PathBuf, text: &str| {
+        fs::write(path, text).map_err(|err| format!("{}: {err}", path.display()))
+    };
+    fs::create_dir_all(&dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+    write(&dir.join(RUNTIME_MODULE).with_extension("py"), RUNTIME)?;
+    write(&module, python)?;
+
+    let interpreter = env::var("PYTHON").unwrap_or_else(|_| "python3".to_owned());
+    let output = Command::new(&interpreter)
+        .arg(&module)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("running {interpreter}: {err}"))?;
+    let _ = fs::remove_file(&module);
+    if !output.status.success() {
+        return Err(format!(
+            "the module failed with {}:\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|err| err.to_string())
+}
+
+/// Directory the modules of `--run` are written to, with the runtime.
+fn scratch_dir() -> PathBuf {
+    env::temp_dir().join(format!("lisp-desu-cases-{}", process::id()))
+}
+
+/// Lines of `expected` missing from `actual`, marked `-`, and lines added to
+/// it, marked `+`, by their longest common subsequence.
+fn diff(expected: &str, actual: &str) -> String {
+    let (old, new) = (
+        expected.lines().collect::<Vec<_>>(),
+        actual.lines().collect::<Vec<_>>(),
+    );
+    // Length of the longest common subsequence of each pair of suffixes
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out.push_str(&format!(" {}\n", old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    out
+}
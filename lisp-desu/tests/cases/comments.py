@@ -0,0 +1,9 @@
+def clamp(x, lo, hi):
+    if x < lo:
+        return lo
+    elif x > hi:
+        return hi
+    else:
+        return x
+
+print([clamp(-5, 0, 10), clamp(5, 0, 10), clamp(50, 0, 10)])
@@ -0,0 +1,20 @@
+from lisp_desu_runtime import cl_cdr, cl_cons, cl_nth, cl_princ_to_string
+def build(n, acc):
+    while True:
+        if n == 0:
+            return acc
+        else:
+            n, acc = n - 1, cl_cons(n, acc)
+            continue
+
+xs = build(200000, None)
+print(len(xs or []))
+print((cl_cdr(xs) or [None])[0])
+small = cl_cons(1, cl_cons(2, [3]))
+print(small)
+print(small == [1, 2, 3])
+print([1, 2] == small)
+print((small or []) + [4])
+print((small or [])[::-1])
+print(cl_nth(2, small))
+print("{}\n".format(cl_princ_to_string(cl_cons(0, small))), end="")
@@ -0,0 +1,23 @@
+from lisp_desu_runtime import cl_cdr, cl_not
+def classify(n):
+    if n < 0:
+        return "negative"
+    elif n == 0:
+        return "zero"
+    else:
+        return "positive"
+
+def first_over(limit, xs):
+    while True:
+        if cl_not(xs):
+            return None
+        if (xs or [None])[0] > limit:
+            return (xs or [None])[0]
+        xs = cl_cdr(xs)
+
+xs = [-2, 0, 5, 12]
+big = first_over(3, xs)
+print(classify((xs or [None])[0]))
+print(classify(0))
+print(big)
+print(first_over(20, xs))
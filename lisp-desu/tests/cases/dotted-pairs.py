@@ -0,0 +1,9 @@
+from lisp_desu_runtime import cl_append, cl_cdr, cl_cons, cl_length, cl_list
+pair = cl_cons(1, 2)
+print(pair)
+print(cl_cdr(pair))
+chain = cl_cons(0, cl_cons(1, cl_list(2, 3)))
+print(cl_length(chain))
+print(cl_append(chain, cl_cons(4, 5)))
+print(cl_cons(1, cl_list(2)) == cl_list(1, 2))
+print(cl_cons(1, 2) == cl_cons(1, 2))
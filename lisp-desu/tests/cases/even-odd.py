@@ -0,0 +1,19 @@
+from lisp_desu_runtime import TailCall, cl_trampoline
+def _my_even_p_body(n):
+    if n == 0:
+        return True
+    else:
+        return TailCall(_my_odd_p_body, n - 1)
+def my_even_p(n):
+    return cl_trampoline(_my_even_p_body, n)
+
+def _my_odd_p_body(n):
+    if n == 0:
+        return None
+    else:
+        return TailCall(_my_even_p_body, n - 1)
+def my_odd_p(n):
+    return cl_trampoline(_my_odd_p_body, n)
+
+print(my_even_p(10000))
+print(my_odd_p(7))
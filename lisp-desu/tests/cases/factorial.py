@@ -0,0 +1,9 @@
+def factorial(n, acc=1):
+    "N!, multiplying into ACC."
+    if n <= 1:
+        return acc
+    else:
+        return factorial(n - 1, acc * n)
+
+print(factorial(10))
+print(factorial(30))
@@ -0,0 +1,7 @@
+_width_ = 4 * 20
+
+def area(height):
+    return _width_ * height
+
+print(1 + 2 + 3)
+print(area(10 - 4))
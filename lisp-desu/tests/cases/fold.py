@@ -0,0 +1,7 @@
+_width_ = 80
+
+def area(height):
+    return _width_ * height
+
+print(6)
+print(area(6))
@@ -0,0 +1,22 @@
+from lisp_desu_runtime import cl_cdr, cl_format, cl_line_break, cl_princ, cl_princ_to_string, cl_write_to_string
+_control_ = None
+print("{} {} {} {}\n".format(cl_princ_to_string(None), cl_princ_to_string([1, 2]), cl_write_to_string("hi"), 42), end="")
+_control_ = "~a ~a ~s ~d~%"
+cl_format(True, _control_, None, [1, 2], "hi", 42)
+print("{}|{}\n".format(cl_princ_to_string(cl_cdr([1, 2, 3])), cl_write_to_string(["a", None])), end="")
+_control_ = "~a|~s~%"
+cl_format(True, _control_, cl_cdr([1, 2, 3]), ["a", None])
+cl_princ("no newline")
+print(f"{cl_line_break(True)}fresh\n", end="")
+cl_princ("no newline")
+_control_ = "~&fresh~%"
+cl_format(True, _control_)
+print(f"{cl_line_break(True)}already fresh\n", end="")
+_control_ = "~&already fresh~%"
+cl_format(True, _control_)
+print(f"{cl_princ_to_string(True)}")
+_control_ = "~&~a"
+print(cl_format(None, _control_, True))
+print("{}\n".format(", ".join(cl_princ_to_string(_x) for _x in [1, None, "x"])), end="")
+print("{}\n".format(" ".join("{}={}".format(cl_princ_to_string(_xs[0]), cl_write_to_string(_xs[1])) for _xs in zip(*[iter(["a", "b", None, [1]])] * 2))), end="")
+print("[{}]\n".format("".join(cl_princ_to_string(_x) for _x in (None or []))), end="")
@@ -0,0 +1,9 @@
+import math
+
+def hypot(a, b):
+    return math.sqrt(a * a + b * b)
+
+xs = [3, 1, 2]
+xs.sort(reverse=True)
+print(xs)
+print(hypot(3, 4))
@@ -0,0 +1,31 @@
+from lisp_desu_runtime import cl_cdr, cl_cons, cl_not, cl_nth, cl_write_to_string
+def build(n, acc):
+    while True:
+        if n == 0:
+            return acc
+        else:
+            n, acc = n - 1, cl_cons(n, acc)
+            continue
+
+def sum(xs):
+    total = 0
+    while True:
+        if cl_not(xs):
+            return total
+        total = total + (xs or [None])[0]
+        xs = cl_cdr(xs)
+
+print(build(3, None))
+xs = None
+print(len(xs or []))
+print((xs or [None])[0])
+print(cl_cdr(xs))
+print(cl_nth(2, xs))
+print((xs or []) + [1] + (xs or []))
+print((xs or [])[::-1])
+print(cl_cdr([1]))
+print(cl_nth(5, [1, 2]))
+print(len(cl_cdr(build(4, None)) or []))
+print(cl_write_to_string(cl_cdr(build(4, None))))
+print(cl_cons(0, cl_cdr(build(2, None))))
+print(sum(list(range(1000001))))
@@ -0,0 +1,25 @@
+x = 10
+
+def twice(n):
+    return n * 2
+
+x_1 = 1
+_t1 = x_1 + 1
+print(_t1)
+print(x)
+twice_1 = 3
+_t2 = twice_1 + 1
+print(_t2)
+print(twice(4))
+
+def sq(x_1):
+    return x_1 * x_1
+
+def apply_sq(sq_1):
+    return sq(sq_1)
+
+print(apply_sq(3))
+print_1 = 5
+len_1 = "ab"
+_t3 = print_1 + len(len_1 or [])
+print(_t3)
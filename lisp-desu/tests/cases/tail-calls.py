@@ -0,0 +1,29 @@
+from lisp_desu_runtime import TailCall, cl_trampoline
+def count_down(n, acc):
+    while True:
+        if n == 0:
+            return acc
+        else:
+            m = n - 1
+            n, acc = m, acc + 1
+            continue
+
+def _ping_body(n):
+    if n == 0:
+        return "ping"
+    else:
+        m = n - 1
+        return TailCall(_pong_body, m)
+def ping(n):
+    return cl_trampoline(_ping_body, n)
+
+def _pong_body(n):
+    if n == 0:
+        return "pong"
+    else:
+        return TailCall(_ping_body, n - 1)
+def pong(n):
+    return cl_trampoline(_pong_body, n)
+
+print(count_down(100000, 0))
+print(ping(100001))
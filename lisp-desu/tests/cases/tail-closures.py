@@ -0,0 +1,17 @@
+from lisp_desu_runtime import cl_cdr, cl_cons, cl_true
+def collect(n, acc):
+    if n == 0:
+        return acc
+    else:
+        return collect(n - 1, cl_cons(lambda: n, acc))
+
+def call_all(fs):
+    while True:
+        if cl_true(fs):
+            print((fs or [None])[0]())
+            fs = cl_cdr(fs)
+            continue
+        else:
+            return None
+
+call_all(collect(3, None))
@@ -0,0 +1,39 @@
+from lisp_desu_runtime import cl_cdr, cl_not, cl_true
+def check(x):
+    if cl_true(x):
+        return "true"
+    else:
+        return "false"
+
+def describe(x):
+    if cl_not(x):
+        return "nil"
+    elif x == 0:
+        return "zero"
+    else:
+        return "other"
+
+def first_true(a, b):
+    return a if cl_true(a) else b
+
+def both(a, b):
+    return b if cl_true(a) else a
+
+print([check(0), check(""), check(None), check([]), check(2 < 1)])
+print([describe(None), describe(0), describe(7)])
+print([first_true(0, 1), first_true(None, 1), first_true("", "x")])
+print([both(0, 1), both(None, 1), both(1, None)])
+print([cl_not(0), cl_not(""), not None, not 1 < 2])
+print(["more" if cl_true(cl_cdr([1])) else "last", "more" if cl_true(cl_cdr([1, 2])) else "last"])
+x = 0
+print(x)
+_t2 = x
+if cl_true(_t2):
+    _t2 = "after"
+print(_t2)
+x = None
+_t6 = x
+if not cl_true(_t6):
+    print("second")
+    _t6 = 0
+print(_t6)
@@ -0,0 +1,4 @@
+def used(x):
+    return x * 2
+
+print(used(21))
@@ -0,0 +1,108 @@
+//! Tests of the inotify events `build --watch` rebuilds on.
+#![cfg(target_os = "linux")]
+use lisp_desu::inotify::{
+    forget, parse_events, relevant, Event, IN_CLOSE_WRITE, IN_CREATE, IN_DELETE, IN_IGNORED,
+    IN_ISDIR, IN_MOVED_FROM,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use support::Scratch;
+
+#[path = "../../ast/tests/support/mod.rs"]
+mod support;
+
+/// A `struct inotify_event` naming `name`, padded as the kernel pads it.
+fn raw_event(wd: i32, mask: u32, name: &str) -> Vec<u8> {
+    let len = match name.len() {
+        0 => 0,
+        len => (len / 16 + 1) * 16,
+    };
+    let mut bytes = vec![];
+    bytes.extend(wd.to_ne_bytes());
+    bytes.extend(mask.to_ne_bytes());
+    bytes.extend(0u32.to_ne_bytes());
+    bytes.extend((len as u32).to_ne_bytes());
+    bytes.extend(name.as_bytes());
+    bytes.resize(16 + len, 0);
+    bytes
+}
+
+fn event(wd: i32, mask: u32, name: &str) -> Event {
+    Event {
+        wd,
+        mask,
+        name: PathBuf::from(name),
+    }
+}
+
+#[test]
+fn parses_events() {
+    let mut buf = raw_event(1, IN_CLOSE_WRITE, "main.lisu");
+    buf.extend(raw_event(2, IN_IGNORED, ""));
+    buf.extend(raw_event(
+        3,
+        IN_CREATE | IN_ISDIR,
+        "a-longer-directory-name",
+    ));
+    assert_eq!(
+        parse_events(&buf),
+        [
+            event(1, IN_CLOSE_WRITE, "main.lisu"),
+            event(2, IN_IGNORED, ""),
+            event(3, IN_CREATE | IN_ISDIR, "a-longer-directory-name"),
+        ]
+    );
+}
+
+#[test]
+fn stops_at_truncated_events() {
+    let mut buf = raw_event(1, IN_CLOSE_WRITE, "main.lisu");
+    let truncated = raw_event(2, IN_CLOSE_WRITE, "other.lisu");
+    buf.extend(&truncated[..20]);
+    assert_eq!(parse_events(&buf), [event(1, IN_CLOSE_WRITE, "main.lisu")]);
+}
+
+#[test]
+fn finds_changed_sources_of_a_file() {
+    let dir = Scratch::new("inotify-file");
+    let input = dir.join("main.lisu");
+    let dirs = HashMap::from([(1, dir.to_path_buf())]);
+    let written = event(1, IN_CLOSE_WRITE, "main.lisu");
+    assert_eq!(relevant(&written, &dirs, &input), Some(input.clone()));
+    let other = event(1, IN_CLOSE_WRITE, "other.lisu");
+    assert_eq!(relevant(&other, &dirs, &input), None);
+    let unwatched = event(2, IN_CLOSE_WRITE, "main.lisu");
+    assert_eq!(relevant(&unwatched, &dirs, &input), None);
+}
+
+#[test]
+fn finds_changed_sources_of_a_directory() {
+    let dir = Scratch::new("inotify-dir");
+    let dirs = HashMap::from([(1, dir.to_path_buf())]);
+    let relevant = |mask, name| relevant(&event(1, mask, name), &dirs, &dir);
+    assert_eq!(relevant(IN_CLOSE_WRITE, "a.lisu"), Some(dir.join("a.lisu")));
+    assert_eq!(relevant(IN_CREATE | IN_ISDIR, "new"), Some(dir.join("new")));
+    assert_eq!(relevant(IN_CLOSE_WRITE, "notes.txt"), None);
+    assert_eq!(relevant(IN_CLOSE_WRITE, ".a.lisu"), None);
+    assert_eq!(relevant(IN_CREATE | IN_ISDIR, ".git"), None);
+}
+
+#[test]
+fn forgets_deleted_directories() {
+    let root = Path::new("/src");
+    let mut dirs = HashMap::from([
+        (1, root.to_owned()),
+        (2, root.join("a")),
+        (3, root.join("a/b")),
+        (4, root.join("c")),
+        (5, root.join("d")),
+    ]);
+    forget(&event(1, IN_DELETE, "c"), &mut dirs);
+    assert_eq!(dirs.len(), 5, "files leave the directories alone");
+    forget(&event(1, IN_DELETE | IN_ISDIR, "a"), &mut dirs);
+    assert!(!dirs.contains_key(&2) && !dirs.contains_key(&3), "{dirs:?}");
+    forget(&event(1, IN_MOVED_FROM | IN_ISDIR, "c"), &mut dirs);
+    assert!(!dirs.contains_key(&4), "{dirs:?}");
+    forget(&event(5, IN_IGNORED, ""), &mut dirs);
+    assert_eq!(dirs, HashMap::from([(1, root.to_owned())]));
+}
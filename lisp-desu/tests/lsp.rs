@@ -0,0 +1,219 @@
+//! Tests of the language server, driven over stdio as an editor would.
+use ast::json::Json;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+struct Client {
+    server: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_lisp-desu"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("starts the server");
+        let input = server.stdin.take().expect("has stdin");
+        let output = BufReader::new(server.stdout.take().expect("has stdout"));
+        Self {
+            server,
+            input,
+            output,
+        }
+    }
+
+    fn send(&mut self, message: Json) {
+        let body = message.to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{body}", body.len()).expect("sends");
+        self.input.flush().expect("sends");
+    }
+
+    fn receive(&mut self) -> Json {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            self.output.read_line(&mut line).expect("receives");
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (_, value) = line.split_once(':').expect("header");
+            length = value.trim().parse::<usize>().ok();
+        }
+        let mut body = vec![0; length.expect("has a Content-Length")];
+        self.output.read_exact(&mut body).expect("receives");
+        Json::parse(&String::from_utf8(body).expect("is UTF-8")).expect("is JSON")
+    }
+
+    fn request(&mut self, id: usize, method: &str, params: Json) -> Json {
+        self.send(Json::object([
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+        let response = self.receive();
+        assert_eq!(response.get("id").and_then(Json::as_u64), Some(id as u64));
+        response
+    }
+
+    /// Open `text` as the document `uri`, and return the diagnostics
+    /// published for it.
+    fn open(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        self.send(Json::object([
+            ("method", "textDocument/didOpen".into()),
+            (
+                "params",
+                Json::object([(
+                    "textDocument",
+                    Json::object([("uri", uri.into()), ("text", text.into())]),
+                )]),
+            ),
+        ]));
+        self.diagnostics(uri)
+    }
+
+    /// Diagnostics next published for `uri`.
+    fn diagnostics(&mut self, uri: &str) -> Vec<Json> {
+        let notification = self.receive();
+        assert_eq!(
+            notification.get("method").and_then(Json::as_str),
+            Some("textDocument/publishDiagnostics")
+        );
+        let params = notification.get("params").expect("has params");
+        assert_eq!(params.get("uri").and_then(Json::as_str), Some(uri));
+        match params.get("diagnostics") {
+            Some(Json::Array(diagnostics)) => diagnostics.clone(),
+            diagnostics => panic!("diagnostics are an array: {diagnostics:?}"),
+        }
+    }
+
+    /// Change the document `uri` to `text`, and return the diagnostics
+    /// published for it.
+    fn change(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        self.send(Json::object([
+            ("method", "textDocument/didChange".into()),
+            (
+                "params",
+                Json::object([
+                    ("textDocument", Json::object([("uri", uri.into())])),
+                    (
+                        "contentChanges",
+                        vec![Json::object([("text", text.into())])].into(),
+                    ),
+                ]),
+            ),
+        ]));
+        self.diagnostics(uri)
+    }
+
+    /// Result of the request `method` at `line` and `character` of `uri`.
+    fn at(&mut self, id: usize, method: &str, uri: &str, line: usize, character: usize) -> Json {
+        let position = Json::object([("line", line.into()), ("character", character.into())]);
+        let params = Json::object([
+            ("textDocument", Json::object([("uri", uri.into())])),
+            ("position", position),
+        ]);
+        let response = self.request(id, method, params);
+        response.get("result").cloned().expect("has a result")
+    }
+
+    fn shut_down(mut self) -> i32 {
+        self.request(1000, "shutdown", Json::Null);
+        self.send(Json::object([("method", "exit".into())]));
+        self.server
+            .wait()
+            .expect("exits")
+            .code()
+            .expect("has a code")
+    }
+}
+
+#[test]
+fn survives_malformed_documents() {
+    let mut client = Client::start();
+    client.request(1, "initialize", Json::object([]));
+    for (i, text) in [
+        ")",
+        "42",
+        "(a . )",
+        ".",
+        "'",
+        "(print \"abc",
+        "(defun f (",
+        ",@",
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let uri = format!("file:///malformed{i}.lisu");
+        let diagnostics = client.open(&uri, text);
+        assert!(!diagnostics.is_empty(), "{text:?} has diagnostics");
+    }
+
+    // The server still answers about documents that can be read
+    let uri = "file:///ok.lisu";
+    assert!(client
+        .open(uri, "(defun f (x) x)\n(print (f 1))\n")
+        .is_empty());
+    let hover = client.request(
+        2,
+        "textDocument/hover",
+        Json::object([
+            ("textDocument", Json::object([("uri", uri.into())])),
+            (
+                "position",
+                Json::object([("line", 1.into()), ("character", 8.into())]),
+            ),
+        ]),
+    );
+    assert!(
+        matches!(hover.get("result"), Some(Json::Object(_))),
+        "{hover}"
+    );
+    assert_eq!(client.shut_down(), 0);
+}
+
+#[test]
+fn forgets_the_analysis_of_earlier_versions() {
+    let mut client = Client::start();
+    client.request(1, "initialize", Json::object([]));
+    let uri = "file:///edited.lisu";
+    client.open(uri, "(defun f (x) x)\n(print (f 1))\n");
+    assert!(matches!(
+        client.at(2, "textDocument/hover", uri, 1, 8),
+        Json::Object(_)
+    ));
+
+    // Spans of the first version would point into the wrong text
+    assert!(!client.change(uri, "\n\n(print (f 1)").is_empty());
+    assert_eq!(client.at(3, "textDocument/hover", uri, 1, 8), Json::Null);
+    assert_eq!(
+        client.at(4, "textDocument/definition", uri, 2, 8),
+        Json::Null
+    );
+    assert_eq!(client.shut_down(), 0);
+}
+
+#[test]
+fn counts_positions_in_utf16_code_units() {
+    let mut client = Client::start();
+    client.request(1, "initialize", Json::object([]));
+    let uri = "file:///emoji.lisu";
+    let diagnostics = client.open(uri, "(print \"\u{1f600}\" nope)\n");
+    let range = diagnostics[0].get("range").expect("has a range");
+    let character = |at: &str| range.get(at)?.get("character")?.as_u64();
+    assert_eq!((character("start"), character("end")), (Some(12), Some(16)));
+
+    // `f` follows three characters taking two code units each
+    client.change(
+        uri,
+        "(defun f (x) x)\n(print \"\u{1f600}\u{1f600}\u{1f600}\" (f 1))\n",
+    );
+    let hover = client.at(2, "textDocument/hover", uri, 1, 17);
+    assert!(matches!(hover, Json::Object(_)), "{hover}");
+    assert_eq!(client.shut_down(), 0);
+}
@@ -0,0 +1,65 @@
+//! Tests of rewriting Python tracebacks to the Lisp forms they come from.
+use ast::sourcemap::SourceMap;
+use lexer::Span;
+use lisp_desu::trace;
+use std::fs;
+use support::Scratch;
+
+#[path = "../../ast/tests/support/mod.rs"]
+mod support;
+
+#[test]
+fn rewrites_frames_of_mapped_modules() {
+    let dir = Scratch::new("trace");
+    let source = dir.join("inverse.lisu");
+    fs::write(
+        &source,
+        "(defun inverse (n)\n  (/ 1 n))\n\n(print (inverse 0))\n",
+    )
+    .expect("writes source");
+    let map = SourceMap {
+        source: source.display().to_string(),
+        lines: vec![
+            Span::new(1, 2, 1, 10),
+            Span::new(2, 2, 3, 9),
+            Span::default(),
+            Span::new(4, 4, 1, 19),
+        ],
+    };
+    let module = dir.join("inverse.py");
+    fs::write(dir.join("inverse.py.map"), map.to_json()).expect("writes map");
+
+    let module = module.display();
+    let source = source.display();
+    let traceback = format!(
+        "\
+Traceback (most recent call last):
+  File \"{module}\", line 4, in <module>
+    print(inverse(0))
+          ^^^^^^^^^^
+  File \"{module}\", line 2, in inverse
+    return 1 / n
+           ~~^~~
+  File \"{module}\", line 3
+  File \"/usr/lib/python3/other.py\", line 7, in helper
+    helper()
+ZeroDivisionError: division by zero
+"
+    );
+    assert_eq!(
+        trace::rewrite(&traceback),
+        format!(
+            "\
+Traceback (most recent call last):
+  {source}:4:1, in <module>
+    (print (inverse 0))
+  {source}:2:3, in inverse
+    (/ 1 n))
+  File \"{module}\", line 3
+  File \"/usr/lib/python3/other.py\", line 7, in helper
+    helper()
+ZeroDivisionError: division by zero
+"
+        )
+    );
+}
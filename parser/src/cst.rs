@@ -0,0 +1,758 @@
+//! Lossless concrete syntax tree, for tools that rewrite sources.
+//!
+//! Unlike the [`Token`](crate::Token)s read by [`StringReader`](crate::StringReader),
+//! every byte of the source belongs to a token of the tree, whitespace and
+//! comments included, so the text of the root is the source exactly. The
+//! tree is read from any source: mismatched parentheses become error nodes
+//! and are reported alongside it.
+//!
+//! The tree is split in two, like rowan's. Green nodes are immutable, know
+//! only their kind, text and children, and may be shared between trees. Red
+//! [`SyntaxNode`]s are cheap handles built on demand over a green tree,
+//! adding parents and offsets into the source. Sharing lets an editor
+//! [`reparse`](Parse::reparse) only the forms an edit touches.
+use lexer::{Cursor, Span, TokenKind as LexerTokenKind};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::ops::Range;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    // Tokens
+    Whitespace,
+    /// `; ...` up to the end of the line.
+    Comment,
+    OpenParen,
+    CloseParen,
+    /// `'` or `` ` ``
+    Quote,
+    /// `.` of a dotted pair.
+    Dot,
+    Comma,
+    Symbol,
+    Number,
+    String,
+
+    // Nodes
+    Root,
+    /// Parenthesised list, missing its `)` if unclosed.
+    List,
+    /// Quote followed by the quoted datum.
+    Quoted,
+    /// Tokens that cannot start a datum, such as an unmatched `)`.
+    Error,
+}
+
+impl SyntaxKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::Comment)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenToken {
+    kind: SyntaxKind,
+    text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    len: usize,
+    children: Vec<GreenElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: impl Into<String>) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::len).sum();
+        Self {
+            kind,
+            len,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    /// Length of the text in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+impl GreenElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            GreenElement::Node(node) => node.kind,
+            GreenElement::Token(token) => token.kind,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{node}")?,
+                GreenElement::Token(token) => f.write_str(&token.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Node of the tree, located in its source.
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    /// Index among the children of the parent.
+    index: usize,
+    offset: usize,
+}
+
+/// Token of the tree, located in its source.
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    parent: SyntaxNode,
+    index: usize,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+impl Eq for SyntaxNode {}
+
+impl PartialEq for SyntaxToken {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.green, &other.green) && self.offset == other.offset
+    }
+}
+
+impl Eq for SyntaxToken {}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        Self(Rc::new(NodeData {
+            green,
+            parent: None,
+            index: 0,
+            offset: 0,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    /// Index among the children of the parent.
+    pub fn index(&self) -> usize {
+        self.0.index
+    }
+
+    /// Byte range of the node in the source.
+    pub fn text_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.len
+    }
+
+    pub fn text(&self) -> String {
+        self.0.green.to_string()
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+        self.0
+            .green
+            .children
+            .iter()
+            .enumerate()
+            .map(move |(index, child)| {
+                let element = match child {
+                    GreenElement::Node(green) => {
+                        SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                            green: green.clone(),
+                            parent: Some(self.clone()),
+                            index,
+                            offset,
+                        })))
+                    }
+                    GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                        green: green.clone(),
+                        parent: self.clone(),
+                        index,
+                        offset,
+                    }),
+                };
+                offset += child.len();
+                element
+            })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens()
+            .filter_map(|element| match element {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+    }
+
+    /// Children other than whitespace and comments.
+    pub fn significant_children(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        self.children_with_tokens()
+            .filter(|element| !element.kind().is_trivia())
+    }
+
+    /// This node and the nodes below it, in source order.
+    pub fn descendants(&self) -> Vec<SyntaxNode> {
+        let mut out = vec![self.clone()];
+        for child in self.children() {
+            out.extend(child.descendants());
+        }
+        out
+    }
+
+    /// Tokens below this node, in source order.
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut out = vec![];
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) => out.extend(node.tokens()),
+                SyntaxElement::Token(token) => out.push(token),
+            }
+        }
+        out
+    }
+
+    /// Token containing the byte at `offset`.
+    pub fn token_at_offset(&self, offset: usize) -> Option<SyntaxToken> {
+        if !self.text_range().contains(&offset) {
+            return None;
+        }
+        self.children_with_tokens()
+            .find(|child| child.text_range().contains(&offset))
+            .and_then(|child| match child {
+                SyntaxElement::Node(node) => node.token_at_offset(offset),
+                SyntaxElement::Token(token) => Some(token),
+            })
+    }
+
+    /// The node and its ancestors, innermost first.
+    pub fn ancestors(&self) -> impl Iterator<Item = SyntaxNode> {
+        std::iter::successors(Some(self.clone()), SyntaxNode::parent)
+    }
+}
+
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn green(&self) -> &Rc<GreenToken> {
+        &self.green
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+
+    /// Index among the children of the parent.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Byte range of the token in the source.
+    pub fn text_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+}
+
+impl SyntaxElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            SyntaxElement::Node(node) => node.kind(),
+            SyntaxElement::Token(token) => token.kind(),
+        }
+    }
+
+    pub fn text_range(&self) -> Range<usize> {
+        match self {
+            SyntaxElement::Node(node) => node.text_range(),
+            SyntaxElement::Token(token) => token.text_range(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            SyntaxElement::Node(node) => node.text(),
+            SyntaxElement::Token(token) => token.text().to_owned(),
+        }
+    }
+}
+
+/// Problem found while reading a concrete syntax tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CstError {
+    pub message: &'static str,
+    /// Byte range of the offending text.
+    pub range: Range<usize>,
+}
+
+/// Span of the characters of `src` in the byte `range`.
+pub fn span(src: &str, range: Range<usize>) -> Span {
+    let position = |offset: usize| {
+        let before = &src[..offset];
+        let row = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        (row, src[line_start..offset].chars().count() + 1)
+    };
+    let (start_row, start_col) = position(range.start);
+    // Spans include their last character
+    let last = src[..range.end]
+        .char_indices()
+        .next_back()
+        .map_or(0, |(offset, _)| offset)
+        .max(range.start);
+    let (end_row, end_col) = position(last);
+    Span::new(start_row, end_row, start_col, end_col)
+}
+
+/// A concrete syntax tree and the problems found reading it.
+#[derive(Debug, Clone)]
+pub struct Parse {
+    pub green: Rc<GreenNode>,
+    pub errors: Vec<CstError>,
+}
+
+impl Parse {
+    pub fn syntax(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+}
+
+/// Read the concrete syntax tree of `src`, whose root's text is `src`.
+pub fn parse(src: &str) -> Parse {
+    let mut builder = Builder::new(src, 0, None);
+    builder.start();
+    while builder.peek().is_some() {
+        builder.element();
+    }
+    let root = builder.finish(SyntaxKind::Root);
+    // Unclosed lists are found innermost first
+    builder.errors.sort_by_key(|error| error.range.start);
+    Parse {
+        green: root,
+        errors: builder.errors,
+    }
+}
+
+/// Replacement of the byte `range` of a source, as made by an editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl Edit {
+    /// The source after the edit.
+    pub fn apply(&self, src: &str) -> String {
+        let mut out = src.to_owned();
+        out.replace_range(self.range.clone(), &self.replacement);
+        out
+    }
+
+    /// Offset after the edit of `offset`, which is not in the range.
+    fn shift(&self, offset: usize) -> usize {
+        offset - self.range.len() + self.replacement.len()
+    }
+}
+
+impl Parse {
+    /// Read the tree of `src`, which is the source of this tree after `edit`.
+    ///
+    /// Only the top-level forms touched by the edit are relexed and
+    /// reparsed, going on past them for as long as the parentheses do not
+    /// balance. Closed lists left unchanged by the edit are shared with this
+    /// tree rather than read again, as are the top-level forms before and
+    /// after. The result is the same as [`parse`]'s.
+    pub fn reparse(&self, src: &str, edit: &Edit) -> Parse {
+        let children = self.green.children();
+        let mut starts = Vec::with_capacity(children.len());
+        let mut offset = 0;
+        for child in children {
+            starts.push(offset);
+            offset += child.len();
+        }
+        let end = |i: usize| starts[i] + children[i].len();
+
+        // The element ending where the edit starts is touched too, as the
+        // lexer looks at the next character to end a token
+        let first = (0..children.len())
+            .find(|&i| end(i) >= edit.range.start)
+            .unwrap_or(children.len().saturating_sub(1));
+        let last = (first..children.len())
+            .take_while(|&i| starts[i] <= edit.range.end)
+            .last()
+            .unwrap_or(first);
+        let from = starts.get(first).copied().unwrap_or(0);
+
+        let reuse = Reuse {
+            old: self,
+            starts: &starts,
+            edit,
+            lists: children
+                .get(first..=last)
+                .unwrap_or_default()
+                .iter()
+                .zip(&starts[first..])
+                .flat_map(|(child, &start)| self.unchanged_lists(child, start, edit))
+                .collect(),
+        };
+        let mut builder = Builder::new(src, from, Some(reuse));
+        builder.start();
+        // Reparse up to the first form after the edit that starts at the top
+        // level, from which on the text and so the tree are as before
+        let mut resume = children.len();
+        while builder.peek().is_some() {
+            builder.element();
+            if let Some(i) = builder.resumes_at(builder.offset) {
+                resume = i;
+                break;
+            }
+        }
+        let reparsed = builder.stack.pop().expect("node was started");
+
+        let mut green = children[..first].to_vec();
+        green.extend(reparsed);
+        green.extend_from_slice(&children[resume..]);
+        let resumed = starts.get(resume).copied().unwrap_or(usize::MAX);
+        let mut errors = self
+            .errors
+            .iter()
+            .filter(|error| error.range.end <= from)
+            .cloned()
+            .collect::<Vec<_>>();
+        errors.extend(builder.errors);
+        errors.extend(
+            self.errors
+                .iter()
+                .filter(|error| error.range.start >= resumed)
+                .map(|error| CstError {
+                    message: error.message,
+                    range: edit.shift(error.range.start)..edit.shift(error.range.end),
+                }),
+        );
+        errors.sort_by_key(|error| error.range.start);
+        Parse {
+            green: Rc::new(GreenNode::new(SyntaxKind::Root, green)),
+            errors,
+        }
+    }
+
+    /// Closed lists in `element`, which starts at `start`, that neither
+    /// overlap `edit` nor hold errors, by their offset after it.
+    fn unchanged_lists(
+        &self,
+        element: &GreenElement,
+        start: usize,
+        edit: &Edit,
+    ) -> Vec<(usize, Rc<GreenNode>)> {
+        let GreenElement::Node(node) = element else {
+            return vec![];
+        };
+        let end = start + node.len();
+        let before = end <= edit.range.start;
+        let after = start >= edit.range.end;
+        if (before || after) && self.is_closed_list(node, start) {
+            let start = if before { start } else { edit.shift(start) };
+            return vec![(start, node.clone())];
+        }
+        let mut out = vec![];
+        let mut offset = start;
+        for child in node.children() {
+            out.extend(self.unchanged_lists(child, offset, edit));
+            offset += child.len();
+        }
+        out
+    }
+
+    /// Whether `node`, at `start`, is a list with its `)` and no errors.
+    fn is_closed_list(&self, node: &GreenNode, start: usize) -> bool {
+        let range = start..start + node.len();
+        node.kind == SyntaxKind::List
+            && node
+                .children
+                .last()
+                .is_some_and(|last| last.kind() == SyntaxKind::CloseParen)
+            && !self
+                .errors
+                .iter()
+                .any(|error| range.contains(&error.range.start))
+    }
+}
+
+/// What a reparse may take from the tree before an edit.
+struct Reuse<'p> {
+    old: &'p Parse,
+    /// Offsets of the top-level elements of the old tree.
+    starts: &'p [usize],
+    edit: &'p Edit,
+    /// Lists of the forms touched by the edit that it left unchanged, by
+    /// their offset after the edit.
+    lists: HashMap<usize, Rc<GreenNode>>,
+}
+
+impl Reuse<'_> {
+    /// Index of the old top-level element after the edit found at `offset`
+    /// after it.
+    fn top_level(&self, offset: usize) -> Option<usize> {
+        let edit = self.edit;
+        let old = (offset + edit.range.len()).checked_sub(edit.replacement.len())?;
+        if offset < edit.range.start + edit.replacement.len() || old < edit.range.end {
+            return None;
+        }
+        self.starts.binary_search(&old).ok()
+    }
+
+    /// Unchanged closed list found at `offset` after the edit.
+    fn list(&self, offset: usize) -> Option<Rc<GreenNode>> {
+        if let Some(list) = self.lists.get(&offset) {
+            return Some(list.clone());
+        }
+        // Forms after the edit may end up in a list left unclosed by it
+        let i = self.top_level(offset)?;
+        match &self.old.green.children()[i] {
+            GreenElement::Node(node) if self.old.is_closed_list(node, self.starts[i]) => {
+                Some(node.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn syntax_kind(kind: LexerTokenKind, text: &str) -> SyntaxKind {
+    match kind {
+        LexerTokenKind::Whitespace => SyntaxKind::Whitespace,
+        LexerTokenKind::LineComment => SyntaxKind::Comment,
+        LexerTokenKind::OpenParen => SyntaxKind::OpenParen,
+        LexerTokenKind::CloseParen => SyntaxKind::CloseParen,
+        LexerTokenKind::Backquote => SyntaxKind::Quote,
+        LexerTokenKind::Dot => SyntaxKind::Dot,
+        LexerTokenKind::Comma => SyntaxKind::Comma,
+        LexerTokenKind::Literal if text.starts_with('"') => SyntaxKind::String,
+        LexerTokenKind::Literal => SyntaxKind::Number,
+        _ => SyntaxKind::Symbol,
+    }
+}
+
+struct Builder<'s, 'p> {
+    src: &'s str,
+    /// Lexer of the source from `lexed` on, run as tokens are needed.
+    cursor: Cursor<'s>,
+    lexed: usize,
+    /// Token read by `peek` but not yet added.
+    peeked: Option<(SyntaxKind, &'s str)>,
+    offset: usize,
+    /// Children of the nodes being built, innermost last.
+    stack: Vec<Vec<GreenElement>>,
+    errors: Vec<CstError>,
+    reuse: Option<Reuse<'p>>,
+}
+
+impl<'s, 'p> Builder<'s, 'p> {
+    /// Builder reading `src` from `offset`, which starts a token.
+    fn new(src: &'s str, offset: usize, reuse: Option<Reuse<'p>>) -> Self {
+        Self {
+            src,
+            cursor: Cursor::new(&src[offset..]),
+            lexed: offset,
+            peeked: None,
+            offset,
+            stack: vec![],
+            errors: vec![],
+            reuse,
+        }
+    }
+
+    fn peek(&mut self) -> Option<SyntaxKind> {
+        if self.peeked.is_none() {
+            let token = self.cursor.next_token();
+            if token.kind == LexerTokenKind::EOF {
+                return None;
+            }
+            let text = &self.src[self.lexed..self.lexed + token.len];
+            self.lexed += token.len;
+            self.peeked = Some((syntax_kind(token.kind, text), text));
+        }
+        self.peeked.map(|(kind, _)| kind)
+    }
+
+    fn start(&mut self) {
+        self.stack.push(vec![]);
+    }
+
+    fn finish(&mut self, kind: SyntaxKind) -> Rc<GreenNode> {
+        let children = self.stack.pop().expect("node was started");
+        let node = Rc::new(GreenNode::new(kind, children));
+        if let Some(parent) = self.stack.last_mut() {
+            parent.push(GreenElement::Node(node.clone()));
+        }
+        node
+    }
+
+    fn bump(&mut self) {
+        self.peek();
+        let (kind, text) = self.peeked.take().expect("token was peeked");
+        self.offset += text.len();
+        let token = GreenElement::Token(Rc::new(GreenToken::new(kind, text)));
+        self.stack.last_mut().expect("node was started").push(token);
+    }
+
+    fn error(&mut self, message: &'static str, range: Range<usize>) {
+        self.errors.push(CstError { message, range });
+    }
+
+    /// Index of the old top-level element from which on the old tree holds,
+    /// when reparsing and `offset` is at the top level.
+    fn resumes_at(&self, offset: usize) -> Option<usize> {
+        self.reuse.as_ref()?.top_level(offset)
+    }
+
+    /// Add `node` of the old tree in place of the tokens of its text.
+    fn reuse(&mut self, node: Rc<GreenNode>) {
+        self.offset += node.len();
+        self.cursor = Cursor::new(&self.src[self.offset..]);
+        self.lexed = self.offset;
+        self.peeked = None;
+        let parent = self.stack.last_mut().expect("node was started");
+        parent.push(GreenElement::Node(node));
+    }
+
+    /// Read trivia or a datum.
+    fn element(&mut self) {
+        let Some(kind) = self.peek() else {
+            return;
+        };
+        let start = self.offset;
+        match kind {
+            SyntaxKind::OpenParen => {
+                if let Some(list) = self.reuse.as_ref().and_then(|reuse| reuse.list(start)) {
+                    return self.reuse(list);
+                }
+                self.start();
+                self.bump();
+                loop {
+                    match self.peek() {
+                        None => {
+                            self.error("unclosed `(`", start..start + 1);
+                            break;
+                        }
+                        Some(SyntaxKind::CloseParen) => {
+                            self.bump();
+                            break;
+                        }
+                        Some(_) => self.element(),
+                    }
+                }
+                self.finish(SyntaxKind::List);
+            }
+            SyntaxKind::CloseParen => {
+                self.start();
+                self.bump();
+                self.finish(SyntaxKind::Error);
+                self.error("unmatched `)`", start..self.offset);
+            }
+            SyntaxKind::Quote => {
+                self.start();
+                self.bump();
+                while self.peek().is_some_and(SyntaxKind::is_trivia) {
+                    self.bump();
+                }
+                match self.peek() {
+                    None | Some(SyntaxKind::CloseParen) => {
+                        self.error("expected a datum after the quote", start..start + 1)
+                    }
+                    Some(_) => self.element(),
+                }
+                self.finish(SyntaxKind::Quoted);
+            }
+            _ => self.bump(),
+        }
+    }
+}
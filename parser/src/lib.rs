@@ -1,5 +1,9 @@
 #![allow(dead_code)]
 use lexer::{Cursor, Keyword, Span, Token as LexerToken, TokenKind as LexerTokenKind};
+use std::fmt::{self, Display};
+
+pub mod cst;
+pub mod pretty;
 
 pub struct Parser<'a> {
     pub string_reader: StringReader<'a>,
@@ -14,6 +18,8 @@ pub struct StringReader<'a> {
 pub enum TokenKind {
     SExpr(SExpr),
     Atom(AtomKind),
+    /// `'datum`
+    Quote(Box<Token>),
     Nil,
     ListNil,
     EOF,
@@ -21,7 +27,12 @@ pub enum TokenKind {
 
 #[derive(Debug, Clone)]
 pub enum SExpr {
-    Cons { car: Box<Token>, cdr: Vec<Token> },
+    Cons {
+        car: Box<Token>,
+        cdr: Vec<Token>,
+        /// Improper tail following a '.', as in `(a . b)`.
+        tail: Option<Box<Token>>,
+    },
     Nil,
 }
 
@@ -40,17 +51,65 @@ pub struct Token {
 #[derive(Debug)]
 pub enum ParseError {
     UnexpectedToken(Vec<LexerTokenKind>, LexerToken),
+    /// A `)` closing no list.
+    UnmatchedParen(Span),
+    /// A symbol or literal outside any list, which is no form.
+    TopLevelAtom(Span),
+    /// A string missing its closing `"`.
+    UnterminatedString(Span),
+    /// A list still open at the end of the source, by the span of its `(`.
+    UnclosedList(Span),
+    /// A `.` outside any list.
+    StrayDot(Span),
+    /// A `'` or the `.` of a dotted pair with no datum after it.
+    MissingDatum(Span),
 }
 
 impl ParseError {
     fn expected(expect: &[LexerTokenKind], got: LexerToken) -> Self {
         Self::UnexpectedToken(expect.to_vec(), got)
     }
+
+    /// Span of the offending text, or the default span for the end of the
+    /// source.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken(_, token) => token.span,
+            ParseError::UnmatchedParen(span)
+            | ParseError::TopLevelAtom(span)
+            | ParseError::UnterminatedString(span)
+            | ParseError::UnclosedList(span)
+            | ParseError::StrayDot(span)
+            | ParseError::MissingDatum(span) => *span,
+        }
+    }
+
+    /// Whether the source ended where more was expected.
+    fn is_eof(&self) -> bool {
+        matches!(self, ParseError::UnexpectedToken(_, token) if token.kind == LexerTokenKind::EOF)
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::UnexpectedToken(_, token) if token.kind == LexerTokenKind::EOF => {
+                "unexpected end of source"
+            }
+            ParseError::UnexpectedToken(..) => "unexpected token",
+            ParseError::UnmatchedParen(_) => "unmatched `)`",
+            ParseError::TopLevelAtom(_) => "expected a form in parentheses",
+            ParseError::UnterminatedString(_) => "unterminated string",
+            ParseError::UnclosedList(_) => "unclosed `(`",
+            ParseError::StrayDot(_) => "`.` outside a list",
+            ParseError::MissingDatum(_) => "expected a datum after this",
+        })
+    }
 }
 
 macro_rules! token_expect {
     ($self:ident, $pat:pat) => {{
-        let lexer_token = $self.next_lexer();
+        let lexer_token = $self.next_lexer()?;
         if matches!(lexer_token.kind, $pat) {
             std::result::Result::Ok(lexer_token)
         } else {
@@ -68,22 +127,24 @@ impl<'a> StringReader<'a> {
     }
 
     pub fn next_token(&mut self) -> Result<Token, ParseError> {
-        let lexer_token = self.cursor.next_token();
+        let lexer_token = self.next_lexer()?;
         match lexer_token.kind() {
-            LexerTokenKind::Whitespace | LexerTokenKind::LineComment => self.next_token(),
             LexerTokenKind::OpenParen => self.parse_sexpr(lexer_token),
-            LexerTokenKind::CloseParen => todo!(),
-            LexerTokenKind::Keyword(..) => todo!(),
-            LexerTokenKind::Ident => todo!(),
-            LexerTokenKind::OpenAngleBracket => todo!(),
-            LexerTokenKind::CloseAngleBracket => todo!(),
-            LexerTokenKind::Eq => todo!(),
-            LexerTokenKind::Bang => todo!(),
-            LexerTokenKind::And => todo!(),
-            LexerTokenKind::Backquote => todo!(),
-            LexerTokenKind::Literal => todo!(),
-            LexerTokenKind::Dummy => todo!(),
-            LexerTokenKind::Comma => todo!(),
+            LexerTokenKind::Backquote => self.parse_cell(lexer_token),
+            LexerTokenKind::CloseParen => Err(ParseError::UnmatchedParen(lexer_token.span)),
+            LexerTokenKind::Keyword(..)
+            | LexerTokenKind::Ident
+            | LexerTokenKind::Literal
+            | LexerTokenKind::OpenAngleBracket
+            | LexerTokenKind::CloseAngleBracket
+            | LexerTokenKind::Eq
+            | LexerTokenKind::Bang
+            | LexerTokenKind::And => Err(ParseError::TopLevelAtom(lexer_token.span)),
+            LexerTokenKind::Dot => Err(ParseError::StrayDot(lexer_token.span)),
+            LexerTokenKind::Comma
+            | LexerTokenKind::Dummy
+            | LexerTokenKind::Whitespace
+            | LexerTokenKind::LineComment => Err(ParseError::expected(&[], lexer_token)),
             LexerTokenKind::EOF => Ok(Token {
                 kind: TokenKind::EOF,
                 span: Span::default(),
@@ -92,9 +153,20 @@ impl<'a> StringReader<'a> {
     }
 
     fn parse_sexpr(&mut self, lexer_token: LexerToken) -> Result<Token, ParseError> {
+        let open = lexer_token.span;
+        self.parse_list(lexer_token)
+            .map_err(|err| match err.is_eof() {
+                true => ParseError::UnclosedList(open),
+                false => err,
+            })
+    }
+
+    /// Read the rest of the list opened by `lexer_token`.
+    fn parse_list(&mut self, lexer_token: LexerToken) -> Result<Token, ParseError> {
         let start_row = lexer_token.span.start_row;
         let start_col = lexer_token.span.start_col;
 
+        // A leading `.` is no dotted pair but the symbol of `(. object attr)`
         let car_lexer = token_expect!(
             self,
             LexerTokenKind::Keyword(..)
@@ -108,6 +180,7 @@ impl<'a> StringReader<'a> {
                 | LexerTokenKind::Eq
                 | LexerTokenKind::Bang
                 | LexerTokenKind::And
+                | LexerTokenKind::Dot
         )?;
 
         let cell = self.parse_cell(car_lexer)?;
@@ -127,22 +200,32 @@ impl<'a> StringReader<'a> {
         };
 
         let mut cdr_tokens = vec![];
+        let mut tail = None;
         loop {
             let cdr_lexer = token_expect!(
                 self,
                 LexerTokenKind::Keyword(..)
-                | LexerTokenKind::Ident
-                | LexerTokenKind::Backquote
-                | LexerTokenKind::Literal
-                | LexerTokenKind::OpenParen
-                | LexerTokenKind::CloseParen
-                | LexerTokenKind::OpenAngleBracket
-                | LexerTokenKind::CloseAngleBracket
-                | LexerTokenKind::Eq
-                | LexerTokenKind::Bang
-                | LexerTokenKind::And
+                    | LexerTokenKind::Ident
+                    | LexerTokenKind::Backquote
+                    | LexerTokenKind::Literal
+                    | LexerTokenKind::OpenParen
+                    | LexerTokenKind::CloseParen
+                    | LexerTokenKind::OpenAngleBracket
+                    | LexerTokenKind::CloseAngleBracket
+                    | LexerTokenKind::Eq
+                    | LexerTokenKind::Bang
+                    | LexerTokenKind::And
+                    | LexerTokenKind::Dot
             )?;
 
+            if cdr_lexer.kind == LexerTokenKind::Dot {
+                let tail_lexer = self.expect_datum(cdr_lexer.span)?;
+                tail = Some(Box::new(self.parse_cell(tail_lexer)?));
+                let close = token_expect!(self, LexerTokenKind::CloseParen)?;
+                cdr_tokens.push(self.parse_cell(close)?);
+                break;
+            }
+
             let cdr = self.parse_cell(cdr_lexer)?;
             let is_nil = matches!(cdr.kind, TokenKind::Nil);
             cdr_tokens.push(cdr);
@@ -163,11 +246,38 @@ impl<'a> StringReader<'a> {
             kind: TokenKind::SExpr(SExpr::Cons {
                 car,
                 cdr: cdr_tokens,
+                tail,
             }),
             span,
         })
     }
 
+    /// Read the lexer token starting a datum, which cannot be a ')', after
+    /// the `'` or `.` at `after`.
+    fn expect_datum(&mut self, after: Span) -> Result<LexerToken, ParseError> {
+        let datum = token_expect!(
+            self,
+            LexerTokenKind::Keyword(..)
+                | LexerTokenKind::Ident
+                | LexerTokenKind::Backquote
+                | LexerTokenKind::Literal
+                | LexerTokenKind::OpenParen
+                | LexerTokenKind::OpenAngleBracket
+                | LexerTokenKind::CloseAngleBracket
+                | LexerTokenKind::Eq
+                | LexerTokenKind::Bang
+                | LexerTokenKind::And
+        );
+        datum.map_err(|err| match err {
+            ParseError::UnexpectedToken(_, token)
+                if matches!(token.kind, LexerTokenKind::CloseParen | LexerTokenKind::EOF) =>
+            {
+                ParseError::MissingDatum(after)
+            }
+            err => err,
+        })
+    }
+
     fn parse_cell(&mut self, lexer_token: LexerToken) -> Result<Token, ParseError> {
         Ok(match lexer_token.kind {
             // Recurse into S-Expr production
@@ -185,6 +295,19 @@ impl<'a> StringReader<'a> {
                 span: lexer_token.span,
                 kind: TokenKind::Atom(AtomKind::Symbol(lexer_token, Some(kw))),
             },
+            LexerTokenKind::Backquote => {
+                let datum_lexer = self.expect_datum(lexer_token.span)?;
+                let datum = self.parse_cell(datum_lexer)?;
+                Token {
+                    span: Span::new(
+                        lexer_token.span.start_row,
+                        datum.span.end_row,
+                        lexer_token.span.start_col,
+                        datum.span.end_col,
+                    ),
+                    kind: TokenKind::Quote(Box::new(datum)),
+                }
+            }
             _ => Token {
                 span: lexer_token.span,
                 kind: TokenKind::Atom(AtomKind::Literal(lexer_token)),
@@ -192,51 +315,21 @@ impl<'a> StringReader<'a> {
         })
     }
 
-    fn next_lexer(&mut self) -> LexerToken {
+    /// Next lexer token other than whitespace and comments.
+    fn next_lexer(&mut self) -> Result<LexerToken, ParseError> {
         loop {
+            let start = self.cursor.offset();
             let token = self.cursor.next_token();
-            if token.kind != LexerTokenKind::Whitespace {
-                break token;
-            }
-        }
-    }
-
-    // TODO
-    pub fn dbg_token(&self, token: &Token) {
-        // {STRING} {KIND} {SPAN}
-        dbg!(token.span);
-        self.print_token(token);
-    }
-    fn print_token(&self, token: &Token) {
-        match token.kind {
-            TokenKind::SExpr(ref s) => match s {
-                SExpr::Cons { car, cdr } => {
-                    self.print_token(car);
-                    for t in cdr {
-                        self.print_token(t);
+            match token.kind {
+                LexerTokenKind::Whitespace | LexerTokenKind::LineComment => {}
+                LexerTokenKind::Literal => {
+                    let text = &self.src[start..start + token.len];
+                    if text.starts_with('"') && (text.len() == 1 || !text.ends_with('"')) {
+                        return Err(ParseError::UnterminatedString(token.span));
                     }
+                    return Ok(token);
                 }
-                SExpr::Nil => {
-                    dbg!(s);
-                }
-            },
-            TokenKind::Atom(ref a) => match a {
-                AtomKind::Literal(literal) => {
-                    self.cursor.dbg_token(literal);
-                }
-                AtomKind::Symbol(symbol, kw) => {
-                    self.cursor.dbg_token(symbol);
-                    dbg!(kw);
-                }
-            },
-            TokenKind::Nil => {
-                dbg!(token);
-            }
-            TokenKind::ListNil => {
-                dbg!(token);
-            }
-            TokenKind::EOF => {
-                dbg!(token);
+                _ => return Ok(token),
             }
         }
     }
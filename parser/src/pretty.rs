@@ -0,0 +1,322 @@
+//! Canonical layout of Lisp sources, read through the [`cst`](crate::cst).
+//!
+//! A list that fits in the line width is printed on one line. Otherwise the
+//! body of forms such as `defun`, `let` and `loop` is indented by two columns
+//! under the head, with their distinguished arguments (the name and lambda
+//! list of a `defun`, the bindings of a `let`) kept on the first line, the
+//! arguments of other calls are aligned under the first one, and the elements
+//! of data lists under the first element. `defun`s always put their body on
+//! lines of its own.
+//!
+//! Comments are kept, either after the form they followed on the same line
+//! or on a line of their own, as are single blank lines between forms.
+use crate::cst::{self, CstError, SyntaxElement, SyntaxKind, SyntaxNode};
+
+/// Width used unless told otherwise.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Forms whose body is indented by two columns, and how many arguments
+/// precede the body.
+const BODY_FORMS: [(&str, usize); 21] = [
+    ("defun", 2),
+    ("defmacro", 2),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("flet", 1),
+    ("labels", 1),
+    ("loop", 0),
+    ("progn", 0),
+    ("when", 1),
+    ("unless", 1),
+    ("dolist", 1),
+    ("dotimes", 1),
+    ("block", 1),
+    ("case", 1),
+    ("unwind-protect", 1),
+    ("handler-case", 1),
+    ("with-open-file", 1),
+    ("destructuring-bind", 2),
+    ("multiple-value-bind", 2),
+    ("defpackage", 1),
+];
+
+/// Reformat `src` to fit in `width` columns where possible. Sources with
+/// mismatched parentheses are left alone.
+pub fn format(src: &str, width: usize) -> Result<String, Vec<CstError>> {
+    let parse = cst::parse(src);
+    if !parse.errors.is_empty() {
+        return Err(parse.errors);
+    }
+    let mut printer = Printer {
+        out: String::new(),
+        width,
+    };
+    printer.top_level(&parse.syntax());
+    Ok(printer.out)
+}
+
+/// Datum or comment of a sequence of forms.
+enum Item {
+    Datum(SyntaxElement),
+    /// Comment, and whether it follows a datum on the same line.
+    Comment(String, bool),
+    /// One or more blank lines.
+    Blank,
+}
+
+/// How the elements of a list too long for a line are laid out.
+enum Style {
+    /// The first `n` arguments follow the head, the rest are indented by two.
+    Body(usize),
+    /// Arguments are aligned under the first one.
+    Call,
+    /// Elements are aligned under the first one.
+    Data,
+}
+
+struct Printer {
+    out: String,
+    width: usize,
+}
+
+impl Printer {
+    fn top_level(&mut self, root: &SyntaxNode) {
+        for item in items(root.children_with_tokens()) {
+            match item {
+                Item::Blank => self.out.push('\n'),
+                Item::Comment(text, true) => {
+                    self.out.pop();
+                    self.out.push_str(&format!(" {text}\n"));
+                }
+                Item::Comment(text, false) => self.out.push_str(&format!("{text}\n")),
+                Item::Datum(datum) => {
+                    self.datum(&datum, false);
+                    self.out.push('\n');
+                }
+            }
+        }
+    }
+
+    /// Print `element` from the current column. Lists within `data` are not
+    /// calls.
+    fn datum(&mut self, element: &SyntaxElement, data: bool) {
+        let fits = |flat: &String| self.column() + flat.chars().count() <= self.width;
+        match flat(element) {
+            Some(flat) if fits(&flat) && !always_breaks(element) => {
+                self.out.push_str(&flat);
+                return;
+            }
+            _ => {}
+        }
+        match element {
+            SyntaxElement::Node(node) if node.kind() == SyntaxKind::List => self.list(node, data),
+            SyntaxElement::Node(node) if node.kind() == SyntaxKind::Quoted => self.quoted(node),
+            _ => self.out.push_str(&element.text()),
+        }
+    }
+
+    fn list(&mut self, node: &SyntaxNode, data: bool) {
+        let col = self.column();
+        let children = node.children_with_tokens().collect::<Vec<_>>();
+        // Within the parentheses
+        let inner = &children[1..children.len() - 1];
+        let items = items(inner.iter().cloned());
+
+        self.out.push('(');
+        let style = match items.first() {
+            _ if data => Style::Data,
+            Some(Item::Datum(SyntaxElement::Token(head))) if head.kind() == SyntaxKind::Symbol => {
+                match BODY_FORMS.iter().find(|(name, _)| *name == head.text()) {
+                    Some((_, distinguished)) => Style::Body(*distinguished),
+                    None => Style::Call,
+                }
+            }
+            _ => Style::Data,
+        };
+        let mut items = items.into_iter().peekable();
+        let indent = match style {
+            Style::Body(distinguished) => {
+                if let Some(Item::Datum(head)) = items.next_if(is_datum) {
+                    self.datum(&head, data);
+                }
+                for _ in 0..distinguished {
+                    let Some(Item::Datum(arg)) = items.next_if(is_datum) else {
+                        break;
+                    };
+                    self.out.push(' ');
+                    self.datum(&arg, data);
+                }
+                col + 2
+            }
+            Style::Call => {
+                if let Some(Item::Datum(head)) = items.next_if(is_datum) {
+                    self.datum(&head, data);
+                }
+                match items.next_if(is_datum) {
+                    Some(Item::Datum(arg)) => {
+                        self.out.push(' ');
+                        let align = self.column();
+                        self.datum(&arg, data);
+                        align
+                    }
+                    _ => col + 2,
+                }
+            }
+            Style::Data => {
+                if let Some(Item::Datum(first)) = items.next_if(is_datum) {
+                    self.datum(&first, data);
+                }
+                col + 1
+            }
+        };
+
+        let mut blank = false;
+        let mut after_comment = false;
+        let mut prev: Option<SyntaxKind> = None;
+        for item in items {
+            match item {
+                Item::Blank => blank = true,
+                Item::Comment(text, true) => {
+                    self.out.push_str(&format!(" {text}"));
+                    after_comment = true;
+                }
+                Item::Comment(text, false) => {
+                    self.newline(indent, blank);
+                    self.out.push_str(&text);
+                    after_comment = true;
+                    blank = false;
+                }
+                Item::Datum(datum) => {
+                    match prev {
+                        // Kept next to the datum they introduce
+                        Some(SyntaxKind::Dot) if !after_comment => self.out.push(' '),
+                        Some(SyntaxKind::Comma) if !after_comment => {}
+                        _ => self.newline(indent, blank),
+                    }
+                    self.datum(&datum, data);
+                    prev = Some(datum.kind());
+                    after_comment = false;
+                    blank = false;
+                }
+            }
+        }
+        if after_comment {
+            self.newline(indent, false);
+        }
+        self.out.push(')');
+    }
+
+    fn quoted(&mut self, node: &SyntaxNode) {
+        let col = self.column();
+        for child in node.children_with_tokens() {
+            match child.kind() {
+                SyntaxKind::Whitespace => {}
+                SyntaxKind::Comment => {
+                    self.out.push_str(&child.text());
+                    self.newline(col + 1, false);
+                }
+                SyntaxKind::Quote => self.out.push_str(&child.text()),
+                _ => self.datum(&child, true),
+            }
+        }
+    }
+
+    /// Start a line indented by `indent`, after a blank line if `blank`.
+    fn newline(&mut self, indent: usize, blank: bool) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        if blank {
+            self.out.push('\n');
+        }
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    fn column(&self) -> usize {
+        let line = match self.out.rfind('\n') {
+            Some(newline) => &self.out[newline + 1..],
+            None => &self.out,
+        };
+        line.chars().count()
+    }
+}
+
+fn is_datum(item: &Item) -> bool {
+    matches!(item, Item::Datum(_))
+}
+
+/// Data and comments of a sequence of elements, with the whitespace between
+/// them dropped but for blank lines.
+fn items(elements: impl Iterator<Item = SyntaxElement>) -> Vec<Item> {
+    let mut items = vec![];
+    let mut newlines = 0;
+    for element in elements {
+        match element.kind() {
+            SyntaxKind::Whitespace => newlines += element.text().matches('\n').count(),
+            kind => {
+                let trailing = kind == SyntaxKind::Comment && newlines == 0 && !items.is_empty();
+                if newlines >= 2 && !items.is_empty() {
+                    items.push(Item::Blank);
+                }
+                items.push(match kind {
+                    SyntaxKind::Comment => {
+                        Item::Comment(element.text().trim_end().to_owned(), trailing)
+                    }
+                    _ => Item::Datum(element),
+                });
+                newlines = 0;
+            }
+        }
+    }
+    items
+}
+
+/// `element` on one line, unless it holds a comment or a line break.
+fn flat(element: &SyntaxElement) -> Option<String> {
+    let node = match element {
+        SyntaxElement::Token(token) if token.text().contains('\n') => return None,
+        SyntaxElement::Token(token) => return Some(token.text().to_owned()),
+        SyntaxElement::Node(node) => node,
+    };
+    let mut out = String::new();
+    let mut prev = None;
+    for child in node.children_with_tokens() {
+        let kind = child.kind();
+        match kind {
+            SyntaxKind::Whitespace => continue,
+            SyntaxKind::Comment | SyntaxKind::Error => return None,
+            _ => {}
+        }
+        let separated = !matches!(
+            (prev, kind),
+            (None, _)
+                | (
+                    Some(SyntaxKind::OpenParen | SyntaxKind::Quote | SyntaxKind::Comma),
+                    _
+                )
+                | (_, SyntaxKind::CloseParen)
+        );
+        if separated {
+            out.push(' ');
+        }
+        out.push_str(&flat(&child)?);
+        prev = Some(kind);
+    }
+    Some(out)
+}
+
+/// Whether `element` is a `defun` with a body, which is never put on one
+/// line.
+fn always_breaks(element: &SyntaxElement) -> bool {
+    let SyntaxElement::Node(node) = element else {
+        return false;
+    };
+    let data = node
+        .significant_children()
+        .filter(|child| !matches!(child.kind(), SyntaxKind::OpenParen | SyntaxKind::CloseParen))
+        .collect::<Vec<_>>();
+    node.kind() == SyntaxKind::List
+        && data.len() > 3
+        && matches!(data[0].text().as_str(), "defun" | "defmacro")
+}
@@ -0,0 +1,286 @@
+//! Tests of the lossless concrete syntax tree.
+use parser::cst::{parse, Edit, GreenElement, SyntaxElement, SyntaxKind};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+fn assert_round_trip(src: &str) {
+    let parsed = parse(src);
+    assert_eq!(parsed.syntax().text(), src);
+    let tokens = parsed.syntax().tokens();
+    let mut offset = 0;
+    for token in &tokens {
+        assert_eq!(
+            token.text_range().start,
+            offset,
+            "tokens of {src:?} are contiguous"
+        );
+        assert_eq!(&src[token.text_range()], token.text());
+        offset = token.text_range().end;
+    }
+    assert_eq!(offset, src.len());
+}
+
+#[test]
+fn round_trips_examples() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("../lisp-desu/examples");
+    for entry in fs::read_dir(examples).expect("reads examples") {
+        let path = entry.expect("reads entry").path();
+        if path.extension().is_some_and(|ext| ext == "lisu") {
+            assert_round_trip(&fs::read_to_string(&path).expect("reads example"));
+        }
+    }
+}
+
+#[test]
+fn round_trips_trivia_and_malformed_sources() {
+    for src in [
+        "",
+        "   \n\t",
+        "; only a comment",
+        ";\n(a)\n",
+        "(defun f (x) ; why\n  ;; body\n  (+ x 1))\n",
+        "'(a . b) `c ' d",
+        "(a (b",
+        "a) b))",
+        "(f \"unterminated",
+        "'",
+        "(')",
+        "(<= 1 2) (& rest) (= a b)",
+    ] {
+        assert_round_trip(src);
+    }
+}
+
+/// Sources drawn from a fixed pseudo-random sequence of Lisp fragments.
+#[test]
+fn round_trips_generated_sources() {
+    const FRAGMENTS: [&str; 14] = [
+        "(", ")", " ", "\n", "'", "`", ". ", "foo", "12", "1.5", "\"s\"", "; c\n", "&key", "<",
+    ];
+    let mut state = 0x2545_f491_u64;
+    for _ in 0..500 {
+        let mut src = String::new();
+        for _ in 0..(state % 40) {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            src.push_str(FRAGMENTS[(state >> 33) as usize % FRAGMENTS.len()]);
+        }
+        assert_round_trip(&src);
+    }
+}
+
+#[test]
+fn builds_lists_and_quotes() {
+    let parsed = parse("(a 'b) ; done\n");
+    assert!(parsed.errors.is_empty());
+    let root = parsed.syntax();
+    let kinds = root
+        .children_with_tokens()
+        .map(|child| child.kind())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            SyntaxKind::List,
+            SyntaxKind::Whitespace,
+            SyntaxKind::Comment,
+            SyntaxKind::Whitespace
+        ]
+    );
+
+    let list = root.children().next().expect("list");
+    let items = list
+        .significant_children()
+        .map(|child| (child.kind(), child.text()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        items,
+        [
+            (SyntaxKind::OpenParen, "(".to_owned()),
+            (SyntaxKind::Symbol, "a".to_owned()),
+            (SyntaxKind::Quoted, "'b".to_owned()),
+            (SyntaxKind::CloseParen, ")".to_owned()),
+        ]
+    );
+
+    let token = root.token_at_offset(4).expect("token at `b`");
+    assert_eq!(token.text(), "b");
+    assert_eq!(token.parent().kind(), SyntaxKind::Quoted);
+    let ancestors = token
+        .parent()
+        .ancestors()
+        .map(|node| node.kind())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ancestors,
+        [SyntaxKind::Quoted, SyntaxKind::List, SyntaxKind::Root]
+    );
+}
+
+#[test]
+fn reports_mismatched_parens() {
+    let parsed = parse("(a (b)\n)) c");
+    let messages = parsed
+        .errors
+        .iter()
+        .map(|error| (error.message, error.range.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(messages, [("unmatched `)`", 8..9)]);
+    let error = parsed
+        .syntax()
+        .children_with_tokens()
+        .find(|child| child.kind() == SyntaxKind::Error);
+    assert!(matches!(error, Some(SyntaxElement::Node(_))));
+
+    let parsed = parse("(a (b");
+    assert_eq!(parsed.errors.len(), 2);
+    assert!(parsed
+        .errors
+        .iter()
+        .all(|error| error.message == "unclosed `(`"));
+}
+
+/// Apply `edit` to `src` and check that reparsing gives the tree of a full
+/// parse, returning the source after the edit and its parse.
+fn assert_reparses(
+    src: &str,
+    parsed: &parser::cst::Parse,
+    edit: &Edit,
+) -> (String, parser::cst::Parse) {
+    let edited = edit.apply(src);
+    let reparsed = parsed.reparse(&edited, edit);
+    let expected = parse(&edited);
+    assert_eq!(
+        reparsed.green, expected.green,
+        "tree of {src:?} after {edit:?}"
+    );
+    assert_eq!(
+        reparsed.errors, expected.errors,
+        "errors of {src:?} after {edit:?}"
+    );
+    (edited, reparsed)
+}
+
+/// Edits drawn from a fixed pseudo-random sequence, each applied to the
+/// tree reparsed after the one before.
+#[test]
+fn reparses_generated_edits_like_full_parses() {
+    const FRAGMENTS: [&str; 16] = [
+        "(",
+        ")",
+        " ",
+        "\n",
+        "'",
+        "`",
+        ". ",
+        "foo",
+        "12",
+        "1.5",
+        "\"s\"",
+        "; c\n",
+        "&key",
+        "<",
+        "é",
+        "(a (b) c)",
+    ];
+    let mut state = 0x9e37_79b9_u64;
+    let mut next = |bound: usize| {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) as usize % bound.max(1)
+    };
+    for _ in 0..300 {
+        let mut src = String::new();
+        for _ in 0..next(40) {
+            src.push_str(FRAGMENTS[next(FRAGMENTS.len())]);
+        }
+        let mut parsed = parse(&src);
+        for _ in 0..10 {
+            let boundaries = src
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([src.len()])
+                .collect::<Vec<_>>();
+            let start = boundaries[next(boundaries.len())];
+            let end = boundaries
+                .iter()
+                .copied()
+                .find(|&i| i >= start + next(8))
+                .unwrap_or(src.len());
+            let mut replacement = String::new();
+            for _ in 0..next(3) {
+                replacement.push_str(FRAGMENTS[next(FRAGMENTS.len())]);
+            }
+            let edit = Edit {
+                range: start..end,
+                replacement,
+            };
+            (src, parsed) = assert_reparses(&src, &parsed, &edit);
+        }
+    }
+}
+
+fn top_level(parsed: &parser::cst::Parse) -> Vec<GreenElement> {
+    parsed.green.children().to_vec()
+}
+
+fn same(a: &GreenElement, b: &GreenElement) -> bool {
+    match (a, b) {
+        (GreenElement::Node(a), GreenElement::Node(b)) => Rc::ptr_eq(a, b),
+        (GreenElement::Token(a), GreenElement::Token(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+#[test]
+fn reparse_shares_unchanged_forms() {
+    let src = "(defun f (x) x)\n(defun g (y) (list (h y) (+ y 1)))\n(print (g 2))\n";
+    let parsed = parse(src);
+    // `(+ y 1)` becomes `(+ y 10)`
+    let at = src.find("1)))").expect("finds the 1") + 1;
+    let edit = Edit {
+        range: at..at,
+        replacement: "0".to_owned(),
+    };
+    let (_, reparsed) = assert_reparses(src, &parsed, &edit);
+    let (old, new) = (top_level(&parsed), top_level(&reparsed));
+    assert!(same(&old[0], &new[0]));
+    assert!(!same(&old[2], &new[2]));
+    assert!(same(&old[4], &new[4]));
+
+    // `(h y)` and the lambda list are shared within the reparsed `defun`
+    let lists = |element: &GreenElement| match element {
+        GreenElement::Node(node) => node.children().to_vec(),
+        GreenElement::Token(_) => vec![],
+    };
+    let (old_defun, new_defun) = (lists(&old[2]), lists(&new[2]));
+    assert!(same(&old_defun[5], &new_defun[5]));
+    let (old_body, new_body) = (lists(&old_defun[7]), lists(&new_defun[7]));
+    assert!(same(&old_body[3], &new_body[3]));
+    assert!(!same(&old_body[5], &new_body[5]));
+}
+
+#[test]
+fn reparse_nests_forms_in_a_list_left_unclosed() {
+    let src = "(a)\n(b (c))\n(d)\n";
+    let parsed = parse(src);
+    let edit = Edit {
+        range: 0..0,
+        replacement: "(".to_owned(),
+    };
+    let (_, reparsed) = assert_reparses(src, &parsed, &edit);
+    assert_eq!(reparsed.errors.len(), 1);
+    let GreenElement::Node(outer) = &reparsed.green.children()[0] else {
+        panic!("expected a list");
+    };
+    let old = top_level(&parsed);
+    let kept = outer
+        .children()
+        .iter()
+        .filter(|child| old.iter().any(|old| same(old, child)))
+        .count();
+    assert_eq!(kept, 3, "`(b (c))` and `(d)` are shared, and `(a)` too");
+}
@@ -0,0 +1,67 @@
+//! Tests of the canonical layout of sources.
+use parser::pretty::{format, DEFAULT_WIDTH};
+
+fn assert_formats(src: &str, width: usize, expected: &str) {
+    let formatted = format(src, width).expect("formats");
+    assert_eq!(formatted, expected);
+    assert_eq!(format(&formatted, width).expect("formats"), formatted);
+}
+
+#[test]
+fn joins_forms_that_fit() {
+    assert_formats(
+        "(print   (+ 1\n  2))\n\n\n(print '( a . b ))",
+        DEFAULT_WIDTH,
+        "(print (+ 1 2))\n\n(print '(a . b))\n",
+    );
+}
+
+#[test]
+fn indents_bodies_and_aligns_arguments() {
+    let src = "(defun classify (n) (cond ((< n 0) \"negative\") ((= n 0) \"zero\") (t (let ((m (* n 2))) (format t \"~a~%\" m) m))))";
+    let expected = "\
+(defun classify (n)
+  (cond ((< n 0) \"negative\")
+        ((= n 0) \"zero\")
+        (t (let ((m (* n 2)))
+             (format t \"~a~%\" m)
+             m))))
+";
+    assert_formats(src, 40, expected);
+}
+
+#[test]
+fn breaks_defun_bodies() {
+    assert_formats("(defun f (x) x)", DEFAULT_WIDTH, "(defun f (x)\n  x)\n");
+}
+
+#[test]
+fn keeps_comments() {
+    let src = "\
+;;; Header
+
+(defun f (x) ; trailing
+  ;; own line
+  (g x
+  ;; last
+  ))
+(f 1) ; after
+";
+    let expected = "\
+;;; Header
+
+(defun f (x) ; trailing
+  ;; own line
+  (g x
+     ;; last
+     ))
+(f 1) ; after
+";
+    assert_formats(src, DEFAULT_WIDTH, expected);
+}
+
+#[test]
+fn rejects_mismatched_parens() {
+    let errors = format("(a (b", DEFAULT_WIDTH).expect_err("unclosed");
+    assert_eq!(errors.len(), 2);
+}
@@ -0,0 +1,138 @@
+//! Tests of the errors of the reader.
+use lexer::Span;
+use parser::{ParseError, StringReader, TokenKind};
+
+/// The first error reading `src`, form by form.
+fn read_error(src: &str) -> ParseError {
+    let mut reader = StringReader::new(src);
+    loop {
+        match reader.next_token() {
+            Ok(token) if matches!(token.kind, TokenKind::EOF) => {
+                panic!("{src:?} reads without error")
+            }
+            Ok(_) => {}
+            Err(err) => return err,
+        }
+    }
+}
+
+#[test]
+fn reads_forms_to_the_end() {
+    let mut reader = StringReader::new("(print 1) ; done\n'(a . b)\n");
+    assert!(matches!(
+        reader.next_token().unwrap().kind,
+        TokenKind::SExpr(_)
+    ));
+    assert!(matches!(
+        reader.next_token().unwrap().kind,
+        TokenKind::Quote(_)
+    ));
+    assert!(matches!(reader.next_token().unwrap().kind, TokenKind::EOF));
+}
+
+#[test]
+fn rejects_top_level_atoms() {
+    for (src, span) in [
+        ("42", Span::new(1, 1, 1, 2)),
+        ("foo", Span::new(1, 1, 1, 3)),
+        ("(print 1)\n  x", Span::new(2, 2, 3, 3)),
+        // Columns count characters rather than bytes
+        ("(a \"\u{e9}\") \u{fc}n\u{ef}", Span::new(1, 1, 9, 11)),
+        (
+            "(print \"\u{1f600}\")\n  \u{e9}t\u{e9}",
+            Span::new(2, 2, 3, 5),
+        ),
+    ] {
+        let err = read_error(src);
+        assert!(
+            matches!(err, ParseError::TopLevelAtom(_)),
+            "{src:?}: {err:?}"
+        );
+        assert_eq!(err.span(), span, "{src:?}");
+        assert_eq!(err.to_string(), "expected a form in parentheses");
+    }
+}
+
+#[test]
+fn rejects_unmatched_parens() {
+    for (src, span) in [
+        (")", Span::new(1, 1, 1, 1)),
+        ("(a))", Span::new(1, 1, 4, 4)),
+    ] {
+        let err = read_error(src);
+        assert!(
+            matches!(err, ParseError::UnmatchedParen(_)),
+            "{src:?}: {err:?}"
+        );
+        assert_eq!(err.span(), span, "{src:?}");
+    }
+}
+
+#[test]
+fn rejects_unterminated_strings() {
+    for (src, span) in [
+        ("\"abc", Span::new(1, 1, 1, 4)),
+        ("(print \"abc", Span::new(1, 1, 8, 11)),
+        ("(print \"", Span::new(1, 1, 8, 8)),
+    ] {
+        let err = read_error(src);
+        assert!(
+            matches!(err, ParseError::UnterminatedString(_)),
+            "{src:?}: {err:?}"
+        );
+        assert_eq!(err.span(), span, "{src:?}");
+    }
+}
+
+#[test]
+fn rejects_unclosed_lists_at_the_innermost_paren() {
+    for (src, span) in [
+        ("(a (b)", Span::new(1, 1, 1, 1)),
+        ("(print 1)\n(defun f ()\n  (g", Span::new(3, 3, 3, 3)),
+        ("(", Span::new(1, 1, 1, 1)),
+    ] {
+        let err = read_error(src);
+        assert!(
+            matches!(err, ParseError::UnclosedList(_)),
+            "{src:?}: {err:?}"
+        );
+        assert_eq!(err.span(), span, "{src:?}");
+        assert_eq!(err.to_string(), "unclosed `(`");
+    }
+}
+
+#[test]
+fn rejects_stray_commas() {
+    let err = read_error(",");
+    assert!(matches!(err, ParseError::UnexpectedToken(..)), "{err:?}");
+    assert_eq!(err.span(), Span::new(1, 1, 1, 1));
+}
+
+#[test]
+fn rejects_stray_dots() {
+    for (src, span) in [
+        (".", Span::new(1, 1, 1, 1)),
+        ("(a)\n .", Span::new(2, 2, 2, 2)),
+    ] {
+        let err = read_error(src);
+        assert!(matches!(err, ParseError::StrayDot(_)), "{src:?}: {err:?}");
+        assert_eq!(err.span(), span, "{src:?}");
+    }
+}
+
+#[test]
+fn rejects_missing_data_after_dots_and_quotes() {
+    for (src, span) in [
+        ("(a . )", Span::new(1, 1, 4, 4)),
+        ("(a .", Span::new(1, 1, 4, 4)),
+        ("'", Span::new(1, 1, 1, 1)),
+        ("(print ')", Span::new(1, 1, 8, 8)),
+    ] {
+        let err = read_error(src);
+        assert!(
+            matches!(err, ParseError::MissingDatum(_)),
+            "{src:?}: {err:?}"
+        );
+        assert_eq!(err.span(), span, "{src:?}");
+    }
+}